@@ -0,0 +1,42 @@
+#![no_main]
+// Разбор одной страницы: любые байты должны давать либо значения, либо ошибку
+
+use libfuzzer_sys::fuzz_target;
+use rust_apps::types::PageRef;
+
+fuzz_target!(|data: &[u8]| {
+    let page = match PageRef::new(data) {
+        Ok(page) => page,
+        Err(_) => return,
+    };
+
+    let _ = page.meta();
+    let _ = page.meta_checksum();
+    let _ = page.type_name();
+
+    if let Ok(ids) = page.freelist() {
+        assert!(ids.len() * 4 <= data.len());
+    }
+
+    // Ключ собирается из общего префикса и суффикса, и то и другое лежит внутри страницы
+    let prefix_len = page.prefix().map_or(0, |x| x.len());
+    assert!(prefix_len <= data.len());
+
+    if let Ok(inodes) = page.leaf_inodes() {
+        for inode in inodes {
+            assert!(inode.key.len() - prefix_len + inode.value.len() <= data.len());
+        }
+    }
+
+    if let Ok(inodes) = page.branch_inodes() {
+        for inode in inodes {
+            assert!(inode.key.len() - prefix_len <= data.len());
+        }
+    }
+
+    // Поштучный доступ, в том числе за пределами inode_count
+    for idx in 0..std::cmp::min(page.count() + 1, 64) {
+        let _ = page.leaf_inode(idx);
+        let _ = page.branch_inode(idx);
+    }
+});
@@ -0,0 +1,33 @@
+#!/bin/sh
+# Seed corpus для fuzz-целей из файлов, которые пишет writer:
+#   corpus/open - файлы базы целиком (после writer, после коммита, после compact)
+#   corpus/page - отдельные страницы этих файлов
+set -e
+
+root=$(cd "$(dirname "$0")/.." && pwd)
+corpus="$root/fuzz/corpus"
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+cd "$root"
+cargo build --bin writer --bin reader --bin dbtool
+bin="${CARGO_TARGET_DIR:-$root/target}/debug"
+
+rm -rf "$corpus"
+mkdir -p "$corpus/open" "$corpus/page"
+
+cd "$work"
+# Маленькие страницы, чтобы у дерева writer были ветки, а не один лист
+page_size=512
+"$bin/writer" $page_size > /dev/null
+cp db.rust "$corpus/open/writer"
+
+"$bin/reader" > /dev/null
+cp db.rust "$corpus/open/commit"
+
+"$bin/dbtool" compact db.rust > /dev/null
+cp db.rust "$corpus/open/compact"
+
+for name in writer compact; do
+    split -b "$page_size" -d -a 3 "$corpus/open/$name" "$corpus/page/$name-"
+done
@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::process;
+
+use rust_apps::check::Visited;
+use rust_apps::db::{DB, Options};
+use rust_apps::types::{key_to_str, PageHeader, PageId, PageRef};
+
+fn usage() -> ! {
+    eprintln!("usage: dbinspect <command> <path> [args]");
+    eprintln!();
+    eprintln!("commands:");
+    eprintln!("    meta <path>          print the current meta page");
+    eprintln!("    page <path> <id>     print page header and inode headers");
+    eprintln!("    dump <path> <id>     hex dump of the page as it is on disk");
+    eprintln!("    tree <path>          print the tree from the root page");
+    process::exit(2);
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1);
+}
+
+fn checked_page(db: &DB, id: PageId) -> PageRef<'_> {
+    db.page(id).unwrap_or_else(|e| fail(e.to_string()))
+}
+
+fn print_meta(db: &DB) {
+    let meta = db.meta();
+    let (magic, version, page_size, root_page, page_count) = (meta.magic.get(), meta.version.get(), meta.page_size.get(), meta.root_page.get(), meta.page_count.get());
+
+    println!("{}", checked_page(db, meta.slot()));
+    println!("magic:      {:#x}", magic);
+    println!("version:    {}", version);
+    println!("page_size:  {}", page_size);
+    println!("root_page:  {}", root_page);
+    println!("page_count: {}", page_count);
+    println!("freelist:   {}", meta.freelist_page.get());
+    println!("txid:       {}", meta.txid.get());
+    println!("comparator: {}", meta.comparator.get());
+    println!("flags:      {:#x}", meta.flags.get());
+
+    let checksum = checked_page(db, meta.slot()).meta_checksum().unwrap_or_else(|e| fail(format!("meta: {}", e)));
+    if checksum == meta.checksum() {
+        println!("checksum:   {:#x}", checksum);
+    } else {
+        println!("checksum:   {:#x} (expected {:#x})", checksum, meta.checksum());
+    }
+}
+
+fn print_page(page: PageRef) {
+    println!("{}", page);
+    if page.has_prefix() {
+        match page.prefix() {
+            Ok(prefix) => println!("  prefix: {} bytes {:02x?}", prefix.len(), prefix),
+            Err(e) => println!("  prefix: {}", e),
+        }
+    }
+
+    // Битые inode печатаются как ошибка, остальные все равно показываем
+    if page.is_leaf() {
+        for idx in 0..page.count() {
+            match page.leaf_inode(idx) {
+                Ok(inode) => {
+                    let h = inode.header;
+                    let (pos, ksize, vsize, page_id) = (h.pos.get(), h.ksize.get(), h.vsize.get(), h.page_id.get());
+                    print!("  #{:<4} pos={:<6} ksize={:<4} vsize={:<6} page_id={:<6} ", idx, pos, ksize, vsize, page_id);
+                    if page.has_codec() {
+                        print!("codec={:<3} ", inode.codec);
+                    }
+                    println!("key=\"{}\"", key_to_str(&inode.key));
+                }
+                Err(e) => println!("  #{:<4} {}", idx, e),
+            }
+        }
+    } else if page.is_branch() {
+        for idx in 0..page.count() {
+            match page.branch_inode(idx) {
+                Ok(inode) => {
+                    let h = inode.header;
+                    let (pos, ksize, page_id) = (h.pos.get(), h.ksize.get(), h.page_id.get());
+                    println!("  #{:<4} pos={:<6} ksize={:<4} page_id={:<6} key=\"{}\"", idx, pos, ksize, page_id, key_to_str(&inode.key));
+                }
+                Err(e) => println!("  #{:<4} {}", idx, e),
+            }
+        }
+    } else if page.is_freelist() {
+        match page.freelist() {
+            Ok(ids) => println!("  free pages: {:?}", ids),
+            Err(e) => println!("  {}", e),
+        }
+    } else if page.is_meta() {
+        match page.meta() {
+            Ok(meta) => println!("  {:?}", meta),
+            Err(e) => println!("  {}", e),
+        }
+    }
+}
+
+// Вывод в стиле hexdump -C, повторяющиеся строки заменяются на "*"
+fn hex_dump(data: &[u8]) {
+    let mut prev: Option<&[u8]> = None;
+    let mut skipping = false;
+
+    for (idx, line) in data.chunks(16).enumerate() {
+        if prev == Some(line) {
+            if !skipping {
+                println!("*");
+                skipping = true;
+            }
+            continue;
+        }
+        prev = Some(line);
+        skipping = false;
+
+        let hex: Vec<String> = line.iter().map(|x| format!("{:02x}", x)).collect();
+        let ascii: String = line.iter()
+            .map(|&x| if x.is_ascii_graphic() || x == b' ' { x as char } else { '.' })
+            .collect();
+
+        println!("{:08x}  {:<48} |{}|", idx * 16, hex.join(" "), ascii);
+    }
+    println!("{:08x}", data.len());
+}
+
+// Страница в файле как есть, вместе с overflow-страницами: зашифрованная остается
+// зашифрованной, в отличие от DB::page
+fn raw_page(path: &str, db: &DB, id: PageId) -> Vec<u8> {
+    let f = File::open(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+    let read = |offset: u64, len: usize| {
+        let mut buf = vec![0u8; len];
+        f.read_exact_at(&mut buf, offset).unwrap_or_else(|e| fail(format!("page {}: {}", id, e)));
+        buf
+    };
+
+    let offset = id.checked_mul(db.page_size() as u64).unwrap_or_else(|| fail(format!("page {} is out of range", id)));
+    let header = read(offset, std::mem::size_of::<PageHeader>());
+    let overflow = PageRef::new(&header).unwrap_or_else(|e| fail(e.to_string())).page_overflow_count.get();
+    read(offset, (overflow as usize + 1) * db.page_size())
+}
+
+fn key_range(page: &PageRef) -> String {
+    let keys: Vec<String> = if page.is_leaf() {
+        page.leaf_inodes().unwrap_or_else(|e| fail(e.to_string())).iter().map(|x| key_to_str(&x.key)).collect()
+    } else if page.is_branch() {
+        page.branch_inodes().unwrap_or_else(|e| fail(e.to_string())).iter().map(|x| key_to_str(&x.key)).collect()
+    } else {
+        vec![]
+    };
+
+    match (keys.first(), keys.last()) {
+        (Some(first), Some(last)) => format!("[\"{}\" .. \"{}\"]", first, last),
+        _ => "[]".to_string(),
+    }
+}
+
+// Страница, которая уже напечатана, - цикл или общее поддерево: она отмечается, но
+// второй раз не обходится
+fn print_tree(db: &DB, page_id: PageId, level: usize, visited: &mut Visited) {
+    let mut name = String::new();
+    for _ in 0..level {
+        name.push_str("  |");
+    }
+    name.push_str("--");
+
+    if !visited.insert(page_id) {
+        println!("{} page {} (already visited: cycle in tree)", name, page_id);
+        return;
+    }
+
+    let page = checked_page(db, page_id);
+
+    println!("{} page {} ({}, {} inodes, {} overflow) {}", name, page_id, page.type_name(),
+             page.inode_count.get(), page.page_overflow_count.get(), key_range(&page));
+
+    if page.is_branch() {
+        for inode in page.branch_inodes().unwrap_or_else(|e| fail(e.to_string())) {
+            print_tree(db, inode.page_id, level + 1, visited);
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        usage();
+    }
+
+    let path = args[2].as_str();
+    let page_id = || -> PageId {
+        args.get(3)
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_else(|| usage())
+    };
+
+    // Контрольная сумма meta не проверяется, а только печатается: так открывается и файл, у
+    // которого испорчена только она. Остальная meta должна быть целой - ее проверяет open.
+    // Компаратор базы берется из ее meta.
+    let options = Options { read_only: true, verify_checksums: false, ..Options::default() };
+    let db = Options::from_meta(path, options)
+        .and_then(|options| DB::open_with(path, options))
+        .unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+
+    match args[1].as_str() {
+        "meta" => print_meta(&db),
+        "page" => print_page(checked_page(&db, page_id())),
+        "dump" => hex_dump(&raw_page(path, &db, page_id())),
+        "tree" => print_tree(&db, db.meta().root_page.get() as PageId, 0, &mut Visited::new()),
+        _ => usage(),
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::process;
+
+use rust_apps::check::check;
+use rust_apps::compact::compact_with;
+use rust_apps::db::{DB, Options};
+use rust_apps::export::{Encoding, export, Format, import};
+
+fn usage() -> ! {
+    eprintln!("usage: dbtool <command> [args]");
+    eprintln!();
+    eprintln!("commands:");
+    eprintln!("    compact <path>    rewrite the database densely into a fresh file");
+    eprintln!("    check <path>      verify consistency of the database file");
+    eprintln!("    stats <path>      print page and key/value statistics");
+    eprintln!("    export <path> [--format jsonl|csv] [--encoding hex|base64] [--from KEY] [--to KEY]");
+    eprintln!("                      write key/values in [from, to) to stdout; KEY is a stored key (as export prints it)");
+    eprintln!("                      or its prefix, in the chosen encoding");
+    eprintln!("    import <path> <file|-> [--format jsonl|csv] [--encoding hex|base64]");
+    eprintln!("                      load key/values in a single write transaction");
+    process::exit(2);
+}
+
+// Разбирает "--name value" после позиционных аргументов
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|x| x == name)
+        .map(|idx| args.get(idx + 1).unwrap_or_else(|| usage()).as_str())
+}
+
+fn format_option(args: &[String]) -> Format {
+    option(args, "--format").map_or(Format::JsonLines, |x| Format::parse(x).unwrap_or_else(|| usage()))
+}
+
+fn encoding_option(args: &[String]) -> Encoding {
+    option(args, "--encoding").map_or(Encoding::Hex, |x| Encoding::parse(x).unwrap_or_else(|| usage()))
+}
+
+// Граница диапазона - байты ключа так, как он хранится в базе, без выравнивания
+fn key_option(args: &[String], name: &str, encoding: Encoding) -> Option<Vec<u8>> {
+    option(args, name).map(|x| {
+        encoding.decode(x).unwrap_or_else(|e| {
+            eprintln!("{}: {}", name, e);
+            process::exit(2);
+        })
+    })
+}
+
+// Компаратор базы берется из ее meta
+fn options(path: &str, read_only: bool) -> Options {
+    Options::from_meta(path, Options { read_only, ..Options::default() }).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    })
+}
+
+fn open(path: &str, read_only: bool) -> DB {
+    DB::open_with(path, options(path, read_only)).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    })
+}
+
+fn file_size(path: &str) -> u64 {
+    fs::metadata(path).map(|x| x.len()).unwrap_or(0)
+}
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(|x| x.as_str()) {
+        Some("compact") => {
+            let path = args.get(2).unwrap_or_else(|| usage());
+            let before = file_size(path);
+
+            if let Err(e) = compact_with(path, &options(path, true)) {
+                eprintln!("compact failed: {}", e);
+                process::exit(1);
+            }
+
+            println!("{}: {} -> {} bytes", path, before, file_size(path));
+        }
+        Some("check") => {
+            let path = args.get(2).unwrap_or_else(|| usage());
+            let db = open(path, true);
+
+            match check(&db) {
+                Ok(violations) if violations.is_empty() => println!("{}: ok", path),
+                Ok(violations) => {
+                    for v in violations.iter() {
+                        println!("{}", v);
+                    }
+                    println!("{}: {} violations found", path, violations.len());
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("check failed: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Some("stats") => {
+            let path = args.get(2).unwrap_or_else(|| usage());
+
+            match open(path, true).stats() {
+                Ok(stats) => print!("{}", stats),
+                Err(e) => {
+                    eprintln!("stats failed: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Some("export") => {
+            let path = args.get(2).unwrap_or_else(|| usage());
+            let encoding = encoding_option(&args);
+            let from = key_option(&args, "--from", encoding);
+            let to = key_option(&args, "--to", encoding);
+
+            let db = open(path, true);
+            let ret = export(&db, io::stdout().lock(), format_option(&args), encoding,
+                             from.as_deref(), to.as_deref());
+
+            if let Err(e) = ret {
+                eprintln!("export failed: {}", e);
+                process::exit(1);
+            }
+        }
+        Some("import") => {
+            let path = args.get(2).unwrap_or_else(|| usage());
+            let input = args.get(3).unwrap_or_else(|| usage());
+            let db = open(path, false);
+
+            let ret = if input == "-" {
+                import(&db, io::stdin().lock(), format_option(&args), encoding_option(&args))
+            } else {
+                File::open(input)
+                    .and_then(|f| import(&db, BufReader::new(f), format_option(&args), encoding_option(&args)))
+            };
+
+            match ret {
+                Ok(count) => println!("{}: {} key/values imported", path, count),
+                Err(e) => {
+                    eprintln!("import failed: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        _ => usage(),
+    }
+}
@@ -1,7 +0,0 @@
-
-// Управление страницами внутри файла. Задачи:
-// 1. Находить в файле свободные участки требуемого размера и отдавать их программе
-// 2. Освобождать неиспользуемые страницы
-//
-pub struct FreePages {
-}
@@ -0,0 +1,330 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::mem::size_of;
+
+use crate::db::DB;
+use crate::types::{BranchINodeHeader, invalid_data, key_to_str, LeafInodeHeader, MAGIC, Meta, META_PAGES, PageHeader, PageId, prefix_size};
+
+// Нарушение целостности, найденное check. page_id - страница, на которой оно найдено
+// (0 или 1 - слот текущей meta).
+#[derive(Debug)]
+pub struct Violation {
+    pub page_id: PageId,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {}: {}", self.page_id, self.message)
+    }
+}
+
+// Страницы, уже пройденные обходом дерева. На каждую страницу дерева ссылаются один раз,
+// поэтому повторная страница - это цикл или общее поддерево. check отмечает такие страницы
+// нарушением, а остальные обходы (stats, compact, загрузка BPlusTree, dbinspect) на них
+// останавливаются: иначе цикл обходится до бесконечности.
+#[derive(Default)]
+pub struct Visited {
+    pages: HashSet<PageId>,
+}
+
+impl Visited {
+    pub fn new() -> Visited {
+        Visited::default()
+    }
+
+    // false, если страница уже была
+    pub fn insert(&mut self, page_id: PageId) -> bool {
+        self.pages.insert(page_id)
+    }
+
+    pub fn contains(&self, page_id: PageId) -> bool {
+        self.pages.contains(&page_id)
+    }
+
+    // Отмечает страницу вместе с overflow-страницами. Ошибка, если какая-то уже пройдена.
+    pub fn visit(&mut self, page_id: PageId, page_overflow_count: u32) -> io::Result<()> {
+        for id in page_id..=page_id + page_overflow_count as PageId {
+            if !self.insert(id) {
+                return Err(invalid_data(format!("page {} is referenced more than once (cycle in tree)", id)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Проверка целостности файла базы (fsck). Обходит все страницы, достижимые из meta, и
+// собирает все найденные нарушения, не останавливаясь на первом:
+//  - тип каждой страницы известен и это лист или ветка;
+//  - заголовки inode, ключи и значения не выходят за границы страницы (с учетом overflow);
+//  - сжатые значения распаковываются (кроме сжатых кодеком, которого база не знает);
+//  - ключи отсортированы компаратором базы внутри страницы и между листьями;
+//  - ключ-разделитель в ветке не больше первого ключа дочерней страницы и больше всех
+//    ключей левее нее (разделитель может быть укорочен, см. Comparator::separator);
+//  - на каждую страницу ссылаются не больше одного раза, а каждая страница до
+//    Meta.page_count либо достижима, либо записана в freelist (и не то и другое сразу).
+pub fn check(db: &DB) -> io::Result<Vec<Violation>> {
+    let meta = db.meta();
+    let mut checker = Checker {
+        db,
+        page_size: db.page_size(),
+        page_count: meta.page_count.get() as PageId,
+        meta,
+        file_size: db.file_size()?,
+        seen: Visited::new(),
+        free: HashSet::new(),
+        last_key: None,
+        violations: vec![],
+    };
+
+    checker.check_meta();
+    if checker.violations.is_empty() {
+        checker.check_page(meta.root_page.get() as PageId, None);
+        checker.check_freelist();
+        checker.check_unreachable();
+    }
+
+    Ok(checker.violations)
+}
+
+struct Checker<'a> {
+    db: &'a DB,
+    page_size: usize,
+    page_count: PageId,
+    // Проверяется одна meta, взятая в начале проверки
+    meta: Meta,
+    file_size: u64,
+
+    seen: Visited,
+    // Страницы из freelist
+    free: HashSet<PageId>,
+    // Последний ключ в уже проверенных листьях, для проверки порядка между страницами
+    last_key: Option<Cow<'a, [u8]>>,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, page_id: PageId, message: String) {
+        self.violations.push(Violation { page_id, message });
+    }
+
+    fn check_meta(&mut self) {
+        let meta = self.meta;
+        let (magic, page_size, root_page, page_count) = (meta.magic.get(), meta.page_size.get(), meta.root_page.get(), meta.page_count.get());
+
+        let slot = meta.slot();
+        if magic != MAGIC {
+            self.report(slot, format!("invalid magic {:#x}", magic));
+        }
+
+        match self.db.page(slot).and_then(|x| x.meta_checksum()) {
+            Ok(checksum) if checksum == meta.checksum() => {}
+            _ => self.report(slot, "meta checksum mismatch".to_string()),
+        }
+
+        if page_size as usize != self.page_size || page_size == 0 {
+            self.report(slot, format!("invalid page size {}", page_size));
+        }
+
+        if page_count as u64 * self.page_size as u64 > self.file_size {
+            self.report(slot, format!("page_count {} exceeds file size {}", page_count, self.file_size));
+        }
+
+        if (root_page as PageId) < META_PAGES || root_page >= page_count {
+            self.report(slot, format!("root page {} is out of range", root_page));
+        }
+
+        // Второй слот - предыдущая meta, он может быть и порван: она уже не нужна
+        for page_id in 0..META_PAGES {
+            self.seen.insert(page_id);
+        }
+    }
+
+    // separator - ключ-разделитель, под которым на страницу ссылается родитель
+    fn check_page(&mut self, page_id: PageId, separator: Option<Cow<'a, [u8]>>) {
+        if page_id < META_PAGES || page_id >= self.page_count {
+            self.report(page_id, format!("reference to page out of range (page_count={})", self.page_count));
+            return;
+        }
+
+        if self.seen.contains(page_id) {
+            self.report(page_id, "page is referenced more than once".to_string());
+            return;
+        }
+
+        let page = match self.db.page(page_id) {
+            Ok(page) => page,
+            Err(e) => {
+                self.report(page_id, e.to_string());
+                return;
+            }
+        };
+        let (id, inode_count, page_overflow_count) = (page.id.get(), page.inode_count.get(), page.page_overflow_count.get());
+        let last_page_id = page_id + page_overflow_count as PageId;
+
+        for id in page_id..=std::cmp::min(last_page_id, self.page_count - 1) {
+            if !self.seen.insert(id) {
+                self.report(id, format!("overflow page of page {} is referenced more than once", page_id));
+            }
+        }
+
+        if last_page_id >= self.page_count {
+            self.report(page_id, format!("{} overflow pages go past page_count", page_overflow_count));
+            return;
+        }
+
+        if id != page_id {
+            self.report(page_id, format!("page header has id {}", id));
+        }
+
+        if page.type_name() == "unknown" || !(page.is_leaf() || page.is_branch()) {
+            self.report(page_id, format!("unexpected {} page in tree", page.type_name()));
+            return;
+        }
+
+        if inode_count == 0 && separator.is_some() {
+            self.report(page_id, "empty non-root page".to_string());
+        }
+
+        let page_len = (page_overflow_count as usize + 1) * self.page_size;
+        let inode_size = if page.is_leaf() {
+            size_of::<LeafInodeHeader>()
+        } else {
+            size_of::<BranchINodeHeader>()
+        };
+
+        let prefix = match page.prefix() {
+            Ok(prefix) => prefix,
+            Err(e) => {
+                self.report(page_id, format!("key prefix: {}", e));
+                return;
+            }
+        };
+
+        if size_of::<PageHeader>() + prefix_size(prefix.len()) + inode_count as usize * inode_size > page_len {
+            self.report(page_id, format!("{} inodes do not fit into {} bytes", inode_count, page_len));
+            return;
+        }
+
+        // Ключи (и значения) страницы, прошедшие проверку границ
+        let mut keys = Vec::<(Cow<'a, [u8]>, Option<PageId>)>::new();
+        for idx in 0..inode_count as usize {
+            let inode = if page.is_leaf() {
+                page.leaf_inode(idx).map(|x| {
+                    self.check_value(page_id, &x.key, x.codec, &x.value);
+                    (x.key, None)
+                })
+            } else {
+                page.branch_inode(idx).map(|x| (x.key, Some(x.page_id)))
+            };
+
+            let (key, child) = match inode {
+                Ok(inode) => inode,
+                Err(e) => {
+                    self.report(page_id, format!("inode {}: {}", idx, e));
+                    continue;
+                }
+            };
+
+            if let Some((prev, _)) = keys.last() {
+                if self.db.compare(prev, &key) != Ordering::Less {
+                    let message = format!("key \"{}\" is not greater than previous key \"{}\"", key_to_str(&key), key_to_str(prev));
+                    self.report(page_id, message);
+                }
+            }
+
+            keys.push((key, child));
+        }
+
+        if let (Some(separator), Some((key, _))) = (&separator, keys.first()) {
+            if self.db.compare(separator, key) == Ordering::Greater {
+                let message = format!("first key \"{}\" is less than separator \"{}\" in parent", key_to_str(key), key_to_str(separator));
+                self.report(page_id, message);
+            }
+        }
+
+        // Листья левее уже проверены, и last_key - наибольший ключ в них
+        if let (Some(separator), Some(prev)) = (&separator, &self.last_key) {
+            if self.db.compare(separator, prev) != Ordering::Greater {
+                let message = format!("separator \"{}\" in parent is not greater than last key \"{}\" of previous leaf", key_to_str(separator), key_to_str(prev));
+                self.report(page_id, message);
+            }
+        }
+
+        if page.is_leaf() {
+            if let (Some(prev), Some((key, _))) = (&self.last_key, keys.first()) {
+                if self.db.compare(prev, key) != Ordering::Less {
+                    let message = format!("first key \"{}\" is not greater than last key \"{}\" of previous leaf", key_to_str(key), key_to_str(prev));
+                    self.report(page_id, message);
+                }
+            }
+
+            if let Some((key, _)) = keys.pop() {
+                self.last_key = Some(key);
+            }
+
+            return;
+        }
+
+        for (key, child) in keys {
+            self.check_page(child.unwrap(), Some(key));
+        }
+    }
+
+    fn check_value(&mut self, page_id: PageId, key: &[u8], codec: u8, value: &[u8]) {
+        match self.db.decode(codec, Cow::Borrowed(value)) {
+            Err(e) if e.kind() != io::ErrorKind::Unsupported => {
+                self.report(page_id, format!("value of key \"{}\": {}", key_to_str(key), e));
+            }
+            _ => {}
+        }
+    }
+
+    // Страница freelist сама занята, а страницы из списка не должны быть заняты деревом
+    // и не должны повторяться. Вызывается после обхода дерева.
+    fn check_freelist(&mut self) {
+        let freelist_page = self.meta.freelist_page.get() as PageId;
+        if freelist_page == 0 {
+            return;
+        }
+
+        let free_pages = match self.db.page(freelist_page).and_then(|x| x.freelist().map(|ids| (x, ids))) {
+            Ok((page, ids)) => {
+                for id in freelist_page..=freelist_page + page.page_overflow_count.get() as PageId {
+                    if id >= self.page_count || !self.seen.insert(id) {
+                        self.report(id, "freelist page overlaps another page".to_string());
+                    }
+                }
+                ids
+            }
+            Err(e) => {
+                self.report(freelist_page, e.to_string());
+                return;
+            }
+        };
+
+        for page_id in free_pages {
+            if page_id < META_PAGES || page_id >= self.page_count {
+                self.report(freelist_page, format!("free page {} is out of range (page_count={})", page_id, self.page_count));
+            } else if self.free.contains(&page_id) {
+                self.report(page_id, "page is in freelist more than once".to_string());
+            } else if self.seen.contains(page_id) {
+                self.report(page_id, "page is both in use and free".to_string());
+            } else {
+                self.free.insert(page_id);
+            }
+        }
+    }
+
+    fn check_unreachable(&mut self) {
+        // Все, что не достижимо из корня и не записано в freelist, потеряно
+        for page_id in META_PAGES..self.page_count {
+            if !self.seen.contains(page_id) && !self.free.contains(&page_id) {
+                self.report(page_id, "page is neither reachable nor free".to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::size_of;
+
+use crate::types::{from_bytes, invalid_data, Le16, PAGE_ENCRYPTED, PageHeader, PageId, to_bytes};
+
+// Шифрование страниц на диске. Заголовок страницы остается открытым: по нему читатель
+// узнает тип страницы и сколько у нее overflow-страниц. За заголовком открытым текстом
+// лежит соль, дальше - зашифрованное тело, а в конце последней overflow-страницы - тег
+// аутентификации. Тег покрывает и тело, и заголовок, поэтому подмененная или испорченная
+// страница не расшифровывается в мусор, а дает ошибку. Meta-страница не шифруется.
+//
+// Nonce - номер страницы и соль. Txid для nonce не годится: коммит, который упал или не
+// дошел до meta, оставляет свой txid следующему, и тот пишет те же номера страниц. Поэтому
+// каждая запись (коммит, create, compact, save_tree) берет свежую случайную соль: nonce
+// повторится, только если для одного номера страницы выпадет та же 64-битная соль.
+pub const NONCE_SIZE: usize = 2 * size_of::<u64>();
+pub const SALT_SIZE: usize = size_of::<u64>();
+pub const TAG_SIZE: usize = 16;
+
+pub trait PageCipher: fmt::Debug + Send + Sync {
+    // Шифрует data на месте (размер страницы не меняется, поэтому шифр должен сохранять
+    // длину) и возвращает тег, который покрывает data и открытые данные aad
+    fn encrypt(&self, key: &[u8], nonce: &[u8; NONCE_SIZE], aad: &[u8], data: &mut [u8]) -> [u8; TAG_SIZE];
+    // Расшифровывает data на месте. false, если тег не сошелся: страницу испортили или
+    // подменили, или ключ не тот.
+    fn decrypt(&self, key: &[u8], nonce: &[u8; NONCE_SIZE], aad: &[u8], data: &mut [u8], tag: &[u8; TAG_SIZE]) -> bool;
+}
+
+// Ключ из Options. Debug его не печатает, чтобы ключ не попал в логи вместе с Options.
+#[derive(Clone, Default)]
+pub struct CipherKey(pub Vec<u8>);
+
+impl fmt::Debug for CipherKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CipherKey({} bytes)", self.0.len())
+    }
+}
+
+pub fn nonce(page_id: PageId, salt: u64) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0; NONCE_SIZE];
+    nonce[..8].copy_from_slice(&page_id.to_le_bytes());
+    nonce[8..].copy_from_slice(&salt.to_le_bytes());
+    nonce
+}
+
+// Свежая соль для одной записи страниц. Без шифра соль не нужна, и случайные байты не
+// читаются.
+pub fn salt(cipher: Option<&dyn PageCipher>) -> io::Result<u64> {
+    if cipher.is_none() {
+        return Ok(0);
+    }
+
+    let mut buf = [0; SALT_SIZE];
+    File::open("/dev/urandom")?.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// Сколько байт страницы занимает шифрование. Писатели оставляют их свободными в конце
+// страницы, а seal сдвигает тело на их место.
+pub fn page_overhead(cipher: Option<&dyn PageCipher>) -> usize {
+    match cipher {
+        Some(_) => SALT_SIZE + TAG_SIZE,
+        None => 0,
+    }
+}
+
+// Шифрует страницу buf (вместе с overflow-страницами) на месте. Без шифра ничего не делает.
+pub fn seal(cipher: Option<&dyn PageCipher>, key: &[u8], buf: &mut [u8], salt: u64) {
+    let cipher = match cipher {
+        Some(cipher) => cipher,
+        None => return,
+    };
+
+    let header_size = size_of::<PageHeader>();
+    let mut header: PageHeader = from_bytes(buf);
+    header.flags = Le16::new(header.flags.get() | PAGE_ENCRYPTED);
+
+    let len = buf.len();
+    buf.copy_within(header_size..len - SALT_SIZE - TAG_SIZE, header_size + SALT_SIZE);
+    buf[..header_size].copy_from_slice(to_bytes(&header));
+    buf[header_size..header_size + SALT_SIZE].copy_from_slice(&salt.to_le_bytes());
+
+    let (head, body) = buf.split_at_mut(header_size + SALT_SIZE);
+    let (body, tag) = body.split_at_mut(len - header_size - SALT_SIZE - TAG_SIZE);
+    tag.copy_from_slice(&cipher.encrypt(key, &nonce(header.id.get(), salt), &head[..header_size], body));
+}
+
+// Расшифрованная копия зашифрованной страницы page_id: заголовок (флаг PAGE_ENCRYPTED
+// остается) и тело, без соли и тега
+pub fn open(cipher: &dyn PageCipher, key: &[u8], page_id: PageId, buf: &[u8]) -> io::Result<Vec<u8>> {
+    let header_size = size_of::<PageHeader>();
+    if buf.len() < header_size + SALT_SIZE + TAG_SIZE {
+        return Err(invalid_data(format!("encrypted page {} of {} bytes is too short", page_id, buf.len())));
+    }
+
+    let (head, rest) = buf.split_at(header_size);
+    let (salt, rest) = rest.split_at(SALT_SIZE);
+    let (body, tag) = rest.split_at(rest.len() - TAG_SIZE);
+    let mut salt_bytes = [0; SALT_SIZE];
+    salt_bytes.copy_from_slice(salt);
+    let mut tag_bytes = [0; TAG_SIZE];
+    tag_bytes.copy_from_slice(tag);
+
+    let mut page = Vec::with_capacity(header_size + body.len());
+    page.extend_from_slice(head);
+    page.extend_from_slice(body);
+    let nonce = nonce(page_id, u64::from_le_bytes(salt_bytes));
+    if !cipher.decrypt(key, &nonce, head, &mut page[header_size..], &tag_bytes) {
+        return Err(invalid_data(format!("page {} failed authentication (corrupted page or wrong key)", page_id)));
+    }
+    Ok(page)
+}
+
+// Шифр для проверки обвязки без внешних зависимостей: XOR с гаммой splitmix64 от ключа и
+// nonce, тег - два FNV-хеша ключа, nonce, aad и шифротекста. Криптостойкости в нем нет,
+// для настоящих данных нужен настоящий шифр (AEAD вроде AES-GCM или ChaCha20-Poly1305).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TestCipher;
+
+impl TestCipher {
+    fn apply(key: &[u8], nonce: &[u8; NONCE_SIZE], data: &mut [u8]) {
+        let mut state = fnv(0xcbf29ce484222325, key.iter().chain(nonce.iter()));
+
+        for chunk in data.chunks_mut(size_of::<u64>()) {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut x = state;
+            x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+            x ^= x >> 31;
+
+            for (byte, gamma) in chunk.iter_mut().zip(x.to_le_bytes().iter()) {
+                *byte ^= gamma;
+            }
+        }
+    }
+
+    fn tag(key: &[u8], nonce: &[u8; NONCE_SIZE], aad: &[u8], data: &[u8]) -> [u8; TAG_SIZE] {
+        let bytes = || key.iter().chain(nonce.iter()).chain(aad.iter()).chain(data.iter());
+        let mut tag = [0; TAG_SIZE];
+        tag[..8].copy_from_slice(&fnv(0xcbf29ce484222325, bytes()).to_le_bytes());
+        tag[8..].copy_from_slice(&fnv(0x84222325cbf29ce4, bytes()).to_le_bytes());
+        tag
+    }
+}
+
+fn fnv<'a>(seed: u64, bytes: impl Iterator<Item=&'a u8>) -> u64 {
+    bytes.fold(seed, |hash, &x| (hash ^ x as u64).wrapping_mul(0x100000001b3))
+}
+
+impl PageCipher for TestCipher {
+    fn encrypt(&self, key: &[u8], nonce: &[u8; NONCE_SIZE], aad: &[u8], data: &mut [u8]) -> [u8; TAG_SIZE] {
+        TestCipher::apply(key, nonce, data);
+        TestCipher::tag(key, nonce, aad, data)
+    }
+
+    fn decrypt(&self, key: &[u8], nonce: &[u8; NONCE_SIZE], aad: &[u8], data: &mut [u8], tag: &[u8; TAG_SIZE]) -> bool {
+        if TestCipher::tag(key, nonce, aad, data) != *tag {
+            return false;
+        }
+
+        TestCipher::apply(key, nonce, data);
+        true
+    }
+}
@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+
+use crate::types::invalid_data;
+
+// Id кодека записывается в файл перед каждым значением листа с PAGE_CODEC, поэтому у одной
+// базы могут быть значения, сжатые разными кодеками, и несжатые.
+pub const CODEC_NONE: u8 = 0;
+pub const CODEC_LZ: u8 = 1;
+
+// Сжатие значений. Ключи не сжимаются: по ним идет поиск.
+pub trait Codec: fmt::Debug + Send + Sync {
+    // Id кодека в файле, не CODEC_NONE. Встроенный кодек с тем же id перекрывается.
+    fn id(&self) -> u8;
+    fn compress(&self, data: &[u8]) -> Vec<u8>;
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+// Сжимает value кодеком codec. Если сжатое значение не меньше исходного, значение
+// остается как есть, с CODEC_NONE.
+pub fn encode(codec: Option<&dyn Codec>, value: Vec<u8>) -> (u8, Vec<u8>) {
+    match codec {
+        Some(codec) => {
+            let compressed = codec.compress(&value);
+            if compressed.len() < value.len() {
+                (codec.id(), compressed)
+            } else {
+                (CODEC_NONE, value)
+            }
+        }
+        None => (CODEC_NONE, value),
+    }
+}
+
+// Распаковывает значение, записанное кодеком id. codec - кодек из Options, встроенные
+// известны всегда. Неизвестный кодек - ошибка Unsupported: значение может быть целым,
+// просто база открыта без нужного кодека.
+pub fn decode<'a>(codec: Option<&dyn Codec>, id: u8, value: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+    if id == CODEC_NONE {
+        return Ok(Cow::Borrowed(value));
+    }
+
+    match codec {
+        Some(codec) if codec.id() == id => codec.decompress(value).map(Cow::Owned),
+        _ if id == CODEC_LZ => Lz.decompress(value).map(Cow::Owned),
+        _ => Err(io::Error::new(io::ErrorKind::Unsupported, format!("unknown codec {}", id))),
+    }
+}
+
+// Встроенный LZ77 без зависимостей, в духе LZ4. Формат: длина исходных данных (Le32) и
+// дальше последовательность команд:
+//  - 0xxxxxxx: x + 1 байт литералов следом;
+//  - 1xxxxxxx: повтор x + MIN_MATCH байт, начиная с offset (Le16) байт назад.
+// Повтор может перекрывать сам себя (offset меньше длины) - так кодируются серии.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Lz;
+
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = 0x7F + MIN_MATCH;
+const MAX_LITERALS: usize = 0x80;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 14;
+
+impl Lz {
+    fn hash(data: &[u8]) -> usize {
+        let x = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        (x.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn flush_literals(out: &mut Vec<u8>, literals: &[u8]) {
+        for chunk in literals.chunks(MAX_LITERALS) {
+            out.push((chunk.len() - 1) as u8);
+            out.extend_from_slice(chunk);
+        }
+    }
+}
+
+impl Codec for Lz {
+    fn id(&self) -> u8 {
+        CODEC_LZ
+    }
+
+    // Жадный поиск: на каждой позиции берется последнее вхождение тех же 4 байт из
+    // хеш-таблицы, если оно не дальше MAX_OFFSET
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() / 2 + 8);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+        let mut table = vec![usize::MAX; 1 << HASH_BITS];
+        let (mut pos, mut literals) = (0, 0);
+        while pos + MIN_MATCH <= data.len() {
+            let hash = Lz::hash(&data[pos..]);
+            let candidate = std::mem::replace(&mut table[hash], pos);
+
+            let found = candidate != usize::MAX && pos - candidate <= MAX_OFFSET
+                && data[candidate..candidate + MIN_MATCH] == data[pos..pos + MIN_MATCH];
+            if !found {
+                pos += 1;
+                continue;
+            }
+
+            let len = data[pos..].iter().zip(data[candidate..].iter())
+                .take(MAX_MATCH)
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            Lz::flush_literals(&mut out, &data[literals..pos]);
+            out.push(0x80 | (len - MIN_MATCH) as u8);
+            out.extend_from_slice(&((pos - candidate) as u16).to_le_bytes());
+
+            pos += len;
+            literals = pos;
+        }
+
+        Lz::flush_literals(&mut out, &data[literals..]);
+        out
+    }
+
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let truncated = || invalid_data("truncated lz data".to_string());
+
+        let header = data.get(..4).ok_or_else(truncated)?;
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+
+        // Длине из заголовка верим не больше, чем позволяют сами данные
+        let mut out = Vec::with_capacity(std::cmp::min(len, data.len() * MAX_MATCH));
+        let mut pos = 4;
+        while pos < data.len() {
+            let cmd = data[pos] as usize;
+            pos += 1;
+
+            if cmd & 0x80 == 0 {
+                let literals = data.get(pos..pos + cmd + 1).ok_or_else(truncated)?;
+                out.extend_from_slice(literals);
+                pos += cmd + 1;
+            } else {
+                let offset = data.get(pos..pos + 2).ok_or_else(truncated)?;
+                let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+                pos += 2;
+
+                if offset == 0 || offset > out.len() {
+                    return Err(invalid_data(format!("lz offset {} is out of {} decoded bytes", offset, out.len())));
+                }
+
+                let start = out.len() - offset;
+                for idx in 0..(cmd & 0x7F) + MIN_MATCH {
+                    out.push(out[start + idx]);
+                }
+            }
+
+            if out.len() > len {
+                return Err(invalid_data(format!("lz data decodes to more than {} bytes", len)));
+            }
+        }
+
+        if out.len() != len {
+            return Err(invalid_data(format!("lz data decodes to {} bytes instead of {}", out.len(), len)));
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::check::Visited;
+use crate::cipher::{self, CipherKey, PageCipher};
+use crate::db::{DB, Options};
+use crate::node::{HeapValue, INode, Node};
+use crate::codec::CODEC_NONE;
+use crate::types::{Le32, Le64, Meta, META_PAGES, PageId};
+
+// Офлайн-компактизация. Живое дерево (все, что достижимо из Meta.root_page) переписывается
+// в новый файл подряд: листья и ветки заново упаковываются до заполнения страницы, а
+// страницы, брошенные после copy-on-write коммитов, в новый файл просто не попадают.
+//
+// Новый файл пишется рядом и подменяет старый через rename, поэтому после сбоя на диске
+// будет либо старый файл, либо полностью записанный новый. Во время компактизации с базой
+// никто работать не должен.
+pub fn compact(path: &str) -> io::Result<()> {
+    compact_with(path, &Options::default())
+}
+
+// compact с шифром из options: им читаются зашифрованные страницы исходного файла, и им же
+// шифруется новый файл. Без шифра в options новый файл пишется открытым.
+pub fn compact_with(path: &str, options: &Options) -> io::Result<()> {
+    let tmp_path = format!("{}.compact", path);
+    compact_to_with(path, &tmp_path, options)?;
+    fs::rename(&tmp_path, path)?;
+
+    // rename должен дойти до диска, иначе после сбоя может вернуться старое имя
+    let dir = match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+// Пишет компактную копию базы src в dst (dst перезаписывается)
+pub fn compact_to(src: &str, dst: &str) -> io::Result<()> {
+    compact_to_with(src, dst, &Options::default())
+}
+
+// compact_to с шифром и ключом из options. Компаратор и dupsort берутся из meta src: по ним
+// отсортированы ключи и считаются разделители, поэтому базу со своим компаратором сжать нельзя.
+pub fn compact_to_with(src: &str, dst: &str, options: &Options) -> io::Result<()> {
+    let db = DB::open_with(src, Options::from_meta(src, Options { read_only: true, ..options.clone() })?)?;
+    let f = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(dst)?;
+
+    let src_meta = db.meta();
+    let mut leaf_inodes = vec![];
+    let mut prefixed = false;
+    collect_leaf_inodes(&db, src_meta.root_page.get() as PageId, &mut Visited::new(), &mut leaf_inodes, &mut prefixed)?;
+
+    // Сжатие префиксов сохраняется, если в исходном файле есть сжатые страницы. Копия
+    // пишется одной транзакцией, следующей за последней в исходном файле.
+    let mut writer = PageWriter {
+        f,
+        page_size: db.page_size(),
+        page_count: META_PAGES,
+        prefix_compression: prefixed,
+        cipher: options.cipher.clone(),
+        cipher_key: options.cipher_key.clone(),
+        txid: src_meta.txid.get() + 1,
+        salt: cipher::salt(options.cipher.as_deref())?,
+        db: &db,
+    };
+
+    // Строим дерево снизу вверх: каждый уровень - это ссылки на страницы предыдущего
+    let mut level = writer.write_level(true, leaf_inodes)?;
+    while level.len() > 1 {
+        level = writer.write_level(false, level)?;
+    }
+
+    writer.f.sync_all()?;
+
+    let meta = Meta {
+        root_page: Le32::new(level[0].page_id.unwrap() as u32),
+        page_count: Le32::new(writer.page_count as u32),
+        // Брошенные страницы в новый файл не попали, свободных страниц нет
+        freelist_page: Le32::new(0),
+        txid: Le64::new(writer.txid),
+        ..src_meta
+    };
+
+    writer.f.write_all_at(meta.to_file_bytes(writer.page_size).as_slice(), 0)?;
+    writer.f.set_len(writer.page_count * writer.page_size as u64)?;
+    writer.f.sync_all()
+}
+
+// Все пары ключ-значение дерева по порядку, без копирования (ссылки в mmap; ключи сжатых
+// страниц собираются в хипе). Значения остаются сжатыми, как были, поэтому кодек для
+// compact не нужен. prefixed - встретилась ли страница с общим префиксом ключей.
+fn collect_leaf_inodes<'a>(db: &'a DB, page_id: PageId, visited: &mut Visited, inodes: &mut Vec<INode<'a>>, prefixed: &mut bool) -> io::Result<()> {
+    let page = db.page(page_id)?;
+    visited.visit(page_id, page.page_overflow_count.get())?;
+    *prefixed |= page.has_prefix();
+
+    if page.is_leaf() {
+        for inode in page.leaf_inodes()? {
+            inodes.push(INode {
+                key: inode.key.into(),
+                value: inode.value.into(),
+                codec: inode.codec,
+                page_id: None,
+            });
+        }
+    } else {
+        for inode in page.branch_inodes()? {
+            collect_leaf_inodes(db, inode.page_id, visited, inodes, prefixed)?;
+        }
+    }
+
+    Ok(())
+}
+
+struct PageWriter<'c> {
+    f: File,
+    page_size: usize,
+    page_count: PageId,
+    prefix_compression: bool,
+    cipher: Option<Arc<dyn PageCipher>>,
+    cipher_key: CipherKey,
+    txid: u64,
+    // Соль зашифрованных страниц копии (см. cipher::salt)
+    salt: u64,
+    // Разделители листьев - DB::separator исходной базы
+    db: &'c DB,
+}
+
+impl PageWriter<'_> {
+    // Раскладывает inodes по нодам так плотно, как позволяет размер страницы, и пишет их.
+    // Возвращает inodes для уровня выше: разделитель и страницу каждой записанной ноды.
+    // Листья отделяются друг от друга разделителем компаратора (для побайтового - самым
+    // коротким), ноды веток - своим первым ключом (он уже разделитель).
+    fn write_level<'a>(&mut self, is_leaf: bool, inodes: Vec<INode<'a>>) -> io::Result<Vec<INode<'a>>> {
+        let mut parents = vec![];
+        let mut node = self.new_node(is_leaf, vec![]);
+        let (mut size, mut codec_size) = (node.size(), 0);
+        let mut separator = None;
+
+        for inode in inodes {
+            // Размер ноды набирается по ходу, как в Node::split_index. Целиком он пересчитывается,
+            // только когда меняется у всех inode сразу: при сжатии префиксов (общий префикс
+            // может укоротиться) и на первом сжатом значении листа (у всех появляется байт кодека).
+            let recount = self.prefix_compression || (codec_size == 0 && inode.codec != CODEC_NONE);
+            node.inodes.push(inode);
+            if recount {
+                (size, codec_size) = (node.size(), node.codec_size());
+            } else {
+                size += node.inode_size(node.inodes.last().unwrap(), 0) + codec_size;
+            }
+
+            // Элемент, который не влезает даже в пустую страницу, уходит в overflow-страницы
+            if size > self.capacity() && node.inodes.len() > 1 {
+                let last = node.inodes.pop().unwrap();
+                parents.push(self.write_node(&node, separator.take())?);
+                if is_leaf {
+                    separator = Some(self.db.separator(node.inodes.last().unwrap().key(), last.key()).to_vec());
+                }
+                node = self.new_node(is_leaf, vec![last]);
+                (size, codec_size) = (node.size(), node.codec_size());
+            }
+        }
+
+        if !node.inodes.is_empty() || parents.is_empty() {
+            parents.push(self.write_node(&node, separator)?);
+        }
+
+        Ok(parents)
+    }
+
+    // Место в странице под ноду, без байт под шифрование
+    fn capacity(&self) -> usize {
+        self.page_size - cipher::page_overhead(self.cipher.as_deref())
+    }
+
+    fn new_node<'a>(&self, is_leaf: bool, inodes: Vec<INode<'a>>) -> Node<'a> {
+        let mut node = Node::new(is_leaf, inodes);
+        node.prefix_compression = self.prefix_compression;
+        node
+    }
+
+    fn write_node<'a>(&mut self, node: &Node<'a>, separator: Option<Vec<u8>>) -> io::Result<INode<'a>> {
+        let page_num = (node.size() + self.page_size - self.capacity()).div_ceil(self.page_size);
+        let page_id = self.page_count;
+        self.page_count += page_num as PageId;
+
+        let mut buffer = vec![0u8; page_num * self.page_size];
+        node.serialize(page_id, (page_num - 1) as u32, buffer.as_mut_slice());
+        cipher::seal(self.cipher.as_deref(), &self.cipher_key.0, &mut buffer, self.salt);
+        self.f.write_all_at(buffer.as_slice(), page_id * self.page_size as u64)?;
+
+        Ok(INode {
+            key: match separator {
+                Some(separator) => HeapValue::Heap(separator),
+                None => node.inodes.first().map_or(HeapValue::None, |x| x.key.clone()),
+            },
+            value: HeapValue::None,
+            codec: CODEC_NONE,
+            page_id: Some(page_id),
+        })
+    }
+}
@@ -0,0 +1,88 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::types::shortest_separator;
+
+// Id компаратора хранится в meta: дерево, отсортированное одним компаратором, другим не
+// читается, поэтому база открывается только с тем, с которым создана.
+pub const COMPARATOR_BYTES: u32 = 0;
+pub const COMPARATOR_REVERSE: u32 = 1;
+pub const COMPARATOR_CASE_INSENSITIVE: u32 = 2;
+
+// Порядок ключей в дереве. Ключи, равные по compare, - один и тот же ключ.
+pub trait Comparator: fmt::Debug + Send + Sync {
+    // Id компаратора в meta. Встроенные занимают id до 255, свои лучше брать больше.
+    fn id(&self) -> u32;
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    // Разделитель для ветки между соседними нодами: ключ s, для которого last < s <= first.
+    // Первый ключ правой ноды подходит всегда, укоротить его можно только зная порядок.
+    fn separator<'a>(&self, _last: &[u8], first: &'a [u8]) -> &'a [u8] {
+        first
+    }
+}
+
+// Побайтовый порядок, по умолчанию
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Bytes;
+
+impl Comparator for Bytes {
+    fn id(&self) -> u32 {
+        COMPARATOR_BYTES
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn separator<'a>(&self, last: &[u8], first: &'a [u8]) -> &'a [u8] {
+        shortest_separator(last, first)
+    }
+}
+
+// Побайтовый в обратную сторону: курсор идет от больших ключей к меньшим
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Reverse;
+
+impl Comparator for Reverse {
+    fn id(&self) -> u32 {
+        COMPARATOR_REVERSE
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+// Побайтовый без учета регистра ASCII: "Key" и "KEY" - один ключ
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CaseInsensitive;
+
+impl Comparator for CaseInsensitive {
+    fn id(&self) -> u32 {
+        COMPARATOR_CASE_INSENSITIVE
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.iter().map(u8::to_ascii_lowercase).cmp(b.iter().map(u8::to_ascii_lowercase))
+    }
+}
+
+// Встроенный компаратор по id из meta. None - id не встроенного компаратора.
+pub fn builtin(id: u32) -> Option<Arc<dyn Comparator>> {
+    match id {
+        COMPARATOR_BYTES => Some(Arc::new(Bytes)),
+        COMPARATOR_REVERSE => Some(Arc::new(Reverse)),
+        COMPARATOR_CASE_INSENSITIVE => Some(Arc::new(CaseInsensitive)),
+        _ => None,
+    }
+}
+
+// Компаратор из Options: None - побайтовый
+pub fn resolve(comparator: Option<&Arc<dyn Comparator>>) -> Arc<dyn Comparator> {
+    match comparator {
+        Some(comparator) => comparator.clone(),
+        None => Arc::new(Bytes),
+    }
+}
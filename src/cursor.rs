@@ -0,0 +1,296 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::io;
+use std::mem::size_of;
+
+use crate::db::DB;
+use crate::node::{Node, NodeCache};
+use crate::types::{invalid_data, Key, Meta, PageId};
+
+// Пара ключ-значение. Ключ со страницы с общим префиксом и сжатое значение собираются в
+// отдельный буфер, остальные указывают прямо в mmap.
+pub type Item<'a> = (Cow<'a, [u8]>, Cow<'a, [u8]>);
+
+// Курсор по листьям дерева в порядке ключей. Хранит путь от корня до текущего листа:
+// страницу и индекс inode на каждом уровне. Читает страницы напрямую из mmap по meta,
+// взятой при создании курсора, поэтому видит только закоммиченные на тот момент данные:
+// страницы этого снимка коммиты не перезаписывают.
+//
+// Курсор транзакции (Tx::cursor) видит и ее незакоммиченные изменения: листья, которые она
+// уже поменяла, читаются из ее нод. До коммита дерево не делится и не сливается, поэтому
+// ветки у нод и у страниц одни и те же, а отличаются только inode листьев.
+pub struct Cursor<'a> {
+    db: &'a DB,
+    meta: Meta,
+    nodes: Option<&'a NodeCache<'a>>,
+    stack: Vec<(PageId, usize)>,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(db: &'a DB, meta: Meta) -> Cursor<'a> {
+        Cursor {
+            db,
+            meta,
+            nodes: None,
+            stack: vec![],
+        }
+    }
+
+    pub(crate) fn with_nodes(db: &'a DB, meta: Meta, nodes: &'a NodeCache<'a>) -> Cursor<'a> {
+        Cursor {
+            nodes: Some(nodes),
+            ..Cursor::new(db, meta)
+        }
+    }
+
+    pub fn first(&mut self) -> io::Result<Option<Item<'a>>> {
+        self.stack.clear();
+        self.stack.push((self.meta.root_page.get() as PageId, 0));
+        self.go_first()?;
+
+        self.current_or_next()
+    }
+
+    pub fn last(&mut self) -> io::Result<Option<Item<'a>>> {
+        self.stack.clear();
+        let root = self.meta.root_page.get() as PageId;
+        self.stack.push((root, self.count(root)?.saturating_sub(1)));
+        self.go_last()?;
+
+        self.current_or_prev()
+    }
+
+    // Встает на первый ключ, который больше или равен key (в dupsort-базе - на первое
+    // значение ключа)
+    pub fn seek(&mut self, key: &[u8]) -> io::Result<Option<Item<'a>>> {
+        self.stack.clear();
+
+        let mut page_id = self.meta.root_page.get() as PageId;
+        loop {
+            self.check_depth()?;
+
+            let page = self.db.page(page_id)?;
+            if page.is_leaf() {
+                let idx = match self.node(page_id) {
+                    Some(node) => node.inodes.iter()
+                        .position(|x| self.db.compare(x.key(), key) != Ordering::Less)
+                        .unwrap_or(node.inodes.len()),
+                    None => page.leaf_inodes()?.iter()
+                        .position(|x| self.db.compare(&x.key, key) != Ordering::Less)
+                        .unwrap_or(page.count()),
+                };
+                self.stack.push((page_id, idx));
+                break;
+            }
+
+            // Последний разделитель, который не больше key; если key меньше всех - первый
+            let inodes = page.branch_inodes()?;
+            let idx = inodes.iter()
+                .position(|x| self.db.compare(&x.key, key) == Ordering::Greater)
+                .unwrap_or(inodes.len())
+                .saturating_sub(1);
+            self.stack.push((page_id, idx));
+            page_id = page.branch_inode(idx)?.page_id;
+        }
+
+        self.current_or_next()
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<Option<Item<'a>>> {
+        if let Some(top) = self.stack.last_mut() {
+            top.1 += 1;
+        }
+
+        self.current_or_next()
+    }
+
+    pub fn prev(&mut self) -> io::Result<Option<Item<'a>>> {
+        while let Some(&(_, idx)) = self.stack.last() {
+            if idx > 0 {
+                self.stack.last_mut().unwrap().1 -= 1;
+                return self.current();
+            }
+
+            // Лист кончился: поднимаемся до уровня, где есть предыдущий потомок
+            self.stack.pop();
+            while let Some(&(_, idx)) = self.stack.last() {
+                if idx > 0 {
+                    break;
+                }
+                self.stack.pop();
+            }
+
+            match self.stack.last_mut() {
+                Some(top) => top.1 -= 1,
+                None => return Ok(None),
+            }
+            self.go_last()?;
+
+            // Встаем за последним элементом листа, на следующей итерации шагнем на него
+            let &(page_id, _) = self.stack.last().unwrap();
+            self.stack.last_mut().unwrap().1 = self.count(page_id)?;
+        }
+
+        Ok(None)
+    }
+
+    // Следующее значение того же ключа dupsort-базы. Если значений у ключа больше нет,
+    // курсор остается на месте.
+    pub fn next_dup(&mut self) -> io::Result<Option<Item<'a>>> {
+        self.step_dup(Cursor::next)
+    }
+
+    // Предыдущее значение того же ключа dupsort-базы
+    pub fn prev_dup(&mut self) -> io::Result<Option<Item<'a>>> {
+        self.step_dup(Cursor::prev)
+    }
+
+    fn step_dup(&mut self, step: fn(&mut Self) -> io::Result<Option<Item<'a>>>) -> io::Result<Option<Item<'a>>> {
+        let key = match self.current()? {
+            Some((key, _)) => key,
+            None => return Ok(None),
+        };
+
+        let stack = self.stack.clone();
+        match step(self)? {
+            Some(item) if self.db.comparator().compare(&item.0, &key) == Ordering::Equal => Ok(Some(item)),
+            _ => {
+                self.stack = stack;
+                Ok(None)
+            }
+        }
+    }
+
+    // В dupsort-базе ключ дерева делится на ключ и значение (см. DB::compare)
+    pub fn current(&self) -> io::Result<Option<Item<'a>>> {
+        let &(page_id, idx) = match self.stack.last() {
+            Some(top) => top,
+            None => return Ok(None),
+        };
+
+        if let Some(node) = self.node(page_id) {
+            let inode = match node.inodes.get(idx) {
+                Some(inode) => inode,
+                None => return Ok(None),
+            };
+            if self.db.dupsort() {
+                return split_dup(Cow::Borrowed(inode.key()), page_id).map(Some);
+            }
+
+            return Ok(Some((Cow::Borrowed(inode.key()), self.db.decode(inode.codec, Cow::Borrowed(inode.value()))?)));
+        }
+
+        let page = self.db.page(page_id)?;
+        if idx >= page.count() {
+            return Ok(None);
+        }
+
+        let inode = page.leaf_inode(idx)?;
+        if self.db.dupsort() {
+            return split_dup(inode.key, page_id).map(Some);
+        }
+
+        Ok(Some((inode.key, self.db.decode(inode.codec, inode.value)?)))
+    }
+
+    // Если курсор стоит за концом листа - переходит на начало следующего
+    fn current_or_next(&mut self) -> io::Result<Option<Item<'a>>> {
+        while let Some(&(page_id, idx)) = self.stack.last() {
+            if idx < self.count(page_id)? {
+                return self.current();
+            }
+
+            self.stack.pop();
+            while let Some(&(page_id, idx)) = self.stack.last() {
+                if idx + 1 < self.count(page_id)? {
+                    break;
+                }
+                self.stack.pop();
+            }
+
+            match self.stack.last_mut() {
+                Some(top) => top.1 += 1,
+                None => return Ok(None),
+            }
+            self.go_first()?;
+        }
+
+        Ok(None)
+    }
+
+    fn current_or_prev(&mut self) -> io::Result<Option<Item<'a>>> {
+        let &(page_id, _) = match self.stack.last() {
+            Some(top) => top,
+            None => return Ok(None),
+        };
+
+        if self.count(page_id)? > 0 {
+            return self.current();
+        }
+
+        self.prev()
+    }
+
+    // Спускается от текущей позиции в самый левый лист
+    fn go_first(&mut self) -> io::Result<()> {
+        loop {
+            let &(page_id, idx) = self.stack.last().unwrap();
+            let page = self.db.page(page_id)?;
+            if page.is_leaf() {
+                return Ok(());
+            }
+
+            self.check_depth()?;
+            let child = page.branch_inode(idx)?.page_id;
+            self.stack.push((child, 0));
+        }
+    }
+
+    // Спускается от текущей позиции в самый правый лист
+    fn go_last(&mut self) -> io::Result<()> {
+        loop {
+            let &(page_id, idx) = self.stack.last().unwrap();
+            let page = self.db.page(page_id)?;
+            if page.is_leaf() {
+                return Ok(());
+            }
+
+            self.check_depth()?;
+            let child = page.branch_inode(idx)?.page_id;
+            self.stack.push((child, self.count(child)?.saturating_sub(1)));
+        }
+    }
+
+    // Путь длиннее, чем страниц в файле, бывает только в дереве с циклом
+    fn check_depth(&self) -> io::Result<()> {
+        if self.stack.len() > self.meta.page_count.get() as usize {
+            return Err(invalid_data(format!("cycle in tree at page {}", self.stack.last().unwrap().0)));
+        }
+
+        Ok(())
+    }
+
+    fn count(&self, page_id: PageId) -> io::Result<usize> {
+        match self.node(page_id) {
+            Some(node) => Ok(node.inodes.len()),
+            None => Ok(self.db.page(page_id)?.count()),
+        }
+    }
+
+    fn node(&self, page_id: PageId) -> Option<&'a Node<'a>> {
+        self.nodes.and_then(|x| x.node(page_id))
+    }
+}
+
+fn split_dup(key: Cow<'_, [u8]>, page_id: PageId) -> io::Result<Item<'_>> {
+    let len = size_of::<Key>();
+    if key.len() < len {
+        return Err(invalid_data(format!("dupsort key of {} bytes on page {} is too short", key.len(), page_id)));
+    }
+
+    Ok(match key {
+        Cow::Borrowed(key) => (Cow::Borrowed(&key[..len]), Cow::Borrowed(&key[len..])),
+        Cow::Owned(key) => (Cow::Owned(key[..len].to_vec()), Cow::Owned(key[len..].to_vec())),
+    })
+}
@@ -0,0 +1,1122 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, BufWriter, Write};
+use std::mem::{self, size_of};
+use std::ops::RangeInclusive;
+use std::os::unix::fs::FileExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::sync::atomic::{self, AtomicU64, AtomicUsize};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use memmap::Mmap;
+use log::trace;
+
+use crate::cipher::{self, CipherKey, PageCipher};
+use crate::codec::{self, Codec, CODEC_NONE};
+use crate::comparator::{self, Comparator, COMPARATOR_BYTES};
+use crate::cursor::Cursor;
+use crate::node;
+use crate::node::{INode, HeapValue};
+use crate::stats::{self, Stats};
+use crate::types::{freelist_to_page_bytes, from_bytes, invalid_data, Key, key_to_str, Le32, Le64, MAGIC, MAX_PAGE_SIZE, Meta,
+                   META_DUPSORT, META_PAGES, MIN_PAGE_SIZE, PageHeader, PageId, PageRef, read_meta, SyncMode, VERSION};
+
+// Сколько вызовов batch объединяется в одну транзакцию и сколько ждать остальных
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+pub const DEFAULT_MAX_BATCH_DELAY: Duration = Duration::from_millis(10);
+
+// Файл отображается в память с запасом: страницы, дописанные коммитами в конец файла,
+// сразу становятся видны через тот же mapping без перемапливания.
+pub const DEFAULT_MAX_SIZE: usize = 1 << 30;
+
+// До какой доли страницы заполняется нода, отделяемая при делении переполненной ноды
+pub const DEFAULT_FILL_PERCENT: f64 = 0.5;
+pub const MIN_FILL_PERCENT: f64 = 0.1;
+pub const MAX_FILL_PERCENT: f64 = 1.0;
+
+// Как часто перепроверять блокировку файла, занятую другим процессом
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+// Сколько расшифрованных страниц держит кеш
+const DECRYPTED_CACHE_PAGES: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    // Только чтение: файл открывается без записи, update и batch возвращают ошибку
+    pub read_only: bool,
+    // Если файла нет - создать пустую базу
+    pub create_if_missing: bool,
+    // Размер страницы новой базы. У существующей всегда берется из meta.
+    pub page_size: usize,
+    // Размер нового файла в байтах. Меньше, чем нужно под данные, файл не бывает.
+    pub initial_size: u64,
+    // Размер отображения в память. Дальше него база расти не может.
+    pub max_size: usize,
+    pub sync_mode: SyncMode,
+    // Аналог MAP_POPULATE: прочитать весь файл в память сразу при открытии
+    pub mmap_populate: bool,
+    // Сколько ждать блокировку файла, если ее держит другой процесс. None - ждать сколько угодно.
+    pub lock_timeout: Option<Duration>,
+    // Проверять контрольную сумму meta при открытии
+    pub verify_checksums: bool,
+    // До какой доли страницы заполняется левая часть при делении ноды (MIN..=MAX_FILL_PERCENT)
+    pub fill_percent: f64,
+    // Деление по правому краю: если ключ добавлен в конец самой правой ноды дерева, левая
+    // часть заполняется целиком, а не до fill_percent. Возрастающие ключи (время, счетчики)
+    // тогда дают почти полные страницы вместо наполовину пустых.
+    pub right_edge_split: bool,
+    // Сжатие префиксов: общий префикс ключей страницы пишется на нее один раз (PAGE_PREFIX).
+    // Читаются такие страницы всегда, независимо от этой настройки.
+    pub prefix_compression: bool,
+    // Кодек, которым сжимаются новые значения (см. codec). Значения, сжатые встроенными
+    // кодеками, читаются всегда, сжатые этим - только если он задан.
+    pub codec: Option<Arc<dyn Codec>>,
+    // Шифр страниц и ключ к нему (см. cipher). Новые страницы шифруются, если шифр задан;
+    // зашифрованные страницы без шифра не читаются, незашифрованные читаются всегда.
+    pub cipher: Option<Arc<dyn PageCipher>>,
+    pub cipher_key: CipherKey,
+    // Порядок ключей (см. comparator), None - побайтовый. Задается при создании базы и
+    // сохраняется в meta; открыть базу можно только с тем же компаратором.
+    pub comparator: Option<Arc<dyn Comparator>>,
+    // Несколько значений на ключ, как DUPSORT в LMDB: значения ключа хранятся отсортированными,
+    // put добавляет значение, а не заменяет. Задается при создании базы и сохраняется в meta.
+    // Значения лежат в ключах дерева (см. DB::compare) и кодеком не сжимаются.
+    pub dupsort: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            read_only: false,
+            create_if_missing: false,
+            page_size: page_size::get(),
+            initial_size: 0,
+            max_size: DEFAULT_MAX_SIZE,
+            sync_mode: SyncMode::Full,
+            mmap_populate: false,
+            lock_timeout: None,
+            verify_checksums: true,
+            fill_percent: DEFAULT_FILL_PERCENT,
+            right_edge_split: true,
+            prefix_compression: false,
+            codec: None,
+            cipher: None,
+            cipher_key: CipherKey::default(),
+            comparator: None,
+            dupsort: false,
+        }
+    }
+}
+
+impl Options {
+    // options с настройками, которые записаны в meta файла path: флаг dupsort и компаратор,
+    // который выбирается среди встроенных по id. Так утилиты открывают базу, не зная, как ее
+    // создали. Свой компаратор по id не восстановить - это ошибка.
+    pub fn from_meta(path: &str, options: Options) -> io::Result<Options> {
+        let f = File::open(path)?;
+        let mmap_data = unsafe { memmap::MmapOptions::new().map(&f)? };
+        let meta = read_meta(&mmap_data, None, options.verify_checksums)?;
+
+        let id = meta.comparator.get();
+        let comparator = comparator::builtin(id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("database is sorted by custom comparator {}", id)))?;
+
+        Ok(Options { comparator: Some(comparator), dupsort: meta.flags.get() & META_DUPSORT != 0, ..options })
+    }
+
+    // Размер страницы должен быть таким, какой примет Meta::validate
+    pub fn validate(&self) -> io::Result<()> {
+        if !self.page_size.is_power_of_two() || !(MIN_PAGE_SIZE as usize..=MAX_PAGE_SIZE as usize).contains(&self.page_size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid page size {}", self.page_size)));
+        }
+
+        if !(MIN_FILL_PERCENT..=MAX_FILL_PERCENT).contains(&self.fill_percent) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid fill percent {}", self.fill_percent)));
+        }
+
+        if self.codec.as_ref().is_some_and(|x| x.id() == CODEC_NONE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("codec id {} is reserved", CODEC_NONE)));
+        }
+
+        if self.cipher.is_some() && self.cipher_key.0.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cipher is set without a key".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+// Кеш расшифрованных страниц ограниченного размера: когда он полон, вытесняется страница,
+// попавшая в него раньше всех. Страница отдается как Arc, поэтому вытеснение не трогает
+// тех, кто ее еще читает.
+#[derive(Default)]
+struct DecryptedCache {
+    pages: HashMap<PageId, Arc<[u8]>>,
+    order: VecDeque<PageId>,
+}
+
+impl DecryptedCache {
+    fn get(&self, id: PageId) -> Option<Arc<[u8]>> {
+        self.pages.get(&id).cloned()
+    }
+
+    fn insert(&mut self, id: PageId, buf: Arc<[u8]>) {
+        if self.pages.insert(id, buf).is_some() {
+            return;
+        }
+
+        self.order.push_back(id);
+        if self.order.len() > DECRYPTED_CACHE_PAGES {
+            let oldest = self.order.pop_front().unwrap();
+            self.pages.remove(&oldest);
+        }
+    }
+}
+
+pub struct DB {
+    f: File,
+    mmap_data: Mmap,
+    // Сколько байт mmap лежит в файле. Файл меняет только коммит этой базы (другие
+    // процессы не пишут: файл заблокирован), поэтому длину не нужно спрашивать у ОС на
+    // каждом чтении страницы: коммит увеличивает ее сам, когда страницы уже записаны.
+    data_len: AtomicUsize,
+    page_size: usize,
+    read_only: bool,
+
+    // Пишущая транзакция может быть только одна
+    writer: Mutex<()>,
+    // Meta-страница перезаписывается на месте, читать ее надо под этой блокировкой
+    meta_lock: Mutex<()>,
+    // Слот текущей meta (см. META_PAGES). Коммит пишет другой слот и только потом
+    // переключает этот.
+    meta_slot: AtomicU64,
+
+    batch: Mutex<Option<Arc<Batch>>>,
+    pub max_batch_size: usize,
+    pub max_batch_delay: Duration,
+
+    pub sync_mode: SyncMode,
+    // См. Options::fill_percent, Options::right_edge_split, Options::prefix_compression
+    // и Options::codec
+    pub fill_percent: f64,
+    pub right_edge_split: bool,
+    pub prefix_compression: bool,
+    pub codec: Option<Arc<dyn Codec>>,
+
+    cipher: Option<Arc<dyn PageCipher>>,
+    cipher_key: CipherKey,
+    // Недавно расшифрованные страницы. Записанная страница в файле больше не меняется
+    // (страницы не переиспользуются), поэтому копию можно отдавать повторно.
+    decrypted: Mutex<DecryptedCache>,
+
+    comparator: Arc<dyn Comparator>,
+    dupsort: bool,
+}
+
+impl DB {
+    pub fn open(path: &str) -> io::Result<DB> {
+        DB::open_with(path, Options::default())
+    }
+
+    // Файл может быть чужим (например, бэкап), поэтому meta проверяется до того, как
+    // ей поверить: битый файл - это ошибка открытия, а не паника при чтении.
+    pub fn open_with(path: &str, options: Options) -> io::Result<DB> {
+        options.validate()?;
+
+        if options.create_if_missing && !options.read_only && !Path::new(path).exists() {
+            return DB::create(path, options);
+        }
+
+        let f = OpenOptions::new().read(true).write(!options.read_only).open(path)?;
+        lock(&f, options.read_only, options.lock_timeout)?;
+
+        let file_len = f.metadata()?.len();
+        let map_size = std::cmp::max(file_len as usize, options.max_size);
+
+        let mmap_data = unsafe {
+            memmap::MmapOptions::new().len(map_size).
+                offset(0).map(&f)?
+        };
+
+        let meta = read_meta(&mmap_data[..std::cmp::min(file_len as usize, map_size)], None, options.verify_checksums)?;
+
+        let comparator = comparator::resolve(options.comparator.as_ref());
+        if comparator.id() != meta.comparator.get() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("database is sorted by comparator {}, not {}", meta.comparator.get(), comparator.id())));
+        }
+
+        let dupsort = meta.flags.get() & META_DUPSORT != 0;
+        if dupsort != options.dupsort {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("database is {}dupsort", if dupsort { "" } else { "not " })));
+        }
+
+        if options.mmap_populate {
+            prefault(&mmap_data[..file_len as usize]);
+        }
+
+        Ok(DB {
+            f,
+            data_len: AtomicUsize::new(std::cmp::min(file_len as usize, map_size)),
+            mmap_data,
+            page_size: meta.page_size.get() as usize,
+            read_only: options.read_only,
+            writer: Mutex::new(()),
+            meta_lock: Mutex::new(()),
+            meta_slot: AtomicU64::new(meta.slot()),
+            batch: Mutex::new(None),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_batch_delay: DEFAULT_MAX_BATCH_DELAY,
+            sync_mode: options.sync_mode,
+            fill_percent: options.fill_percent,
+            right_edge_split: options.right_edge_split,
+            prefix_compression: options.prefix_compression,
+            codec: options.codec,
+            cipher: options.cipher,
+            cipher_key: options.cipher_key,
+            decrypted: Mutex::new(DecryptedCache::default()),
+            comparator,
+            dupsort,
+        })
+    }
+
+    // Новая пустая база: два слота meta, пустой freelist и корневой лист без ключей.
+    // Существующий файл не перезаписывается - это ошибка AlreadyExists.
+    pub fn create(path: &str, options: Options) -> io::Result<DB> {
+        options.validate()?;
+
+        let page_size = options.page_size;
+        let (freelist_page, root_page, page_count) = (META_PAGES, META_PAGES + 1, META_PAGES + 2);
+
+        let f = OpenOptions::new().write(true).create_new(true).open(path)?;
+        f.set_len(std::cmp::max(options.initial_size / page_size as u64, page_count) * page_size as u64)?;
+
+        let (cipher, key) = (options.cipher.as_deref(), &options.cipher_key.0);
+        let overhead = cipher::page_overhead(cipher);
+        let salt = cipher::salt(cipher)?;
+        let mut buffer = freelist_to_page_bytes(freelist_page, &[], page_size, overhead);
+        buffer.resize(2 * page_size, 0);
+        node::Node::new(true, vec![]).serialize(root_page, 0, &mut buffer[page_size..]);
+        cipher::seal(cipher, key, &mut buffer[..page_size], salt);
+        cipher::seal(cipher, key, &mut buffer[page_size..], salt);
+        f.write_all_at(buffer.as_slice(), freelist_page * page_size as u64)?;
+
+        // Как и при коммите: meta пишется только после того, как страницы дошли до диска
+        options.sync_mode.sync(&f)?;
+
+        let meta = Meta {
+            magic: Le32::new(MAGIC),
+            version: Le32::new(VERSION),
+            page_size: Le32::new(page_size as u32),
+            root_page: Le32::new(root_page as u32),
+            page_count: Le32::new(page_count as u32),
+            freelist_page: Le32::new(freelist_page as u32),
+            txid: Le64::new(1),
+            comparator: Le32::new(comparator::resolve(options.comparator.as_ref()).id()),
+            flags: Le32::new(if options.dupsort { META_DUPSORT } else { 0 }),
+        };
+        f.write_all_at(meta.to_file_bytes(page_size).as_slice(), 0)?;
+        options.sync_mode.sync(&f)?;
+        drop(f);
+
+        DB::open_with(path, options)
+    }
+
+    // Страница вместе с overflow-страницами. Ошибка, если страница не влезает в файл.
+    pub fn page(&self, id: PageId) -> io::Result<PageRef<'_>> {
+        let data = self.data();
+        let out_of_bounds = || invalid_data(format!("page {} is out of file bounds ({} bytes)", id, data.len()));
+
+        let offset = (id as usize).checked_mul(self.page_size)
+            .filter(|&x| x < data.len())
+            .ok_or_else(out_of_bounds)?;
+        let page = PageRef::new(&data[offset..])?;
+
+        let len = (page.page_overflow_count.get() as usize + 1) * self.page_size;
+        let page = PageRef::new(data.get(offset..offset + len).ok_or_else(out_of_bounds)?)?;
+        if !page.is_encrypted() {
+            return Ok(page);
+        }
+
+        let cipher = self.cipher.as_deref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, format!("page {} is encrypted and no cipher is set", id)))?;
+
+        if let Some(buf) = self.decrypted.lock().unwrap().get(id) {
+            return PageRef::owned(buf);
+        }
+
+        let buf: Arc<[u8]> = cipher::open(cipher, &self.cipher_key.0, id, page.bytes())?.into();
+        self.decrypted.lock().unwrap().insert(id, buf.clone());
+        PageRef::owned(buf)
+    }
+
+    // Порядок ключей базы
+    pub fn comparator(&self) -> &dyn Comparator {
+        self.comparator.as_ref()
+    }
+
+    pub fn dupsort(&self) -> bool {
+        self.dupsort
+    }
+
+    // Порядок ключей дерева. Ключ дерева dupsort-базы - ключ и значение подряд (см. dup_key):
+    // ключи сравниваются компаратором, значения одного ключа - побайтово. Голый ключ
+    // оказывается раньше всех своих значений, поэтому поиск по нему встает на первое.
+    pub(crate) fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        if !self.dupsort {
+            return self.comparator.compare(a, b);
+        }
+
+        let (a_key, a_val) = a.split_at(std::cmp::min(a.len(), size_of::<Key>()));
+        let (b_key, b_val) = b.split_at(std::cmp::min(b.len(), size_of::<Key>()));
+        self.comparator.compare(a_key, b_key).then_with(|| a_val.cmp(b_val))
+    }
+
+    // Разделитель между соседними нодами (см. Comparator::separator). Укороченный разделитель
+    // компаратора годится для ключей dupsort-базы, только если он побайтовый: тогда пары
+    // ключ-значение сортируются так же, как их байты.
+    pub(crate) fn separator<'k>(&self, last: &[u8], first: &'k [u8]) -> &'k [u8] {
+        if self.dupsort && self.comparator.id() != COMPARATOR_BYTES {
+            return first;
+        }
+
+        self.comparator.separator(last, first)
+    }
+
+    // Ключ дерева для пары ключ-значение dupsort-базы
+    fn dup_key(&self, key: &Key, val: &[u8]) -> io::Result<Vec<u8>> {
+        if !self.dupsort {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "database is not dupsort".to_string()));
+        }
+
+        let mut ret = key.to_vec();
+        ret.extend_from_slice(val);
+        Ok(ret)
+    }
+
+    // Сколько байт в конце страницы писатели оставляют под шифрование (см. cipher::seal)
+    pub(crate) fn page_overhead(&self) -> usize {
+        cipher::page_overhead(self.cipher.as_deref())
+    }
+
+    // Шифрует записываемую страницу, если задан шифр
+    pub(crate) fn seal(&self, buf: &mut [u8], salt: u64) {
+        cipher::seal(self.cipher.as_deref(), &self.cipher_key.0, buf, salt)
+    }
+
+    // Соль для страниц одной записи (см. cipher::salt)
+    pub(crate) fn salt(&self) -> io::Result<u64> {
+        cipher::salt(self.cipher.as_deref())
+    }
+
+    // Часть mmap, за которой действительно есть файл. Отображение больше файла, и чтение
+    // за его концом - это SIGBUS, а не ошибка.
+    fn data(&self) -> &[u8] {
+        &self.mmap_data[..self.data_len.load(atomic::Ordering::Acquire)]
+    }
+
+    pub(crate) fn file_size(&self) -> io::Result<u64> {
+        Ok(self.f.metadata()?.len())
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    // Страница целиком, вместе с overflow-страницами
+    pub fn page_bytes(&self, id: PageId) -> io::Result<Cow<'_, [u8]>> {
+        Ok(self.page(id)?.into_bytes())
+    }
+
+    // Свободные страницы по последней закоммиченной meta
+    pub fn freelist(&self) -> io::Result<Vec<PageId>> {
+        self.read_freelist(&self.meta())
+    }
+
+    fn read_freelist(&self, meta: &Meta) -> io::Result<Vec<PageId>> {
+        match meta.freelist_page.get() {
+            0 => Ok(vec![]),
+            id => self.page(id as PageId)?.freelist(),
+        }
+    }
+
+    // Копия последней закоммиченной meta, которую не порвет одновременный коммит. Читающая
+    // операция берет ее один раз и дальше работает только с ней: root_page и page_count
+    // из разных коммитов не смешиваются. Meta-страница есть всегда: open прочитал ее,
+    // а файл открытой базы не уменьшается.
+    pub fn meta(&self) -> Meta {
+        let _lock = self.meta_lock.lock().unwrap();
+        let offset = self.meta_slot.load(atomic::Ordering::Acquire) as usize * self.page_size;
+        from_bytes(&self.mmap_data[offset + size_of::<PageHeader>()..])
+    }
+
+    // Ищет листовой элемент, в котором должен (но не обязан, если его вообще не добавляли)
+    // располагаться нужный ключ
+    fn _tree_search_page(&self, k: &[u8], page_id: PageId) -> io::Result<PageId> {
+        let page = self.page(page_id)?;
+        let inodes = page.branch_inodes()?;
+        if inodes.is_empty() {
+            return Err(invalid_data(format!("branch page {} has no inodes", page_id)));
+        }
+
+        let mut ret_idx = inodes.len() - 1;
+        for (idx, inode) in inodes.iter().enumerate() {
+            trace!("page_id={} key={}", inode.page_id, key_to_str(&inode.key));
+
+            if self.compare(&inode.key, k) == Ordering::Greater {
+                trace!("Desired key found. Current page processing stopped");
+                // Ключ меньше всех ключей страницы - идем в самого левого потомка
+                ret_idx = idx.saturating_sub(1);
+                break;
+            }
+        }
+
+        Ok(inodes[ret_idx].page_id)
+    }
+
+    pub fn search(&self, meta: &Meta, k: Key) -> io::Result<PageId> {
+        let mut page_id = meta.root_page.get() as PageId;
+
+        // Глубже, чем страниц в файле, дерево быть не может: иначе в нем цикл
+        for _ in 0..=meta.page_count.get() {
+            let page = self.page(page_id)?;
+            trace!("Search on page: {:?}", page);
+            if page.is_leaf() {
+                return Ok(page_id);
+            }
+
+            page_id = self._tree_search_page(&k, page_id)?;
+        }
+
+        Err(invalid_data(format!("cycle in tree at page {}", page_id)))
+    }
+
+    // Несжатое значение указывает прямо в mmap, сжатое распаковывается в хип. В dupsort-базе -
+    // первое значение ключа.
+    pub fn get(&self, k: Key) -> io::Result<Option<Cow<'_, [u8]>>> {
+        trace!("Search \"{}\"", key_to_str(&k));
+        if self.dupsort {
+            return match self.cursor().seek(&k)? {
+                Some((key, value)) if self.comparator.compare(&key, &k) == Ordering::Equal => Ok(Some(value)),
+                _ => Ok(None),
+            };
+        }
+
+        let page_id = self.search(&self.meta(), k)?;
+
+        self.page(page_id)?.leaf_inodes()?
+            .into_iter()
+            .find(|inode| self.compare(&inode.key, &k) == Ordering::Equal)
+            .map(|x| self.decode(x.codec, x.value))
+            .transpose()
+    }
+
+    // Все значения ключа по порядку. Без dupsort значение у ключа одно, или его нет.
+    pub fn get_all(&self, k: Key) -> io::Result<Vec<Cow<'_, [u8]>>> {
+        let mut ret = vec![];
+        let mut cursor = self.cursor();
+        let mut item = cursor.seek(&k)?;
+        while let Some((key, value)) = item {
+            if self.comparator.compare(&key, &k) != Ordering::Equal {
+                break;
+            }
+            ret.push(value);
+            item = cursor.next()?;
+        }
+
+        Ok(ret)
+    }
+
+    // Значение со страницы в исходном виде (см. codec::decode)
+    pub fn decode<'a>(&self, codec: u8, value: Cow<'a, [u8]>) -> io::Result<Cow<'a, [u8]>> {
+        match value {
+            Cow::Borrowed(value) => codec::decode(self.codec.as_deref(), codec, value),
+            Cow::Owned(value) if codec == CODEC_NONE => Ok(Cow::Owned(value)),
+            Cow::Owned(value) => Ok(Cow::Owned(codec::decode(self.codec.as_deref(), codec, &value)?.into_owned())),
+        }
+    }
+
+    // Выполняет f в читающей транзакции. Транзакция видит снимок базы на момент своего
+    // начала и не мешает пишущим транзакциям.
+    pub fn view<F>(&self, f: F) -> io::Result<()> where F: FnOnce(&Tx) -> io::Result<()> {
+        let tx = Tx::new(self);
+        f(&tx)
+    }
+
+    // Выполняет f в пишущей транзакции. Если f вернула ошибку, транзакция откатывается
+    // (изменения живут только в node_cache и просто выбрасываются).
+    pub fn update<F>(&self, f: F) -> io::Result<()> where F: FnOnce(&mut Tx) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "database is opened read-only"));
+        }
+
+        // Паника в f только отравляет мьютекс: транзакция жила в node_cache и выброшена вместе
+        // со стеком, файл не тронут, поэтому следующие update могут спокойно писать дальше
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let mut tx = Tx::new(self);
+        f(&mut tx)?;
+        tx.commit()
+    }
+
+    // Аналог Batch из BoltDB: вызовы из разных потоков копятся max_batch_delay (или пока их
+    // не наберется max_batch_size) и выполняются в одной транзакции. Если одна из функций
+    // вернула ошибку, она убирается из пачки и позже выполняется отдельно через update,
+    // а остальные перезапускаются. Поэтому f может вызываться несколько раз и не должна
+    // иметь побочных эффектов вне транзакции.
+    //
+    // Пачку выполняет поток, который ее открыл (лидер), остальные ждут результата.
+    pub fn batch<F>(&self, f: F) -> io::Result<()>
+        where F: Fn(&mut Tx) -> io::Result<()> + Send + Sync + 'static {
+        let f: Arc<BatchFn> = Arc::new(f);
+        let (done, result) = channel();
+
+        let leader = {
+            let mut current = self.batch.lock().unwrap();
+
+            let (b, is_leader) = match current.as_ref() {
+                Some(b) if b.calls.lock().unwrap().len() < self.max_batch_size => (b.clone(), false),
+                _ => {
+                    let b = Arc::new(Batch::new());
+                    *current = Some(b.clone());
+                    (b, true)
+                }
+            };
+
+            let mut calls = b.calls.lock().unwrap();
+            calls.push(Call { f: f.clone(), done });
+            if calls.len() >= self.max_batch_size {
+                b.full.notify_one();
+            }
+            drop(calls);
+
+            if is_leader { Some(b) } else { None }
+        };
+
+        if let Some(b) = leader {
+            self.run_batch(&b);
+        }
+
+        match result.recv().expect("Batch dropped without result") {
+            CallResult::Done(ret) => ret,
+            CallResult::TrySolo => self.update(|tx| f(tx)),
+        }
+    }
+
+    fn run_batch(&self, b: &Arc<Batch>) {
+        {
+            let deadline = Instant::now() + self.max_batch_delay;
+            let mut calls = b.calls.lock().unwrap();
+            while calls.len() < self.max_batch_size {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+
+                calls = b.full.wait_timeout(calls, deadline - now).unwrap().0;
+            }
+        }
+
+        // Отцепляем пачку от DB, новые вызовы пойдут уже в следующую
+        {
+            let mut current = self.batch.lock().unwrap();
+            if current.as_ref().is_some_and(|x| Arc::ptr_eq(x, b)) {
+                *current = None;
+            }
+        }
+
+        let mut calls = mem::take(&mut *b.calls.lock().unwrap());
+
+        while !calls.is_empty() {
+            let mut failed_idx = None;
+            let ret = self.update(|tx| {
+                for (idx, call) in calls.iter().enumerate() {
+                    let ret = panic::catch_unwind(AssertUnwindSafe(|| (call.f)(tx)))
+                        .unwrap_or_else(|_| Err(io::Error::other("batch function panicked")));
+
+                    if ret.is_err() {
+                        failed_idx = Some(idx);
+                        return ret;
+                    }
+                }
+
+                Ok(())
+            });
+
+            if let Some(idx) = failed_idx {
+                let call = calls.remove(idx);
+                let _ = call.done.send(CallResult::TrySolo);
+                continue;
+            }
+
+            for call in calls.drain(..) {
+                let ret = match &ret {
+                    Ok(()) => Ok(()),
+                    Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                };
+
+                let _ = call.done.send(CallResult::Done(ret));
+            }
+        }
+    }
+
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor::new(self, self.meta())
+    }
+
+    pub fn stats(&self) -> io::Result<Stats> {
+        let meta = self.meta();
+        stats::collect(self, meta.root_page.get() as PageId, meta.freelist_page.get() as PageId)
+    }
+
+    // Принудительно сбрасывает файл на диск. Нужен в режиме SyncMode::None, когда
+    // коммиты сами sync не делают.
+    pub fn sync(&self) -> io::Result<()> {
+        self.f.sync_all()
+    }
+}
+
+// memmap не умеет MAP_POPULATE, поэтому просто читаем по байту с каждой страницы
+fn prefault(data: &[u8]) {
+    for offset in (0..data.len()).step_by(page_size::get()) {
+        std::hint::black_box(data[offset]);
+    }
+}
+
+// Пишущий держит эксклюзивную блокировку файла, читающие - разделяемую: два процесса,
+// пишущих в один файл, перетрут страницы друг друга. Блокировка снимается вместе с закрытием f.
+fn lock(f: &File, shared: bool, timeout: Option<Duration>) -> io::Result<()> {
+    let deadline = timeout.map(|x| Instant::now() + x);
+
+    loop {
+        let ret = if shared { f.try_lock_shared() } else { f.try_lock() };
+        match ret {
+            Ok(()) => return Ok(()),
+            Err(TryLockError::Error(e)) => return Err(e),
+            Err(TryLockError::WouldBlock) => {}
+        }
+
+        if deadline.is_some_and(|x| Instant::now() >= x) {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "database is locked by another process"));
+        }
+
+        thread::sleep(LOCK_RETRY_INTERVAL);
+    }
+}
+
+type BatchFn = dyn Fn(&mut Tx) -> io::Result<()> + Send + Sync;
+
+enum CallResult {
+    Done(io::Result<()>),
+    // Функция сломала общую транзакцию, вызывающий поток должен выполнить ее сам
+    TrySolo,
+}
+
+struct Call {
+    f: Arc<BatchFn>,
+    done: Sender<CallResult>,
+}
+
+struct Batch {
+    calls: Mutex<Vec<Call>>,
+    full: Condvar,
+}
+
+impl Batch {
+    fn new() -> Batch {
+        Batch {
+            calls: Mutex::new(vec![]),
+            full: Condvar::new(),
+        }
+    }
+}
+
+pub struct Tx<'a> {
+    db: &'a DB,
+    // Снимок meta на момент начала транзакции. Коммиты только дописывают страницы после
+    // meta.page_count, поэтому все страницы снимка остаются неизменными.
+    meta: Meta,
+    node_cache: node::NodeCache<'a>
+}
+
+// 1. При чтении - читаются данные из страницы. Страница при этом не должна удаляться
+// 2. При записи:
+//      1. Запись осуществляется всегда в листовой узел, который занимает не менее одной страницы;
+//      2. Перезаписывается всегда весь лист (все его страницы) целиком;
+//      3. Поэтому при обновлении листа мы:
+//           - Создаем новую ноду; Новая нода должна содержать ссылки на старые данные из mmap
+//              (чтоб потом скопировать) и ссылки на новые данные (из heap). Заранее выделить страницы
+//              под лист мы не можем, т.к. не знаем сколько он впоследствии будет занимать места;
+//
+// Где хранить новые key и value, кто их owner?
+//    - В Tx, а ссылки на эти данные в INode;
+//    - Только в inode;
+//
+// Node и INode - промежуточные структуры данных, которые связывают:
+//  - Runtime данные (изменение элементов дерева); Pipeline: node -> page -> file
+//  - Старые данные (ссылки на данные из mmap), чтобы избежать лишних копирований данных
+//    Вместо (mmap -> node -> page -> file) у нас (mmap -> (-> &node (link to mmap)->) -> page -> file)
+impl<'a> Tx<'a> {
+    pub fn new(db: &DB) -> Tx<'_> {
+        Tx {
+            db,
+            meta: db.meta(),
+            node_cache: node::NodeCache::new(),
+        }
+    }
+
+    // Читает в node_cache все ноды от корня до листа, в котором должен лежать ключ.
+    // Коммит переписывает их все (copy-on-write), поэтому нужен весь путь, а не только лист.
+    fn read_path(&mut self, key: &[u8]) -> io::Result<usize> {
+        let mut page_id = self.meta.root_page.get() as PageId;
+        let mut parent_id = None;
+
+        for _ in 0..=self.meta.page_count.get() {
+            let node_id = self.node_cache.read_node(self.db.page(page_id)?, parent_id)?;
+            if self.node_cache.nodes[node_id].is_leaf {
+                return Ok(node_id);
+            }
+
+            parent_id = Some(node_id);
+            page_id = self.db._tree_search_page(key, page_id)?;
+        }
+
+        Err(invalid_data(format!("cycle in tree at page {}", page_id)))
+    }
+
+    pub fn db(&self) -> &'a DB {
+        self.db
+    }
+
+    // Курсор по снимку транзакции вместе с ее незакоммиченными put и delete
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor::with_nodes(self.db, self.meta, &self.node_cache)
+    }
+
+    // Как DB::get, но по снимку транзакции и с ее собственными изменениями
+    pub fn get(&self, k: Key) -> io::Result<Option<Cow<'_, [u8]>>> {
+        match self.cursor().seek(&k)? {
+            Some((key, value)) if self.db.comparator.compare(&key, &k) == Ordering::Equal => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    // В dupsort-базе - то же, что put_dup
+    pub fn put(&mut self, key: Key, val: Vec<u8>) -> io::Result<()> {
+        if self.db.dupsort {
+            return self.put_dup(key, val);
+        }
+
+        let node_id = self.read_path(&key)?;
+        let (codec, val) = codec::encode(self.db.codec.as_deref(), val);
+
+        let db = self.db;
+        let node = &mut self.node_cache.nodes[node_id];
+        let pos = node.inodes.binary_search_by(|x| db.compare(x.key(), &key));
+        match pos {
+            Ok(pos) => {
+                node.inodes[pos].value = HeapValue::Heap(val);
+                node.inodes[pos].codec = codec;
+            },
+            Err(pos) => {
+                node.appended |= pos == node.inodes.len();
+                node.inodes.insert(pos, INode {
+                    key: HeapValue::Heap(Vec::from(key)),
+                    value: HeapValue::Heap(val),
+                    codec,
+                    page_id: None,
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    // Добавляет значение ключу dupsort-базы. Пара хранится ключом дерева с пустым значением,
+    // поэтому повторное добавление той же пары ничего не меняет.
+    pub fn put_dup(&mut self, key: Key, val: Vec<u8>) -> io::Result<()> {
+        let dup_key = self.db.dup_key(&key, &val)?;
+        let node_id = self.read_path(&dup_key)?;
+
+        let db = self.db;
+        let node = &mut self.node_cache.nodes[node_id];
+        if let Err(pos) = node.inodes.binary_search_by(|x| db.compare(x.key(), &dup_key)) {
+            node.appended |= pos == node.inodes.len();
+            node.inodes.insert(pos, INode {
+                key: HeapValue::Heap(dup_key),
+                value: HeapValue::Heap(vec![]),
+                codec: CODEC_NONE,
+                page_id: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Удаляет ключ. false, если ключа не было. Ноды не сливаются: лист, из которого удалили
+    // все ключи, убирается из дерева при коммите. В dupsort-базе значения удаляются по
+    // одному через delete_dup.
+    pub fn delete(&mut self, key: Key) -> io::Result<bool> {
+        if self.db.dupsort {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "database is dupsort, use delete_dup".to_string()));
+        }
+
+        self.remove(&key)
+    }
+
+    // Удаляет одно значение ключа dupsort-базы. false, если такой пары не было.
+    pub fn delete_dup(&mut self, key: Key, val: &[u8]) -> io::Result<bool> {
+        let dup_key = self.db.dup_key(&key, val)?;
+        self.remove(&dup_key)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> io::Result<bool> {
+        let node_id = self.read_path(key)?;
+
+        let db = self.db;
+        let node = &mut self.node_cache.nodes[node_id];
+        match node.inodes.binary_search_by(|x| db.compare(x.key(), key)) {
+            Ok(pos) => {
+                node.inodes.remove(pos);
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    // Насколько заполнять левые части при делении ноды. По правому краю делится самая правая
+    // нода дерева: лист - если его последний ключ добавлен в этой транзакции, ветка - всегда
+    // (какие разделители в ней новые, здесь уже не узнать).
+    fn fill_percent(&self, node_id: usize) -> f64 {
+        let nodes = &self.node_cache.nodes;
+        let node = &nodes[node_id];
+
+        let appended = !node.is_leaf || node.appended;
+        if !self.db.right_edge_split || !appended {
+            return self.db.fill_percent;
+        }
+
+        // Родитель ссылается на ноду по ее старой странице, пока сама нода не записана
+        let mut node = node;
+        while let Some(parent_id) = node.parent_id {
+            let parent = &nodes[parent_id];
+            if parent.inodes.last().and_then(|x| x.page_id) != Some(node.page_id) {
+                return self.db.fill_percent;
+            }
+            node = parent;
+        }
+
+        MAX_FILL_PERCENT
+    }
+
+    // Пишет ноду в новые страницы в конце файла. Возвращает ссылку на нее для родителя:
+    // первый ключ и номер страницы.
+    fn write_node(&self, node: &node::Node<'a>, salt: u64, page_count: &mut PageId) -> io::Result<INode<'a>> {
+        let page_size = self.db.page_size;
+        let page_num = (node.size() + self.db.page_overhead()).div_ceil(page_size);
+        let page_id = *page_count;
+        *page_count += page_num as PageId;
+
+        if (*page_count as usize) * page_size > self.db.mmap_data.len() {
+            return Err(io::Error::other("database is full"));
+        }
+
+        let mut buffer = vec![0u8; page_num * page_size];
+        node.serialize(page_id, (page_num - 1) as u32, buffer.as_mut_slice());
+        self.db.seal(&mut buffer, salt);
+        self.db.f.write_all_at(buffer.as_slice(), page_id * page_size as u64)?;
+
+        Ok(INode {
+            key: node.inodes.first().map_or(HeapValue::None, |x| x.key.clone()),
+            value: HeapValue::None,
+            codec: CODEC_NONE,
+            page_id: Some(page_id),
+        })
+    }
+
+    // Свободные страницы по снимку транзакции
+    pub fn freelist(&self) -> io::Result<Vec<PageId>> {
+        self.db.read_freelist(&self.meta)
+    }
+
+    // Страница и все ее overflow-страницы
+    fn page_range(&self, page_id: PageId) -> io::Result<RangeInclusive<PageId>> {
+        let page = self.db.page(page_id)?;
+        Ok(page_id..=page_id + page.page_overflow_count.get() as PageId)
+    }
+
+    // Статистика по страницам, которые видит транзакция. Незакоммиченные изменения
+    // живут только в node_cache и в статистику не попадают.
+    pub fn stats(&self) -> io::Result<Stats> {
+        stats::collect(self.db, self.meta.root_page.get() as PageId, self.meta.freelist_page.get() as PageId)
+    }
+
+    // Пишет в w полный образ базы по снимку транзакции: meta снимка и все страницы до
+    // meta.page_count (дерево вместе со страницами, брошенными коммитами). Пишущие
+    // транзакции при этом продолжают работать: они трогают только страницы после
+    // page_count и meta-страницы, а их мы берем из снимка. Возвращает число записанных байт.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<u64> {
+        let page_size = self.db.page_size;
+
+        let meta_pages = self.meta.to_file_bytes(page_size);
+        w.write_all(meta_pages.as_slice())?;
+
+        let len = self.meta.page_count.get() as usize * page_size;
+        let data = self.db.data();
+        w.write_all(data.get(meta_pages.len()..len)
+            .ok_or_else(|| invalid_data(format!("page_count {} exceeds file size {}", self.meta.page_count.get(), data.len())))?)?;
+        w.flush()?;
+
+        Ok(len as u64)
+    }
+
+    // Горячий бэкап в файл path (файл перезаписывается)
+    pub fn copy_file(&self, path: &str) -> io::Result<u64> {
+        let f = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+        let len = self.write_to(BufWriter::new(&f))?;
+        f.sync_all()?;
+
+        Ok(len)
+    }
+
+    // Все прочитанные ноды пишутся в новые страницы в конце файла, старые страницы не
+    // трогаются (их еще могут читать). Потомок всегда прочитан позже родителя, поэтому
+    // идя с конца мы пишем детей раньше родителей и успеваем обновить ссылки на них.
+    //
+    // Замененные страницы и прежняя страница freelist попадают в новый freelist. Повторно
+    // они пока не используются: читающие транзакции нигде не регистрируются, и нельзя
+    // узнать, что страницу больше никто не читает.
+    pub fn commit(&mut self) -> io::Result<()> {
+        if self.node_cache.nodes.is_empty() {
+            return Ok(());
+        }
+
+        let page_size = self.db.page_size;
+        // Место под шифрование в каждой странице не занимается нодами
+        let capacity = page_size - self.db.page_overhead();
+        let txid = self.meta.txid.get() + 1;
+        let salt = self.db.salt()?;
+        let mut page_count = self.meta.page_count.get() as PageId;
+        let mut root_page = self.meta.root_page.get() as PageId;
+
+        let mut free_pages = self.freelist()?;
+        let freelist_page = self.meta.freelist_page.get() as PageId;
+        if freelist_page != 0 {
+            free_pages.extend(self.page_range(freelist_page)?);
+        }
+
+        for node_id in (0..self.node_cache.nodes.len()).rev() {
+            let fill_percent = self.fill_percent(node_id);
+            let node = &mut self.node_cache.nodes[node_id];
+            let (old_page_id, parent_id, is_leaf) = (node.page_id, node.parent_id, node.is_leaf);
+            let inodes = mem::take(&mut node.inodes);
+            free_pages.extend(self.page_range(old_page_id)?);
+
+            // Нода, из которой удалили все ключи, не пишется: ссылка на нее убирается из
+            // родителя (он обрабатывается позже и тоже может опустеть). Опустевший корень
+            // становится пустым листом.
+            if inodes.is_empty() {
+                if let Some(parent_id) = parent_id {
+                    let parent = &mut self.node_cache.nodes[parent_id];
+                    let idx = parent.inodes.iter()
+                        .position(|x| x.page_id == Some(old_page_id))
+                        .expect("Child page not found in parent inodes");
+                    parent.inodes.remove(idx);
+                    continue;
+                }
+            }
+            let is_leaf = is_leaf || inodes.is_empty();
+
+            // Нода, переросшая страницу, пишется несколькими соседними нодами. Части листа
+            // разделяются разделителем компаратора (для побайтового - самым коротким); у частей
+            // ветки первый ключ - уже разделитель, его и берем.
+            let mut written = vec![];
+            let mut node = node::Node::new(is_leaf, inodes);
+            node.prefix_compression = self.db.prefix_compression;
+            let parts = node.split(capacity, fill_percent);
+            for (idx, part) in parts.iter().enumerate() {
+                let mut inode = self.write_node(part, salt, &mut page_count)?;
+                if is_leaf && idx > 0 {
+                    let last = parts[idx - 1].inodes.last().unwrap().key();
+                    inode.key = HeapValue::Heap(self.db.separator(last, part.inodes[0].key()).to_vec());
+                }
+                written.push(inode);
+            }
+
+            match parent_id {
+                Some(parent_id) => {
+                    let parent = &mut self.node_cache.nodes[parent_id];
+                    let idx = parent.inodes.iter()
+                        .position(|x| x.page_id == Some(old_page_id))
+                        .expect("Child page not found in parent inodes");
+
+                    // Прежний (возможно, укороченный) разделитель остается, пока он не больше
+                    // первого ключа ноды. Больше он бывает только у самого левого потомка, куда
+                    // попадают ключи меньше всех разделителей.
+                    if self.db.compare(parent.inodes[idx].key(), written[0].key()) != Ordering::Greater {
+                        written[0].key = mem::replace(&mut parent.inodes[idx].key, HeapValue::None);
+                    }
+                    parent.inodes.splice(idx..=idx, written);
+                }
+                None => {
+                    // Разделился корень: над ним строятся новые уровни, пока не останется один.
+                    // Новый корень - самая правая нода своего уровня.
+                    let fill_percent = if self.db.right_edge_split { MAX_FILL_PERCENT } else { self.db.fill_percent };
+                    while written.len() > 1 {
+                        let mut level = vec![];
+                        let mut node = node::Node::new(false, written);
+                        node.prefix_compression = self.db.prefix_compression;
+                        for part in node.split(capacity, fill_percent) {
+                            level.push(self.write_node(&part, salt, &mut page_count)?);
+                        }
+                        written = level;
+                    }
+
+                    root_page = written[0].page_id.unwrap();
+                }
+            }
+        }
+
+        free_pages.sort_unstable();
+        free_pages.dedup();
+
+        let freelist_page = page_count;
+        let mut buffer = freelist_to_page_bytes(freelist_page, free_pages.as_slice(), page_size, self.db.page_overhead());
+        page_count += (buffer.len() / page_size) as PageId;
+        if (page_count as usize) * page_size > self.db.mmap_data.len() {
+            return Err(io::Error::other("database is full"));
+        }
+        self.db.seal(&mut buffer, salt);
+        self.db.f.write_all_at(buffer.as_slice(), freelist_page * page_size as u64)?;
+        self.db.data_len.fetch_max(page_count as usize * page_size, atomic::Ordering::Release);
+
+        self.db.sync_mode.sync(&self.db.f)?;
+
+        let meta = Meta {
+            root_page: Le32::new(root_page as u32),
+            page_count: Le32::new(page_count as u32),
+            freelist_page: Le32::new(freelist_page as u32),
+            txid: Le64::new(txid),
+            ..self.meta
+        };
+
+        // Новая meta пишется в другой слот: если запись порвется, при открытии останется
+        // прежняя meta
+        {
+            let _lock = self.db.meta_lock.lock().unwrap();
+            self.db.f.write_all_at(meta.to_page_bytes().as_slice(), meta.slot() * page_size as u64)?;
+            self.db.meta_slot.store(meta.slot(), atomic::Ordering::Release);
+        }
+        self.db.sync_mode.sync(&self.db.f)?;
+
+        self.meta = meta;
+        self.node_cache = node::NodeCache::new();
+        Ok(())
+    }
+}
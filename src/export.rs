@@ -0,0 +1,254 @@
+use std::cmp::Ordering;
+use std::io::{self, BufRead, Write};
+
+use crate::db::DB;
+use crate::types::{bytes_to_key, invalid_data, Key};
+
+// Выгрузка базы в текстовый вид и загрузка обратно. Ключи и значения бинарные,
+// поэтому в файле они всегда закодированы (hex или base64).
+//
+// JSON Lines: по объекту на строку - {"key":"...","value":"..."}
+// CSV: заголовок key,value и далее по паре на строку
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    JsonLines,
+    Csv,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "jsonl" | "json" => Some(Format::JsonLines),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Encoding> {
+        match name {
+            "hex" => Some(Encoding::Hex),
+            "base64" => Some(Encoding::Base64),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, data: &[u8]) -> String {
+        match self {
+            Encoding::Hex => hex_encode(data),
+            Encoding::Base64 => base64_encode(data),
+        }
+    }
+
+    pub fn decode(&self, data: &str) -> io::Result<Vec<u8>> {
+        let ret = match self {
+            Encoding::Hex => hex_decode(data),
+            Encoding::Base64 => base64_decode(data),
+        };
+
+        ret.ok_or_else(|| invalid_data(format!("invalid {:?} string \"{}\"", self, data)))
+    }
+}
+
+// Пишет пары ключ-значение с ключами из [from, to) (границы необязательны). Границы
+// сравниваются компаратором базы с ключами так, как они хранятся (и выгружаются): без
+// выравнивания, поэтому ключ из keys::to_key или из вывода export задает ту же границу.
+// Возвращает количество выгруженных пар.
+pub fn export<W: Write>(db: &DB, mut w: W, format: Format, encoding: Encoding,
+                        from: Option<&[u8]>, to: Option<&[u8]>) -> io::Result<u64> {
+    if format == Format::Csv {
+        writeln!(w, "key,value")?;
+    }
+
+    let mut cursor = db.cursor();
+    let mut item = match from {
+        Some(from) => cursor.seek(from)?,
+        None => cursor.first()?,
+    };
+
+    let mut count = 0;
+    while let Some((key, value)) = item {
+        if to.is_some_and(|to| db.comparator().compare(&key, to) != Ordering::Less) {
+            break;
+        }
+
+        let (key, value) = (encoding.encode(&key), encoding.encode(&value));
+        match format {
+            Format::JsonLines => writeln!(w, "{{\"key\":\"{}\",\"value\":\"{}\"}}", key, value)?,
+            Format::Csv => writeln!(w, "{},{}", key, value)?,
+        }
+
+        count += 1;
+        item = cursor.next()?;
+    }
+
+    w.flush()?;
+    Ok(count)
+}
+
+// Загружает пары из r одной пишущей транзакцией: либо загружается весь файл, либо
+// (при любой ошибке разбора) ничего. Возвращает количество загруженных пар.
+pub fn import<R: BufRead>(db: &DB, r: R, format: Format, encoding: Encoding) -> io::Result<u64> {
+    let mut count = 0;
+
+    db.update(|tx| {
+        for (idx, line) in r.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || (format == Format::Csv && idx == 0 && line == "key,value") {
+                continue;
+            }
+
+            let (key, value) = match format {
+                Format::JsonLines => parse_json_line(line),
+                Format::Csv => parse_csv_line(line),
+            }.ok_or_else(|| invalid_data(format!("line {}: can't parse \"{}\"", idx + 1, line)))?;
+
+            let line_error = |e: io::Error| invalid_data(format!("line {}: {}", idx + 1, e));
+            let key = encoding.decode(&key).and_then(|x| import_key(&x)).map_err(line_error)?;
+            tx.put(key, encoding.decode(&value).map_err(line_error)?)?;
+            count += 1;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(count)
+}
+
+// Ключи в базе фиксированной длины; более короткие в загружаемом файле выравниваются как
+// в str_to_key. Export выгружает ключи целиком, поэтому его вывод загружается как есть.
+fn import_key(data: &[u8]) -> io::Result<Key> {
+    bytes_to_key(data).ok_or_else(|| invalid_data(format!("key of {} bytes is too long", data.len())))
+}
+
+fn parse_csv_line(line: &str) -> Option<(String, String)> {
+    let mut fields = line.split(',').map(|x| x.trim().trim_matches('"').to_string());
+    let key = fields.next()?;
+    let value = fields.next()?;
+
+    if fields.next().is_some() {
+        return None;
+    }
+
+    Some((key, value))
+}
+
+// Разбирает только то, что пишет export: плоский объект со строковыми полями key и value
+fn parse_json_line(line: &str) -> Option<(String, String)> {
+    let mut rest = line.strip_prefix('{')?.strip_suffix('}')?.trim();
+    let (mut key, mut value) = (None, None);
+
+    while !rest.is_empty() {
+        let (name, tail) = parse_json_string(rest)?;
+        let tail = tail.trim_start().strip_prefix(':')?.trim_start();
+        let (field, tail) = parse_json_string(tail)?;
+
+        match name.as_str() {
+            "key" => key = Some(field),
+            "value" => value = Some(field),
+            _ => {}
+        }
+
+        rest = tail.trim_start();
+        if let Some(tail) = rest.strip_prefix(',') {
+            rest = tail.trim_start();
+        } else if !rest.is_empty() {
+            return None;
+        }
+    }
+
+    Some((key?, value?))
+}
+
+fn parse_json_string(data: &str) -> Option<(String, &str)> {
+    let data = data.strip_prefix('"')?;
+    let mut ret = String::new();
+    let mut chars = data.char_indices();
+
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '"' => return Some((ret, &data[idx + 1..])),
+            '\\' => match chars.next()?.1 {
+                '"' => ret.push('"'),
+                '\\' => ret.push('\\'),
+                '/' => ret.push('/'),
+                _ => return None,
+            },
+            _ => ret.push(c),
+        }
+    }
+
+    None
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn hex_decode(data: &str) -> Option<Vec<u8>> {
+    // from_str_radix пропускает знак "+", поэтому цифры проверяются заранее
+    if !data.len().is_multiple_of(2) || !data.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..data.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&data[i..i + 2], 16).ok())
+        .collect()
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut ret = String::new();
+
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+
+    ret
+}
+
+fn base64_decode(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut ret = vec![];
+    let chunks = data.len() / 4;
+    for (idx, chunk) in data.as_bytes().chunks(4).enumerate() {
+        // "=" бывает только в конце последней четверки
+        let padding = chunk.iter().rev().take_while(|&&x| x == b'=').count();
+        if padding > 2 || (padding > 0 && idx + 1 != chunks) {
+            return None;
+        }
+
+        let mut n = 0u32;
+        for &c in chunk[..4 - padding].iter() {
+            let v = BASE64_ALPHABET.iter().position(|&x| x == c)? as u32;
+            n = n << 6 | v;
+        }
+        n <<= 6 * padding as u32;
+
+        let bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        ret.extend_from_slice(&bytes[..3 - padding]);
+    }
+
+    Some(ret)
+}
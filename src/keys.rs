@@ -0,0 +1,215 @@
+use std::io;
+use std::mem::size_of;
+
+use crate::types::{invalid_data, Key};
+
+// Кодирование ключей с сохранением порядка: байты закодированных значений сравниваются так
+// же, как сами значения. Составной ключ - это части подряд, поэтому кортежи сортируются
+// по первой части, потом по второй и так далее, а кодировка первых частей кортежа -
+// префикс кодировки всего ключа (по нему удобно делать seek для выборки диапазона).
+//
+// Кодировка каждой части не бывает префиксом кодировки другого значения того же типа, поэтому
+// нули, которыми to_key дополняет ключ до размера Key, на порядок не влияют.
+pub trait KeyPart: Sized {
+    fn encode_key(&self, out: &mut Vec<u8>);
+    // Читает часть из начала data и сдвигает data за нее
+    fn decode_key(data: &mut &[u8]) -> io::Result<Self>;
+}
+
+// Ключ базы из значения: кодировка, выровненная влево и дополненная нулями. В отличие от
+// str_to_key выравнивание влево, иначе короткие строки оказались бы перед длинными.
+pub fn to_key<T: KeyPart>(val: &T) -> io::Result<Key> {
+    let data = encode(val);
+    if data.len() > size_of::<Key>() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("encoded key of {} bytes does not fit into {}", data.len(), size_of::<Key>())));
+    }
+
+    let mut key: Key = [0; size_of::<Key>()];
+    key[..data.len()].copy_from_slice(&data);
+    Ok(key)
+}
+
+// Значение из ключа, записанного to_key. После значения могут быть только нули.
+pub fn from_key<T: KeyPart>(key: &[u8]) -> io::Result<T> {
+    let mut data = key;
+    let val = T::decode_key(&mut data)?;
+    if data.iter().any(|&x| x != 0) {
+        return Err(invalid_data(format!("{} extra bytes after the key", data.len())));
+    }
+
+    Ok(val)
+}
+
+// Кодировка без выравнивания, например для префикса составного ключа
+pub fn encode<T: KeyPart>(val: &T) -> Vec<u8> {
+    let mut out = vec![];
+    val.encode_key(&mut out);
+    out
+}
+
+// Обратное к encode: data должна закончиться ровно на конце значения
+pub fn decode<T: KeyPart>(data: &[u8]) -> io::Result<T> {
+    let mut rest = data;
+    let val = T::decode_key(&mut rest)?;
+    if !rest.is_empty() {
+        return Err(invalid_data(format!("{} extra bytes after the key", rest.len())));
+    }
+
+    Ok(val)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(invalid_data(format!("truncated key: {} bytes instead of {}", data.len(), len)));
+    }
+
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn take_u64(data: &mut &[u8]) -> io::Result<u64> {
+    let mut buf = [0; size_of::<u64>()];
+    buf.copy_from_slice(take(data, size_of::<u64>())?);
+    Ok(u64::from_be_bytes(buf))
+}
+
+// Беззнаковые - big-endian, старший байт первым
+pub fn encode_u64(out: &mut Vec<u8>, val: u64) {
+    out.extend_from_slice(&val.to_be_bytes());
+}
+
+pub fn decode_u64(data: &mut &[u8]) -> io::Result<u64> {
+    take_u64(data)
+}
+
+// Знаковые - big-endian с инвертированным знаковым битом: отрицательные идут перед
+// положительными
+pub fn encode_i64(out: &mut Vec<u8>, val: i64) {
+    encode_u64(out, (val as u64) ^ (1 << 63));
+}
+
+pub fn decode_i64(data: &mut &[u8]) -> io::Result<i64> {
+    Ok((take_u64(data)? ^ (1 << 63)) as i64)
+}
+
+// У положительных float инвертируется знаковый бит, у отрицательных - все биты: так
+// -inf < отрицательные < -0.0 < 0.0 < положительные < inf. NaN со знаком минус идут перед
+// -inf, без знака - после inf.
+pub fn encode_f64(out: &mut Vec<u8>, val: f64) {
+    let bits = val.to_bits();
+    let bits = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+    encode_u64(out, bits);
+}
+
+pub fn decode_f64(data: &mut &[u8]) -> io::Result<f64> {
+    let bits = take_u64(data)?;
+    let bits = if bits >> 63 == 1 { bits ^ (1 << 63) } else { !bits };
+    Ok(f64::from_bits(bits))
+}
+
+// Байтовые строки: нулевой байт экранируется как 00 FF, конец строки - 00 00. Так строка
+// не бывает префиксом другой, а более короткая сортируется раньше своих продолжений.
+pub fn encode_bytes(out: &mut Vec<u8>, val: &[u8]) {
+    for &x in val {
+        out.push(x);
+        if x == 0 {
+            out.push(0xFF);
+        }
+    }
+    out.extend_from_slice(&[0, 0]);
+}
+
+pub fn decode_bytes(data: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let mut val = vec![];
+    loop {
+        match take(data, 1)?[0] {
+            0 => match take(data, 1)?[0] {
+                0 => return Ok(val),
+                0xFF => val.push(0),
+                x => return Err(invalid_data(format!("invalid escape 0x00 {:#04x} in key string", x))),
+            },
+            x => val.push(x),
+        }
+    }
+}
+
+pub fn encode_str(out: &mut Vec<u8>, val: &str) {
+    encode_bytes(out, val.as_bytes());
+}
+
+pub fn decode_str(data: &mut &[u8]) -> io::Result<String> {
+    String::from_utf8(decode_bytes(data)?).map_err(|e| invalid_data(format!("key string is not utf-8: {}", e)))
+}
+
+impl KeyPart for u64 {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_u64(out, *self);
+    }
+
+    fn decode_key(data: &mut &[u8]) -> io::Result<Self> {
+        decode_u64(data)
+    }
+}
+
+impl KeyPart for i64 {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_i64(out, *self);
+    }
+
+    fn decode_key(data: &mut &[u8]) -> io::Result<Self> {
+        decode_i64(data)
+    }
+}
+
+impl KeyPart for f64 {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_f64(out, *self);
+    }
+
+    fn decode_key(data: &mut &[u8]) -> io::Result<Self> {
+        decode_f64(data)
+    }
+}
+
+impl KeyPart for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_str(out, self);
+    }
+
+    fn decode_key(data: &mut &[u8]) -> io::Result<Self> {
+        decode_str(data)
+    }
+}
+
+impl KeyPart for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_bytes(out, self);
+    }
+
+    fn decode_key(data: &mut &[u8]) -> io::Result<Self> {
+        decode_bytes(data)
+    }
+}
+
+macro_rules! tuple_key_part {
+    ($($name:ident)+) => {
+        impl<$($name: KeyPart),+> KeyPart for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key(out);)+
+            }
+
+            fn decode_key(data: &mut &[u8]) -> io::Result<Self> {
+                Ok(($($name::decode_key(data)?,)+))
+            }
+        }
+    };
+}
+
+tuple_key_part!(A);
+tuple_key_part!(A B);
+tuple_key_part!(A B C);
+tuple_key_part!(A B C D);
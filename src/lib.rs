@@ -0,0 +1,14 @@
+pub mod types;
+pub mod node;
+pub mod db;
+pub mod compact;
+pub mod check;
+pub mod stats;
+pub mod cursor;
+pub mod export;
+pub mod tree;
+pub mod codec;
+pub mod cipher;
+pub mod keys;
+pub mod comparator;
+pub mod table;
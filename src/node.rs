@@ -0,0 +1,302 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::mem::{self, size_of};
+
+use crate::codec::CODEC_NONE;
+use crate::types::{BranchINodeHeader, LeafInodeHeader, Le16, Le32, Le64, PAGE_BRANCH, PAGE_CODEC, PAGE_LEAF, PAGE_PREFIX, PageHeader,
+                   PageId, PageRef, page_prefix_len, prefix_size, to_bytes};
+
+type NodeId = usize;
+
+
+// Указатель на данные дерева. Может указывать на:
+// 1. memory mapping файла бд;
+// 2. данные аллоцированные в хипе (vector) в ходе транзакции;
+#[derive(Clone)]
+pub enum HeapValue<'a> {
+    MMapped(&'a [u8]),
+    Heap(Vec<u8>),
+    None,
+}
+
+// Ключ или значение из mmap указывает в mmap, собранный из префикса и суффикса или
+// скопированный с расшифрованной страницы - в хип
+impl<'a> From<Cow<'a, [u8]>> for HeapValue<'a> {
+    fn from(val: Cow<'a, [u8]>) -> HeapValue<'a> {
+        match val {
+            Cow::Borrowed(x) => HeapValue::MMapped(x),
+            Cow::Owned(x) => HeapValue::Heap(x),
+        }
+    }
+}
+
+
+// Для листа содержит и ключ и значение. Для родителя только ключи. Значение хранится так,
+// как оно будет записано: сжатое кодеком codec.
+#[repr(C)]
+pub(crate) struct INode<'a> {
+    pub(crate) key: HeapValue<'a>,
+    pub(crate) value: HeapValue<'a>,
+    pub(crate) codec: u8,
+
+    pub(crate) page_id: Option<PageId>,
+}
+
+impl<'a> INode<'a> {
+    pub fn key(&self) -> &[u8] {
+        match &self.key {
+            HeapValue::MMapped(k) => k,
+            HeapValue::Heap(kv) => kv.as_slice(),
+            _ => unreachable!("Cant get key of empty inode"),
+        }
+    }
+
+    pub fn value(&self) -> &[u8] {
+        match &self.value {
+            HeapValue::MMapped(v) => v,
+            HeapValue::Heap(v) => v.as_slice(),
+            HeapValue::None => &[],
+        }
+    }
+}
+
+
+// https://gist.github.com/savarin/69acd246302567395f65ad6b97ee503d
+#[repr(C)]
+pub struct Node<'a> {
+    id: NodeId,
+    pub(crate) is_leaf: bool,
+    pub(crate) parent_id: Option<NodeId>,
+    childs: Vec<NodeId>,
+    pub(crate) page_id: PageId,
+    // Писать общий префикс ключей на страницу один раз (см. PAGE_PREFIX)
+    pub(crate) prefix_compression: bool,
+
+    // runtime only
+    pub(crate) inodes: Vec<INode<'a>>,
+    // Последний ключ ноды добавлен в этой транзакции (см. Tx::fill_percent)
+    pub(crate) appended: bool,
+}
+
+
+impl<'a> Node<'a> {
+    // Нода, не привязанная ни к дереву, ни к странице; используется при записи
+    // нового файла страница за страницей (см. compact)
+    pub(crate) fn new(is_leaf: bool, inodes: Vec<INode<'a>>) -> Node<'a> {
+        Node {
+            id: 0,
+            is_leaf,
+            parent_id: None,
+            childs: vec![],
+            page_id: 0,
+            prefix_compression: false,
+            inodes,
+            appended: false,
+        }
+    }
+
+    // Размер страницы(страниц), необходимый для записи ноды
+    pub fn size(&self) -> usize {
+        let prefix_len = self.prefix().len();
+        let codec_size = self.codec_size();
+        size_of::<PageHeader>() + prefix_size(prefix_len)
+            + self.inodes.iter().map(|x| self.inode_size(x, prefix_len) + codec_size).sum::<usize>()
+    }
+
+    // Байт кодека перед каждым значением пишется, только если в листе есть сжатые значения
+    pub(crate) fn codec_size(&self) -> usize {
+        if self.is_leaf && self.inodes.iter().any(|x| x.codec != CODEC_NONE) {
+            1
+        } else {
+            0
+        }
+    }
+
+    // Общий префикс ключей, который пишется на страницу один раз. Пустой, если сжатие
+    // выключено.
+    fn prefix(&self) -> &[u8] {
+        match (self.prefix_compression, self.inodes.first(), self.inodes.last()) {
+            (true, Some(first), Some(last)) => &first.key()[..page_prefix_len(self.inodes.len(), first.key(), last.key())],
+            _ => &[],
+        }
+    }
+
+    // Делит ноду, не влезающую в страницу, на несколько соседних. Каждая, кроме последней,
+    // заполняется примерно до fill_percent страницы. Нода из одного элемента не делится,
+    // даже если он больше страницы: он уходит в overflow-страницы.
+    pub(crate) fn split(mut self, page_size: usize, fill_percent: f64) -> Vec<Node<'a>> {
+        let threshold = (page_size as f64 * fill_percent) as usize;
+        let mut nodes = vec![];
+
+        while self.size() > page_size && self.inodes.len() > 1 {
+            let right = self.inodes.split_off(self.split_index(threshold));
+            let mut left = Node::new(self.is_leaf, mem::replace(&mut self.inodes, right));
+            left.prefix_compression = self.prefix_compression;
+            nodes.push(left);
+        }
+
+        nodes.push(self);
+        nodes
+    }
+
+    // Индекс, с которого inodes уходят в правую ноду: левая набирается, пока не превысит
+    // threshold, но в каждой остается хотя бы один элемент. Префикс левой части не короче
+    // префикса всей ноды, а байт кодека ей нужен не чаще, чем всей ноде, поэтому размер
+    // левой части считается с запасом.
+    fn split_index(&self, threshold: usize) -> usize {
+        let prefix_len = self.prefix().len();
+        let codec_size = self.codec_size();
+        let mut size = size_of::<PageHeader>() + prefix_size(prefix_len);
+        for (idx, inode) in self.inodes.iter().enumerate().take(self.inodes.len() - 1) {
+            let inode_size = self.inode_size(inode, prefix_len) + codec_size;
+            if idx > 0 && size + inode_size > threshold {
+                return idx;
+            }
+            size += inode_size;
+        }
+
+        self.inodes.len() - 1
+    }
+
+    pub(crate) fn inode_size(&self, inode: &INode, prefix_len: usize) -> usize {
+        let stored_inode_size = if self.is_leaf {
+            size_of::<LeafInodeHeader>()
+        } else {
+            size_of::<BranchINodeHeader>()
+        };
+
+        stored_inode_size + inode.key().len() - prefix_len + inode.value().len()
+    }
+
+    // Формат такой же, как у save_tree в writer: заголовок страницы, общий префикс ключей
+    // (если он есть), заголовки inode, затем суффиксы ключей, байты кодеков (если они есть)
+    // и значения. pos считается относительно заголовка inode.
+    pub fn serialize(&self, page_id: PageId, page_overflow_count: u32, buf: &mut [u8]) {
+        let prefix = self.prefix();
+        let codec_size = self.codec_size();
+        let mut flags = if self.is_leaf { PAGE_LEAF } else { PAGE_BRANCH };
+        if !prefix.is_empty() {
+            flags |= PAGE_PREFIX;
+        }
+        if codec_size > 0 {
+            flags |= PAGE_CODEC;
+        }
+
+        let page = PageHeader {
+            id: Le64::new(page_id),
+            flags: Le16::new(flags),
+            inode_count: Le32::new(self.inodes.len() as u32),
+            page_overflow_count: Le32::new(page_overflow_count),
+        };
+
+        let mut offset = write_bytes(buf, 0, to_bytes(&page));
+        if !prefix.is_empty() {
+            offset = write_bytes(buf, offset, &(prefix.len() as u32).to_le_bytes());
+            offset = write_bytes(buf, offset, prefix);
+        }
+
+        let mut kvoffset = self.size() - self.inodes.iter()
+            .map(|x| x.key().len() - prefix.len() + codec_size + x.value().len())
+            .sum::<usize>();
+
+        for inode in self.inodes.iter() {
+            if self.is_leaf {
+                let header = LeafInodeHeader {
+                    pos: Le32::new((kvoffset - offset) as u32),
+                    ksize: Le32::new((inode.key().len() - prefix.len()) as u32),
+                    vsize: Le32::new(inode.value().len() as u32),
+                    page_id: Le32::new(page_id as u32),
+                };
+                offset = write_bytes(buf, offset, to_bytes(&header));
+            } else {
+                let header = BranchINodeHeader {
+                    pos: Le32::new((kvoffset - offset) as u32),
+                    ksize: Le32::new((inode.key().len() - prefix.len()) as u32),
+                    page_id: Le32::new(inode.page_id.unwrap() as u32),
+                };
+                offset = write_bytes(buf, offset, to_bytes(&header));
+            }
+
+            kvoffset = write_bytes(buf, kvoffset, &inode.key()[prefix.len()..]);
+            if codec_size > 0 {
+                kvoffset = write_bytes(buf, kvoffset, &[inode.codec]);
+            }
+            kvoffset = write_bytes(buf, kvoffset, inode.value());
+        }
+    }
+}
+
+fn write_bytes(buf: &mut [u8], offset: usize, data: &[u8]) -> usize {
+    buf[offset..offset + data.len()].copy_from_slice(data);
+    offset + data.len()
+}
+
+
+pub struct NodeCache<'a> {
+    pub(crate) nodes: Vec<Node<'a>>,
+    pages: HashMap<PageId, NodeId>,
+}
+
+impl Default for NodeCache<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> NodeCache<'a> {
+    pub fn new() -> NodeCache<'a> {
+        NodeCache {
+            nodes: vec![],
+            pages: HashMap::new(),
+        }
+    }
+
+    // Нода, прочитанная из страницы page_id в этой транзакции
+    pub(crate) fn node(&self, page_id: PageId) -> Option<&Node<'a>> {
+        self.pages.get(&page_id).map(|&id| &self.nodes[id])
+    }
+
+    // Читает страницу в ноду. Если страница уже прочитана в этой транзакции, возвращает
+    // существующую ноду (вместе со всеми изменениями в ней).
+    pub fn read_node(&mut self, p: PageRef<'a>, parent_id: Option<NodeId>) -> io::Result<NodeId> {
+        if let Some(&id) = self.pages.get(&p.id()) {
+            return Ok(id);
+        }
+
+        let inodes = if p.is_leaf() {
+            p.leaf_inodes()?.into_iter()
+                .map(|x| INode {
+                    key: x.key.into(),
+                    value: x.value.into(),
+                    codec: x.codec,
+                    page_id: None,
+                })
+                .collect()
+        } else {
+            p.branch_inodes()?.into_iter()
+                .map(|x| INode {
+                    key: x.key.into(),
+                    value: HeapValue::None,
+                    codec: CODEC_NONE,
+                    page_id: Some(x.page_id),
+                })
+                .collect()
+        };
+
+        let id = self.nodes.len();
+        self.pages.insert(p.id(), id);
+        self.nodes.push(Node {
+            id,
+            is_leaf: p.is_leaf(),
+            parent_id,
+            childs: vec![],
+            page_id: p.id(),
+            prefix_compression: false,
+            inodes,
+            appended: false,
+        });
+
+        Ok(id)
+    }
+}
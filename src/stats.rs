@@ -0,0 +1,194 @@
+use std::fmt;
+use std::io;
+use std::mem::size_of;
+
+use crate::check::Visited;
+use crate::db::DB;
+use crate::types::{BranchINodeHeader, LeafInodeHeader, Meta, META_PAGES, PageHeader, PageId, prefix_size};
+
+// Статистика по файлу базы: сколько каких страниц, насколько они заполнены и какого
+// размера ключи и значения. Считается обходом страниц от корня.
+#[derive(Debug, Default)]
+pub struct Stats {
+    // Количество уровней дерева (корень-лист = 1)
+    pub depth: usize,
+
+    pub leaf: PageStats,
+    pub branch: PageStats,
+    pub meta: PageStats,
+    // Страница со списком свободных страниц (вместе с ее overflow-страницами)
+    pub freelist: PageStats,
+    // Дополнительные страницы нод, не влезших в одну страницу
+    pub overflow_page_count: u64,
+    // Страницы файла, не занятые ни деревом, ни meta, ни freelist: брошенные после
+    // copy-on-write коммитов и еще не использованный хвост файла
+    pub free_page_count: u64,
+
+    // Пары ключ-значение в обычных листьях и в листах с overflow-страницами
+    pub inline_count: u64,
+    pub overflow_count: u64,
+
+    pub key_sizes: Histogram,
+    pub value_sizes: Histogram,
+}
+
+#[derive(Debug, Default)]
+pub struct PageStats {
+    pub count: u64,
+    // Байты, занятые заголовками, ключами и значениями
+    pub used_bytes: u64,
+    // Байты всех страниц этого типа, включая overflow
+    pub allocated_bytes: u64,
+    fill_sum: f64,
+}
+
+impl PageStats {
+    fn add(&mut self, used: usize, allocated: usize) {
+        self.count += 1;
+        self.used_bytes += used as u64;
+        self.allocated_bytes += allocated as u64;
+        self.fill_sum += used as f64 / allocated as f64;
+    }
+
+    // Среднее заполнение страницы (0..1)
+    pub fn avg_fill(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        self.fill_sum / self.count as f64
+    }
+}
+
+// Гистограмма по степеням двойки: buckets[0] - размер 0, buckets[i] - размеры
+// в диапазоне [2^(i-1), 2^i)
+#[derive(Debug, Default)]
+pub struct Histogram {
+    pub buckets: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn add(&mut self, size: usize) {
+        let idx = (usize::BITS - size.leading_zeros()) as usize;
+        if self.buckets.len() <= idx {
+            self.buckets.resize(idx + 1, 0);
+        }
+
+        self.buckets[idx] += 1;
+    }
+
+    // Границы корзины [from, to)
+    pub fn bounds(idx: usize) -> (usize, usize) {
+        match idx {
+            0 => (0, 1),
+            _ => (1 << (idx - 1), 1 << idx),
+        }
+    }
+}
+
+pub(crate) fn collect(db: &DB, root_page: PageId, freelist_page: PageId) -> io::Result<Stats> {
+    let mut stats = Stats::default();
+    // Оба слота meta с контрольными суммами
+    for _ in 0..META_PAGES {
+        stats.meta.add(size_of::<PageHeader>() + size_of::<Meta>() + size_of::<u32>(), db.page_size());
+    }
+
+    walk(db, root_page, 1, &mut stats, &mut Visited::new())?;
+
+    let mut freelist_pages = 0;
+    if freelist_page != 0 {
+        let page = db.page(freelist_page)?;
+        freelist_pages = page.page_overflow_count.get() as u64 + 1;
+        stats.freelist.add(size_of::<PageHeader>() + page.freelist()?.len() * size_of::<u32>(), page.bytes().len());
+    }
+
+    let file_pages = db.file_size()? / db.page_size() as u64;
+    let used_pages = stats.meta.count + stats.leaf.count + stats.branch.count + stats.overflow_page_count + freelist_pages;
+    stats.free_page_count = file_pages.saturating_sub(used_pages);
+
+    Ok(stats)
+}
+
+fn walk(db: &DB, page_id: PageId, level: usize, stats: &mut Stats, visited: &mut Visited) -> io::Result<()> {
+    let page = db.page(page_id)?;
+    visited.visit(page_id, page.page_overflow_count.get())?;
+    let overflow = page.page_overflow_count.get() as usize;
+    let allocated = (overflow + 1) * db.page_size();
+
+    stats.depth = std::cmp::max(stats.depth, level);
+    stats.overflow_page_count += overflow as u64;
+
+    // Размеры ключей в гистограмме - полные, а занятое место считается по суффиксам.
+    // Размеры значений - как они записаны, то есть сжатые.
+    let prefix_len = page.prefix()?.len();
+    let codec_size = page.has_codec() as usize;
+    let mut used = size_of::<PageHeader>() + prefix_size(prefix_len);
+    if page.is_leaf() {
+        for inode in page.leaf_inodes()? {
+            let (ksize, vsize) = (inode.key.len(), inode.value.len());
+            used += size_of::<LeafInodeHeader>() + ksize - prefix_len + codec_size + vsize;
+
+            stats.key_sizes.add(ksize);
+            stats.value_sizes.add(vsize);
+            if overflow > 0 {
+                stats.overflow_count += 1;
+            } else {
+                stats.inline_count += 1;
+            }
+        }
+
+        stats.leaf.add(used, allocated);
+    } else {
+        let inodes = page.branch_inodes()?;
+        for inode in inodes.iter() {
+            used += size_of::<BranchINodeHeader>() + inode.key.len() - prefix_len;
+        }
+
+        stats.branch.add(used, allocated);
+
+        for inode in inodes {
+            walk(db, inode.page_id, level + 1, stats, visited)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for PageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} pages, {} of {} bytes used, avg fill {:.1}%",
+               self.count, self.used_bytes, self.allocated_bytes, self.avg_fill() * 100.0)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, &count) in self.buckets.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+
+            let (from, to) = Histogram::bounds(idx);
+            writeln!(f, "    [{}, {}): {}", from, to, count)?;
+        }
+
+        fmt::Result::Ok(())
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "depth: {}", self.depth)?;
+        writeln!(f, "leaf: {}", self.leaf)?;
+        writeln!(f, "branch: {}", self.branch)?;
+        writeln!(f, "meta: {}", self.meta)?;
+        writeln!(f, "freelist: {}", self.freelist)?;
+        writeln!(f, "overflow pages: {}", self.overflow_page_count)?;
+        writeln!(f, "free pages: {}", self.free_page_count)?;
+        writeln!(f, "key/values: {} inline, {} in overflow pages", self.inline_count, self.overflow_count)?;
+        writeln!(f, "key sizes:")?;
+        write!(f, "{}", self.key_sizes)?;
+        writeln!(f, "value sizes:")?;
+        write!(f, "{}", self.value_sizes)
+    }
+}
@@ -0,0 +1,331 @@
+use std::io;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Bound, RangeBounds};
+
+use crate::cursor::{Cursor, Item};
+use crate::db::{DB, Tx};
+use crate::keys::{self, KeyPart};
+use crate::types::{invalid_data, Key};
+
+// Типизированная таблица поверх базы: ключи кодируются KeyPart (с сохранением порядка, см.
+// keys), значения - Encode/Decode. Бакетов в базе нет, поэтому таблица занимает все
+// пространство ключей базы: две таблицы в одной базе видят ключи друг друга.
+//
+// И читает, и пишет таблица в транзакции, которую ей передали: get, iter и range видят
+// снимок транзакции вместе с ее собственными put и delete.
+//
+// У ключа таблицы одно значение, поэтому в dupsort-базе таблицу не создать: put там добавлял
+// бы значение к уже записанным, а delete не знал бы, какое из них удалять.
+pub struct Table<K, V> {
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: KeyPart, V: Encode + Decode> Table<K, V> {
+    pub fn new(db: &DB) -> io::Result<Table<K, V>> {
+        if db.dupsort() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "table in a dupsort database".to_string()));
+        }
+
+        Ok(Table { types: PhantomData })
+    }
+
+    pub fn get(&self, tx: &Tx, key: &K) -> io::Result<Option<V>> {
+        match tx.get(keys::to_key(key)?)? {
+            Some(value) => decode(&value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&self, tx: &mut Tx, key: &K, value: &V) -> io::Result<()> {
+        tx.put(keys::to_key(key)?, encode(value))
+    }
+
+    pub fn delete(&self, tx: &mut Tx, key: &K) -> io::Result<bool> {
+        tx.delete(keys::to_key(key)?)
+    }
+
+    // Все пары таблицы в порядке ключей
+    pub fn iter<'t>(&self, tx: &'t Tx) -> io::Result<Iter<'t, K, V>> {
+        self.range(tx, ..)
+    }
+
+    // Пары с ключами из range в порядке ключей. Границы сравниваются компаратором базы.
+    pub fn range<'t, R: RangeBounds<K>>(&self, tx: &'t Tx, range: R) -> io::Result<Iter<'t, K, V>> {
+        let bound = |bound: Bound<&K>| -> io::Result<Bound<Key>> {
+            Ok(match bound {
+                Bound::Included(key) => Bound::Included(keys::to_key(key)?),
+                Bound::Excluded(key) => Bound::Excluded(keys::to_key(key)?),
+                Bound::Unbounded => Bound::Unbounded,
+            })
+        };
+        let (start, end) = (bound(range.start_bound())?, bound(range.end_bound())?);
+
+        let mut cursor = tx.cursor();
+        let mut item = match &start {
+            Bound::Included(key) | Bound::Excluded(key) => cursor.seek(key)?,
+            Bound::Unbounded => cursor.first()?,
+        };
+        if let (Bound::Excluded(start), Some((key, _))) = (&start, &item) {
+            if tx.db().comparator().compare(key, start).is_eq() {
+                item = cursor.next()?;
+            }
+        }
+
+        Ok(Iter { db: tx.db(), cursor, item: Some(item), end, types: PhantomData })
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    db: &'a DB,
+    cursor: Cursor<'a>,
+    // Следующая пара; None - итерация закончилась
+    item: Option<Option<Item<'a>>>,
+    end: Bound<Key>,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: KeyPart, V: Decode> Iterator for Iter<'_, K, V> {
+    type Item = io::Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.item.take()??;
+
+        let past_end = match &self.end {
+            Bound::Included(end) => self.db.comparator().compare(&key, end).is_gt(),
+            Bound::Excluded(end) => self.db.comparator().compare(&key, end).is_ge(),
+            Bound::Unbounded => false,
+        };
+        if past_end {
+            return None;
+        }
+
+        // После ошибки итерация заканчивается
+        let decoded = keys::from_key(&key).and_then(|key| Ok((key, decode(&value)?)));
+        if decoded.is_ok() {
+            match self.cursor.next() {
+                Ok(item) => self.item = Some(item),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        Some(decoded)
+    }
+}
+
+// Кодирование значений таблицы. Кодировка не обязана сохранять порядок, но должна сама
+// знать свою длину: значения кортежей и структур пишутся подряд.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    // Читает значение из начала data и сдвигает data за него
+    fn decode(data: &mut &[u8]) -> io::Result<Self>;
+}
+
+pub fn encode<T: Encode + ?Sized>(val: &T) -> Vec<u8> {
+    let mut out = vec![];
+    val.encode(&mut out);
+    out
+}
+
+// Обратное к encode: data должна закончиться ровно на конце значения
+pub fn decode<T: Decode>(data: &[u8]) -> io::Result<T> {
+    let mut rest = data;
+    let val = T::decode(&mut rest)?;
+    if !rest.is_empty() {
+        return Err(invalid_data(format!("{} extra bytes after the value", rest.len())));
+    }
+
+    Ok(val)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(invalid_data(format!("truncated value: {} bytes instead of {}", data.len(), len)));
+    }
+
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+// Числа - little-endian фиксированной длины
+macro_rules! number_encode {
+    ($($ty:ty)+) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(data: &mut &[u8]) -> io::Result<Self> {
+                    let mut buf = [0; size_of::<$ty>()];
+                    buf.copy_from_slice(take(data, size_of::<$ty>())?);
+                    Ok(<$ty>::from_le_bytes(buf))
+                }
+            }
+        )+
+    };
+}
+
+number_encode!(u8 u16 u32 u64 i8 i16 i32 i64 f32 f64);
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(data: &mut &[u8]) -> io::Result<Self> {
+        match take(data, 1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            x => Err(invalid_data(format!("invalid bool {}", x))),
+        }
+    }
+}
+
+// Строки и последовательности - длина (Le32) и элементы
+fn encode_len(out: &mut Vec<u8>, len: usize) {
+    (len as u32).encode(out);
+}
+
+fn decode_len(data: &mut &[u8]) -> io::Result<usize> {
+    Ok(u32::decode(data)? as usize)
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(out, self.len());
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+impl Decode for String {
+    fn decode(data: &mut &[u8]) -> io::Result<Self> {
+        let len = decode_len(data)?;
+        String::from_utf8(take(data, len)?.to_vec()).map_err(|e| invalid_data(format!("string is not utf-8: {}", e)))
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_len(out, self.len());
+        for x in self {
+            x.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(data: &mut &[u8]) -> io::Result<Self> {
+        let len = decode_len(data)?;
+        // Длине верим не больше, чем позволяют данные: каждый элемент занимает хотя бы байт
+        let mut val = Vec::with_capacity(std::cmp::min(len, data.len()));
+        for _ in 0..len {
+            val.push(T::decode(data)?);
+        }
+        Ok(val)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Some(x) => {
+                out.push(1);
+                x.encode(out);
+            }
+            None => out.push(0),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(data: &mut &[u8]) -> io::Result<Self> {
+        match bool::decode(data)? {
+            true => T::decode(data).map(Some),
+            false => Ok(None),
+        }
+    }
+}
+
+macro_rules! tuple_encode {
+    ($($name:ident)+) => {
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode(out);)+
+            }
+        }
+
+        impl<$($name: Decode),+> Decode for ($($name,)+) {
+            fn decode(data: &mut &[u8]) -> io::Result<Self> {
+                Ok(($($name::decode(data)?,)+))
+            }
+        }
+    };
+}
+
+tuple_encode!(A);
+tuple_encode!(A B);
+tuple_encode!(A B C);
+tuple_encode!(A B C D);
+
+// Encode и Decode для своей структуры без derive: поля кодируются по порядку, как кортеж.
+//
+//     struct User { name: String, age: u32 }
+//     table_value!(User { name, age });
+#[macro_export]
+macro_rules! table_value {
+    ($ty:ident { $($field:ident),+ $(,)? }) => {
+        impl $crate::table::Encode for $ty {
+            fn encode(&self, out: &mut Vec<u8>) {
+                $($crate::table::Encode::encode(&self.$field, out);)+
+            }
+        }
+
+        impl $crate::table::Decode for $ty {
+            fn decode(data: &mut &[u8]) -> std::io::Result<Self> {
+                Ok($ty { $($field: $crate::table::Decode::decode(data)?),+ })
+            }
+        }
+    };
+}
+
+// KeyPart для своей структуры: ключ сортируется по полям в порядке перечисления
+//
+//     struct EventKey { tenant: u64, ts: i64 }
+//     table_key!(EventKey { tenant, ts });
+#[macro_export]
+macro_rules! table_key {
+    ($ty:ident { $($field:ident),+ $(,)? }) => {
+        impl $crate::keys::KeyPart for $ty {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                $($crate::keys::KeyPart::encode_key(&self.$field, out);)+
+            }
+
+            fn decode_key(data: &mut &[u8]) -> std::io::Result<Self> {
+                Ok($ty { $($field: $crate::keys::KeyPart::decode_key(data)?),+ })
+            }
+        }
+    };
+}
@@ -0,0 +1,784 @@
+use core::fmt;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io;
+use std::sync::Arc;
+use std::mem::size_of;
+use std::os::unix::fs::FileExt;
+
+use log::trace;
+
+use crate::check::Visited;
+use crate::cipher;
+use crate::codec::{self, CODEC_NONE};
+use crate::comparator::{self, Comparator};
+use crate::db::{DB, MAX_FILL_PERCENT, MIN_FILL_PERCENT, Options};
+
+use crate::types::{BranchINodeHeader, bytes_to_key, invalid_data, Key, key_to_str, LeafInodeHeader, Le16, Le32, Le64, MAGIC, Meta, META_PAGES, OnDisk,
+                   PAGE_BRANCH, PAGE_CODEC, PAGE_LEAF, PAGE_PREFIX, page_prefix_len, PageHeader, PageId, prefix_size,
+                   to_bytes, VERSION};
+
+type NodeId = usize;
+
+// Для листа содержит и ключ и значение. Для родителя только ключи
+struct INode {
+    key: Key,
+    value: Option<Vec<u8>>,
+}
+
+// https://gist.github.com/savarin/69acd246302567395f65ad6b97ee503d
+//
+// У ветки inodes и childs одной длины: inodes[i].key - разделитель для childs[i], как и в
+// файле. Все ключи childs[i] не меньше inodes[i].key (кроме самого левого потомка, куда
+// уходят ключи меньше всех разделителей) и меньше inodes[i + 1].key.
+struct Node {
+    id: NodeId,
+    is_leaf: bool,
+    parent_id: Option<NodeId>,
+    childs: Vec<NodeId>,
+    // runtime only
+    inodes: Vec<INode>,
+}
+
+impl Node {
+    // Размер ноды в файле. save_tree пишет в ветку не разделители из памяти, а самые
+    // короткие разделители между потомками, поэтому для ветки размер считается с запасом:
+    // по полным ключам и без общего префикса (у записанных разделителей он может оказаться
+    // короче). Значения save_tree сжимает кодеком, если он задан, а здесь они считаются
+    // несжатыми и с байтом кодека - тоже с запасом.
+    pub fn size(&self, prefix_compression: bool, codec: bool) -> u64 {
+        let prefix_len = self.prefix_len(prefix_compression);
+        (size_of::<PageHeader>() + prefix_size(prefix_len)) as u64 + self.inodes.iter().map(|x| self.inode_size(x, prefix_len, codec)).sum::<u64>()
+    }
+
+    fn prefix_len(&self, prefix_compression: bool) -> usize {
+        match (prefix_compression && self.is_leaf, self.inodes.first(), self.inodes.last()) {
+            (true, Some(first), Some(last)) => page_prefix_len(self.inodes.len(), &first.key, &last.key),
+            _ => 0,
+        }
+    }
+
+    fn inode_size(&self, inode: &INode, prefix_len: usize, codec: bool) -> u64 {
+        let stored_inode_size = if self.is_leaf {
+            size_of::<LeafInodeHeader>() + codec as usize
+        } else {
+            size_of::<BranchINodeHeader>()
+        };
+
+        (stored_inode_size + inode.key.len() - prefix_len + inode.value.as_ref().map_or(0, |x| x.len())) as u64
+    }
+
+    // Индекс, с которого inodes уходят в правую ноду при делении: левая набирается, пока
+    // не превысит threshold, но в каждой остается хотя бы один элемент. Префикс левой части
+    // не короче префикса всей ноды, поэтому по нему размер левой части считается с запасом.
+    fn split_index(&self, threshold: u64, prefix_compression: bool, codec: bool) -> usize {
+        let prefix_len = self.prefix_len(prefix_compression);
+        let mut size = (size_of::<PageHeader>() + prefix_size(prefix_len)) as u64;
+        for (idx, inode) in self.inodes.iter().enumerate().take(self.inodes.len() - 1) {
+            let inode_size = self.inode_size(inode, prefix_len, codec);
+            if idx > 0 && size + inode_size > threshold {
+                return idx;
+            }
+            size += inode_size;
+        }
+
+        self.inodes.len() - 1
+    }
+
+}
+
+pub struct BPlusTree {
+    // Нода делится, когда перестает влезать в страницу. Одиночный элемент больше страницы
+    // не делится, он уходит в overflow-страницы.
+    page_size: usize,
+    // См. Options::fill_percent и Options::right_edge_split
+    fill_percent: f64,
+    right_edge_split: bool,
+    // См. Options::prefix_compression
+    prefix_compression: bool,
+    // Задан ли Options::codec. Сам кодек нужен только save_tree, в памяти значения несжатые.
+    codec: bool,
+    // Порядок ключей, см. Options::comparator
+    comparator: Arc<dyn Comparator>,
+
+    nodes: Vec<Node>,
+    // Список всех нод дерева
+    root_id: NodeId,
+}
+
+impl BPlusTree {
+    pub fn new(page_size: usize) -> BPlusTree {
+        BPlusTree::with_options(&Options { page_size, ..Options::default() })
+    }
+
+    // Из options берутся размер страницы, правила деления нод, сжатие префиксов и кодек.
+    // Сжатие должно совпадать с тем, с которым дерево потом запишет save_tree, иначе страницы
+    // будут заполнены не так, как задано fill_percent.
+    pub fn with_options(options: &Options) -> BPlusTree {
+        BPlusTree {
+            // Ноды делятся по месту, которое останется в странице после шифрования
+            page_size: options.page_size - cipher::page_overhead(options.cipher.as_deref()),
+            fill_percent: options.fill_percent,
+            right_edge_split: options.right_edge_split,
+            prefix_compression: options.prefix_compression,
+            codec: options.codec.is_some(),
+            comparator: comparator::resolve(options.comparator.as_ref()),
+            nodes: vec![Node {
+                id: 0,
+                is_leaf: true,
+                parent_id: None,
+                childs: vec![],
+                inodes: vec![],
+            }],
+            root_id: 0,
+        }
+    }
+
+    // Читает дерево из файла базы, чтобы его можно было изменить и записать обратно через
+    // save_tree. Размер страницы берется из файла, правила деления нод - по умолчанию
+    // (поменять можно через set_fill_percent). Значения распаковываются, поэтому сжатые
+    // не встроенным кодеком прочитать нельзя.
+    pub fn load(path: &str) -> io::Result<BPlusTree> {
+        BPlusTree::load_with(path, &Options::default())
+    }
+
+    // То же, что load, но с кодеком, шифром и правилами деления нод из options. Размер
+    // страницы все равно берется из файла.
+    pub fn load_with(path: &str, options: &Options) -> io::Result<BPlusTree> {
+        let db = DB::open_with(path, Options { read_only: true, ..options.clone() })?;
+        if db.dupsort() {
+            return Err(dupsort_unsupported());
+        }
+
+        // Корневой лист из with_options не нужен: все ноды, включая корень, читаются из файла
+        let mut tree = BPlusTree::with_options(&Options { page_size: db.page_size(), ..options.clone() });
+        tree.nodes.clear();
+        tree.root_id = tree.load_node(&db, db.meta().root_page.get() as PageId, 1, &mut Visited::new())?;
+
+        Ok(tree)
+    }
+
+    // Ноды создаются после своих потомков, чтобы create_node проставил им parent_id
+    fn load_node(&mut self, db: &DB, page_id: PageId, level: usize, visited: &mut Visited) -> io::Result<NodeId> {
+        let page = db.page(page_id)?;
+        visited.visit(page_id, page.page_overflow_count.get())?;
+        if page.count() == 0 && level > 1 {
+            return Err(invalid_data(format!("empty non-root page {}", page_id)));
+        }
+
+        let to_key = |key: &[u8]| bytes_to_key(key)
+            .ok_or_else(|| invalid_data(format!("key of {} bytes on page {} is too long", key.len(), page_id)));
+
+        if page.is_leaf() {
+            let inodes = page.leaf_inodes()?.into_iter()
+                .map(|x| Ok(INode { key: to_key(&x.key)?, value: Some(db.decode(x.codec, x.value)?.into_owned()) }))
+                .collect::<io::Result<Vec<_>>>()?;
+
+            return Ok(self.create_node(true, None, inodes, vec![]));
+        }
+
+        // Разделители в файле могут быть укорочены (и тогда это не Key), поэтому в памяти
+        // разделителем становится первый ключ потомка
+        let mut inodes = vec![];
+        let mut childs = vec![];
+        for inode in page.branch_inodes()? {
+            let child_id = self.load_node(db, inode.page_id, level + 1, visited)?;
+            inodes.push(INode { key: self.first_key(child_id), value: None });
+            childs.push(child_id);
+        }
+
+        if childs.is_empty() {
+            return Err(invalid_data(format!("branch page {} has no inodes", page_id)));
+        }
+
+        Ok(self.create_node(false, None, inodes, childs))
+    }
+
+    pub fn set_fill_percent(&mut self, fill_percent: f64, right_edge_split: bool) {
+        assert!((MIN_FILL_PERCENT..=MAX_FILL_PERCENT).contains(&fill_percent), "Invalid fill percent {}", fill_percent);
+
+        self.fill_percent = fill_percent;
+        self.right_edge_split = right_edge_split;
+    }
+
+    // Добавляет ключ или заменяет значение существующего
+    pub fn add(&mut self, key: Key, value: Vec<u8>) {
+        let target_node_id = self._search(&key);
+
+        let comparator = self.comparator.clone();
+        let inodes = &mut self.node_mut(target_node_id).inodes;
+        let appended = match inodes.binary_search_by(|inode| comparator.compare(&inode.key, &key)) {
+            Ok(idx) => {
+                inodes[idx].value = Some(value);
+                return;
+            }
+            Err(idx) => {
+                inodes.insert(idx, INode { key, value: Some(value) });
+                idx == inodes.len() - 1
+            }
+        };
+
+        // Ключ больше всех в дереве: все ноды на пути делятся по правому краю (разделители
+        // в родителях при этом тоже добавляются в конец)
+        let fill_percent = if self.right_edge_split && appended && self.is_rightmost(target_node_id) {
+            MAX_FILL_PERCENT
+        } else {
+            self.fill_percent
+        };
+
+        // Деление добавляет элемент в родителя, поэтому проверяем путь вверх, пока ноды делятся
+        let mut node_to_split = Some(target_node_id);
+        while let Some(mut node_id) = node_to_split {
+            if !self.is_overfull(node_id) {
+                break;
+            }
+
+            // Правая часть тоже может не влезать в страницу, если добавили большое значение
+            while self.is_overfull(node_id) {
+                node_id = self.split(node_id, fill_percent);
+            }
+            node_to_split = self.node(node_id).parent_id;
+        }
+    }
+
+    fn is_rightmost(&self, mut node_id: NodeId) -> bool {
+        while let Some(parent_id) = self.node(node_id).parent_id {
+            if self.node(parent_id).childs.last() != Some(&node_id) {
+                return false;
+            }
+            node_id = parent_id;
+        }
+
+        true
+    }
+
+    fn is_overfull(&self, node_id: NodeId) -> bool {
+        let node = self.node(node_id);
+        node.inodes.len() > 1 && node.size(self.prefix_compression, self.codec) > self.page_size as u64
+    }
+
+    // Удаляет ключ и возвращает его значение. Ноды не сливаются: опустевший лист просто
+    // убирается из родителя (и так вверх), а корень-ветка с одним потомком заменяется им.
+    pub fn remove(&mut self, key: Key) -> Option<Vec<u8>> {
+        let leaf_id = self._search(&key);
+        let idx = self.node(leaf_id).inodes.binary_search_by(|inode| self.comparator.compare(&inode.key, &key)).ok()?;
+        let value = self.node_mut(leaf_id).inodes.remove(idx).value;
+
+        let mut node_id = leaf_id;
+        while self.node(node_id).inodes.is_empty() {
+            let parent_id = match self.node(node_id).parent_id {
+                Some(parent_id) => parent_id,
+                None => break,
+            };
+
+            let parent = self.node_mut(parent_id);
+            let child_idx = parent.childs.iter()
+                .position(|&x| x == node_id)
+                .expect("Invalid parent_id on node. Node not found in parent.childs");
+            parent.childs.remove(child_idx);
+            parent.inodes.remove(child_idx);
+
+            node_id = parent_id;
+        }
+
+        while !self.node(self.root_id).is_leaf && self.node(self.root_id).childs.len() == 1 {
+            self.root_id = self.node(self.root_id).childs[0];
+            let root_id = self.root_id;
+            self.node_mut(root_id).parent_id = None;
+        }
+
+        // Последний лист удален целиком - дерево снова из одного пустого листа
+        if !self.node(self.root_id).is_leaf && self.node(self.root_id).childs.is_empty() {
+            let root_id = self.root_id;
+            self.node_mut(root_id).is_leaf = true;
+        }
+
+        value
+    }
+
+    pub fn get(&self, key: Key) -> Option<&Vec<u8>> {
+        let target_node = self.node(self._search(&key));
+
+        for inode in &target_node.inodes {
+            if self.comparator.compare(&inode.key, &key) == Ordering::Equal {
+                return inode.value.as_ref();
+            }
+        }
+
+        None
+    }
+
+    // Количество ключей в дереве
+    pub fn len(&self) -> usize {
+        self.leaves().iter().map(|&id| self.node(id).inodes.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Все пары ключ-значение по порядку ключей
+    pub fn iter(&self) -> impl Iterator<Item=(&Key, &Vec<u8>)> {
+        self.leaves().into_iter()
+            .flat_map(move |id| self.node(id).inodes.iter())
+            .map(|inode| (&inode.key, inode.value.as_ref().unwrap()))
+    }
+
+    // Листья слева направо
+    fn leaves(&self) -> Vec<NodeId> {
+        let mut leaves = vec![];
+        let mut stack = vec![self.root_id];
+
+        while let Some(node_id) = stack.pop() {
+            let node = self.node(node_id);
+            if node.is_leaf {
+                leaves.push(node_id);
+            }
+
+            stack.extend(node.childs.iter().rev());
+        }
+
+        leaves
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id]
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    // Наименьший ключ поддерева. Разделитель самого левого потомка может быть больше его
+    // настоящего первого ключа, поэтому спускаемся до листа.
+    fn first_key(&self, node_id: NodeId) -> Key {
+        let mut node = self.node(node_id);
+        while !node.is_leaf {
+            node = self.node(node.childs[0]);
+        }
+
+        node.inodes[0].key
+    }
+
+    // Наибольший ключ поддерева
+    fn last_key(&self, node_id: NodeId) -> Key {
+        let mut node = self.node(node_id);
+        while !node.is_leaf {
+            node = self.node(*node.childs.last().unwrap());
+        }
+
+        node.inodes.last().unwrap().key
+    }
+
+    // Регистрирует ноду в дереве и обновляет ссылки у дочерних элементов на вновь созданный ID
+    fn create_node(&mut self, is_leaf: bool, parent_id: Option<NodeId>, inodes: Vec<INode>, childs: Vec<NodeId>) -> NodeId {
+        let id = self.nodes.len() as NodeId;
+        for &child_id in childs.iter() {
+            self.node_mut(child_id).parent_id = Some(id);
+        }
+
+        self.nodes.push(Node {
+            id: self.nodes.len() as NodeId,
+            is_leaf,
+            parent_id,
+            childs,
+            inodes,
+        });
+
+        id
+    }
+
+    // Выставляет разделители веток равными первым ключам потомков, как они будут записаны
+    // в файл. Для поиска это не нужно, только для наглядного вывода.
+    pub fn update_childs(&mut self) {
+        let mut seen_nodes = HashMap::<NodeId, bool>::new();
+        let mut stack = vec![self.root_id];
+
+        loop {
+            if stack.is_empty() {
+                break;
+            }
+
+            let node_id = stack.pop().unwrap();
+            if seen_nodes.insert(node_id, true).is_none() {
+                stack.push(node_id);
+                for &child_id in self.nodes[node_id].childs.iter() {
+                    stack.push(child_id);
+                }
+
+                continue;
+            }
+
+            if self.nodes[node_id].is_leaf {
+                continue;
+            }
+
+            for idx in 0..self.nodes[node_id].childs.len() {
+                let k = self.first_key(self.nodes[node_id].childs[idx]);
+                self.nodes[node_id].inodes[idx].key = k;
+            }
+        }
+    }
+
+    // Отделяет от ноды правую часть и возвращает ее id
+    fn split(&mut self, left_node_id: NodeId, fill_percent: f64) -> NodeId {
+        let middle = self.node(left_node_id).split_index((self.page_size as f64 * fill_percent) as u64, self.prefix_compression, self.codec);
+
+        // Правая нода забирает себе старшие ключи и потомков, которые
+        // содержат старшие диапазоны (если это не лист)
+        let is_leaf = self.node(left_node_id).is_leaf;
+        let right_inodes = self.node_mut(left_node_id).inodes.split_off(middle);
+        let right_childs = if !self.node_mut(left_node_id).childs.is_empty() {
+            self.node_mut(left_node_id).childs.split_off(middle)
+        } else {
+            Vec::<NodeId>::new()
+        };
+
+        let parent_id = self.node_mut(left_node_id).parent_id;
+        let right_node_id = self.create_node(is_leaf, parent_id, right_inodes, right_childs);
+
+        // Первый ключ правой ноды становится ее разделителем в родителе
+        let first_right_key = self.node_mut(right_node_id).inodes[0].key;
+        let first_left_key = self.first_key(left_node_id);
+
+        if let Some(parent_id) = parent_id {
+            // Вставляем ссылку на новый узел сразу же после исходного узла. Позицию берем по
+            // потомкам, а не поиском по ключу: разделитель самого левого потомка может быть
+            // больше first_right_key. Поэтому же опускаем разделитель левой ноды до ее
+            // настоящего первого ключа, иначе разделители в родителе перестанут быть упорядочены.
+            let parent = self.node_mut(parent_id);
+
+            let right_child_idx = parent.childs.iter()
+                .position(|&x| x == left_node_id)
+                .expect("Invalid parent_id on node. Node not found in parent.childs");
+
+            parent.inodes[right_child_idx].key = first_left_key;
+            parent.childs.insert(right_child_idx + 1, right_node_id);
+            parent.inodes.insert(right_child_idx + 1, INode { key: first_right_key, value: None });
+        } else {
+            // Расщепляется корень, надо создать новый
+            self.root_id = self.create_node(
+                false,
+                None,
+                vec![INode { key: first_left_key, value: None }, INode { key: first_right_key, value: None }],
+                vec![left_node_id, right_node_id],
+            );
+        };
+
+        right_node_id
+    }
+
+    fn _search(&self, key: &Key) -> NodeId {
+        self._tree_search(key, self.root_id)
+    }
+
+    fn _tree_search(&self, key: &Key, node_id: NodeId) -> NodeId {
+        let node = self.node(node_id);
+        if node.is_leaf {
+            return node_id;
+        }
+
+        // Ищем индекс ребенка, в котором искать дальше:
+        // key < keys[1] -> 0
+        // keys[i] <= key < key[i+1] -> i
+        // key >= keys[last] -> last
+        let child_index = node.inodes.iter()
+            .position(|x| self.comparator.compare(&x.key, key) == Ordering::Greater)
+            .unwrap_or(node.childs.len())
+            .saturating_sub(1);
+
+        self._tree_search(key, node.childs[child_index])
+    }
+}
+
+impl Display for BPlusTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut stack = Vec::<(NodeId, u32)>::new();
+        stack.push((self.root_id, 0));
+
+        loop {
+            if stack.is_empty() {
+                break;
+            }
+
+            let (node_id, level) = stack.pop().unwrap();
+
+            let node = &self.nodes[node_id];
+
+            let mut name: String = String::new();
+            for _ in 0..level {
+                name.push_str("  ");
+                if level > 0 {
+                    name.push('|');
+                }
+            }
+            name.push_str("--");
+
+            for (idx, k) in node.inodes.iter().enumerate() {
+                name.push_str(key_to_str(&k.key).as_str());
+
+                if idx < node.inodes.len() - 1 {
+                    name.push(',');
+                }
+            }
+
+            writeln!(f, "{} (id={}, parent_id={:?}, childs={:?})", name.as_str(), node.id, node.parent_id, node.childs.as_slice())?;
+
+            for &child_id in node.childs.iter() {
+                stack.push((child_id, level + 1));
+            }
+        }
+
+        fmt::Result::Ok(())
+    }
+}
+
+
+struct Allocator {
+    page_size: usize,
+    free_pages: Vec<u64>,
+    // page_ids
+    allocated_pages: Vec<u64>,
+    // Страниц в файле. Когда свободные кончаются, новые выделяются за концом файла.
+    page_count: u64,
+}
+
+impl Allocator {
+    pub fn new(page_size: usize, file_len: u64) -> Allocator {
+        // Страницы meta заняты всегда, даже в пустом файле
+        let page_count = std::cmp::max(file_len / page_size as u64, META_PAGES);
+
+        let mut free_pages = vec![];
+        for i in META_PAGES..page_count {
+            free_pages.push(i);
+        }
+
+        Allocator {
+            page_size,
+            free_pages,
+            allocated_pages: (0..META_PAGES).collect(),
+            page_count,
+        }
+    }
+
+    pub fn get_free_page(&mut self, size: u64) -> PageHeader {
+        let mut total_size = 0u64;
+        let mut pages = Vec::<u64>::new();
+        loop {
+            let page_id = if self.free_pages.is_empty() {
+                self.page_count += 1;
+                self.page_count - 1
+            } else {
+                self.free_pages.remove(0)
+            };
+
+            pages.push(page_id);
+            self.allocated_pages.push(page_id);
+            total_size += self.page_size as u64;
+
+            if total_size >= size {
+                break;
+            }
+        }
+
+        PageHeader {
+            id: Le64::new(pages[0]),
+            page_overflow_count: Le32::new((pages.len() - 1) as u32),
+            flags: Le16::new(0),
+            inode_count: Le32::new(0),
+        }
+    }
+}
+
+fn serialize_data<T: OnDisk>(buf: &mut [u8], offset: usize, data: T) -> usize {
+    buf[offset..offset + size_of::<T>()].copy_from_slice(to_bytes(&data));
+
+    offset + size_of::<T>()
+}
+
+// У ключа BPlusTree одно значение: dupsort-базы он не читает и не пишет
+fn dupsort_unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "BPlusTree does not support dupsort databases".to_string())
+}
+
+// Пишет дерево в новый файл базы по пути path (файл перезаписывается). Из options берутся
+// размер страницы, начальный размер файла, режим sync, сжатие префиксов и кодек значений.
+pub fn save_tree(tree: &BPlusTree, path: &str, options: &Options) -> io::Result<()> {
+    options.validate()?;
+    if options.dupsort {
+        return Err(dupsort_unsupported());
+    }
+
+    let page_size = options.page_size;
+    let overhead = cipher::page_overhead(options.cipher.as_deref());
+    let salt = cipher::salt(options.cipher.as_deref())?;
+    let f = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+    f.set_len(0)?;
+    f.set_len(options.initial_size / page_size as u64 * page_size as u64)?;
+
+    let mut allocator = Allocator::new(page_size, f.metadata()?.len());
+
+    let mut writed_pages = HashMap::<NodeId, u64>::new();
+    let mut seen_nodes = HashMap::<NodeId, bool>::new();
+    let mut stack = vec![tree.root_id];
+
+    loop {
+        if stack.is_empty() {
+            break;
+        }
+
+        let node = tree.node(stack.pop().unwrap());
+        if !node.childs.is_empty() && !seen_nodes.contains_key(&node.id) {
+            stack.push(node.id);
+            for &child_id in node.childs.iter() {
+                stack.push(child_id);
+            }
+
+            seen_nodes.insert(node.id, true);
+            continue;
+        }
+
+        // В ветку пишутся не разделители из памяти, а самые короткие ключи, которые отделяют
+        // потомка от предыдущего. У первого потомка - его первый ключ целиком.
+        let keys: Vec<Vec<u8>> = if node.is_leaf {
+            node.inodes.iter().map(|x| x.key.to_vec()).collect()
+        } else {
+            node.childs.iter().enumerate()
+                .map(|(idx, &child_id)| match idx {
+                    0 => tree.first_key(child_id).to_vec(),
+                    _ => tree.comparator.separator(&tree.last_key(node.childs[idx - 1]), &tree.first_key(child_id)).to_vec(),
+                })
+                .collect()
+        };
+
+        let prefix_len = match (options.prefix_compression, keys.first(), keys.last()) {
+            (true, Some(first), Some(last)) => page_prefix_len(keys.len(), first, last),
+            _ => 0,
+        };
+
+        // Значения листа в том виде, в каком они пишутся: сжатые кодеком и его id
+        let values: Vec<(u8, Vec<u8>)> = node.inodes.iter()
+            .filter_map(|x| x.value.clone())
+            .map(|x| codec::encode(options.codec.as_deref(), x))
+            .collect();
+        let codec_size = values.iter().any(|x| x.0 != CODEC_NONE) as usize;
+
+        let stored_inode_size = if node.is_leaf {
+            size_of::<LeafInodeHeader>()
+        } else {
+            size_of::<BranchINodeHeader>()
+        };
+        let node_header_size = size_of::<PageHeader>() + prefix_size(prefix_len) + stored_inode_size * keys.len();
+        let node_size = node_header_size
+            + keys.iter().map(|x| x.len() - prefix_len).sum::<usize>()
+            + values.iter().map(|x| codec_size + x.1.len()).sum::<usize>();
+
+        let mut page = allocator.get_free_page((node_size + overhead) as u64);
+        let mut buffer = vec![0u8; (page.page_overflow_count.get() as usize + 1) * page_size];
+        page.inode_count = Le32::new(keys.len() as u32);
+
+        if page.page_overflow_count.get() > 0 {
+            trace!("page {} has {} overflow pages", page.id.get(), page.page_overflow_count.get());
+        }
+
+        let mut flags = if node.is_leaf {
+            PAGE_LEAF
+        } else {
+            PAGE_BRANCH
+        };
+        if prefix_len > 0 {
+            flags |= PAGE_PREFIX;
+        }
+        if codec_size > 0 {
+            flags |= PAGE_CODEC;
+        }
+        page.flags = Le16::new(flags);
+
+        let page_id = page.id.get();
+
+        // 1. Write page header and common key prefix
+        let mut offset: usize = 0;
+        {
+            offset = serialize_data(&mut buffer, offset, page);
+        }
+
+        if prefix_len > 0 {
+            buffer[offset..offset + size_of::<u32>()].copy_from_slice(&(prefix_len as u32).to_le_bytes());
+            offset += size_of::<u32>();
+            buffer[offset..offset + prefix_len].copy_from_slice(&keys[0][..prefix_len]);
+            offset += prefix_len;
+        }
+
+        // 2. Write inodes. pos - offset относительно текущего заголовка inode для удобства чтения
+        let mut kvoffset = node_header_size;
+        for (idx, key) in keys.iter().enumerate() {
+            let suffix = &key[prefix_len..];
+            if node.is_leaf {
+                let (codec, value) = &values[idx];
+                let leaf_header: LeafInodeHeader = LeafInodeHeader {
+                    pos: Le32::new((kvoffset - offset) as u32),
+                    ksize: Le32::new(suffix.len() as u32),
+                    vsize: Le32::new(value.len() as u32),
+                    page_id: Le32::new(page_id as u32),
+                };
+
+                offset = serialize_data(&mut buffer, offset, leaf_header);
+
+                buffer[kvoffset..kvoffset + suffix.len()].copy_from_slice(suffix);
+                kvoffset += suffix.len();
+
+                if codec_size > 0 {
+                    buffer[kvoffset] = *codec;
+                    kvoffset += codec_size;
+                }
+
+                buffer[kvoffset..kvoffset + value.len()].copy_from_slice(value);
+                kvoffset += value.len();
+            } else {
+                let branch_header: BranchINodeHeader = BranchINodeHeader {
+                    pos: Le32::new((kvoffset - offset) as u32),
+                    ksize: Le32::new(suffix.len() as u32),
+                    page_id: Le32::new(*writed_pages.get(&node.childs[idx]).unwrap() as u32),
+                };
+
+                offset = serialize_data(&mut buffer, offset, branch_header);
+
+                buffer[kvoffset..kvoffset + suffix.len()].copy_from_slice(suffix);
+                kvoffset += suffix.len();
+            }
+        }
+
+        cipher::seal(options.cipher.as_deref(), &options.cipher_key.0, &mut buffer, salt);
+        f.write_all_at(buffer.as_slice(), page_id * page_size as u64)?;
+        writed_pages.insert(node.id, page_id);
+    }
+
+    // Последняя страница могла быть записана не целиком
+    let page_count = allocator.allocated_pages.iter().max().unwrap() + 1;
+    if f.metadata()?.len() < page_count * page_size as u64 {
+        f.set_len(page_count * page_size as u64)?;
+    }
+
+    // Meta пишется последней и только после того, как страницы с данными дошли до диска
+    options.sync_mode.sync(&f)?;
+
+    let h = Meta {
+        magic: Le32::new(MAGIC),
+        version: Le32::new(VERSION),
+        page_size: Le32::new(page_size as u32),
+        root_page: Le32::new(*writed_pages.get(&tree.root_id).unwrap() as u32),
+        page_count: Le32::new(page_count as u32),
+        // Страницы выделялись подряд, свободных между ними нет
+        freelist_page: Le32::new(0),
+        txid: Le64::new(1),
+        comparator: Le32::new(tree.comparator.id()),
+        flags: Le32::new(0),
+    };
+
+    f.write_all_at(h.to_file_bytes(page_size).as_slice(), 0)?;
+    options.sync_mode.sync(&f)?;
+
+    Ok(())
+}
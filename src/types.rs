@@ -0,0 +1,669 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::iter::FromIterator;
+use std::mem::size_of;
+use std::ops::Deref;
+use std::ptr::slice_from_raw_parts;
+use std::str;
+use std::sync::Arc;
+
+use crate::codec::CODEC_NONE;
+
+const MAX_KEY_SIZE: usize = 32;
+
+pub type PageId = u64;
+pub type Key = [u8; MAX_KEY_SIZE];
+
+pub fn key_to_str(val: &[u8]) -> String {
+    String::from_iter(
+        val.iter()
+            .filter(|&&x| x != 0)
+            .map(|&x| x as char)
+    )
+}
+
+pub fn str_to_key(val: &str) -> Key {
+    let mut ret: Key = [0; MAX_KEY_SIZE];
+    for (i, &b) in val.as_bytes().iter().rev().enumerate() {
+        ret[MAX_KEY_SIZE - i - 1] = b;
+    }
+
+    ret
+}
+
+// Как str_to_key, но для произвольных байт. None, если ключ не влезает в Key.
+pub fn bytes_to_key(val: &[u8]) -> Option<Key> {
+    if val.len() > MAX_KEY_SIZE {
+        return None;
+    }
+
+    let mut ret: Key = [0; MAX_KEY_SIZE];
+    ret[MAX_KEY_SIZE - val.len()..].copy_from_slice(val);
+    Some(ret)
+}
+
+// Самый короткий разделитель между соседними нодами: префикс first (первого ключа правой
+// ноды), который больше last (последнего ключа левой). Ветке не нужен весь ключ: поиск
+// сравнивает ключи побайтно, и префикс делит ноды так же, как полный ключ.
+pub fn shortest_separator<'a>(last: &[u8], first: &'a [u8]) -> &'a [u8] {
+    let common = last.iter().zip(first.iter()).take_while(|(a, b)| a == b).count();
+    &first[..std::cmp::min(common + 1, first.len())]
+}
+
+pub fn val_to_str(val: &[u8]) -> &str {
+    str::from_utf8(val).unwrap()
+}
+
+
+pub const VERSION: u32 = 11;
+pub const MAGIC: u32 = 0x9B9AB9EE;
+
+// Допустимые размеры страницы (степень двойки)
+pub const MIN_PAGE_SIZE: u32 = 512;
+pub const MAX_PAGE_SIZE: u32 = 1 << 16;
+
+// Meta лежит в двух слотах, страницах 0 и 1. Коммит пишет новую meta в слот txid % 2, не
+// трогая текущую, поэтому порванная запись портит только один слот (см. read_meta).
+pub const META_PAGES: PageId = 2;
+
+// Числа в файле всегда хранятся little-endian, независимо от платформы. Обертки держат
+// байты как есть и имеют выравнивание 1, поэтому структуры из них не имеют паддинга и
+// читаются/пишутся одинаково на любой архитектуре.
+macro_rules! le_type {
+    ($name:ident, $t:ty) => {
+        #[repr(transparent)]
+        #[derive(Copy, Clone, Default, PartialEq, Eq)]
+        pub struct $name([u8; size_of::<$t>()]);
+
+        impl $name {
+            pub fn new(val: $t) -> $name {
+                $name(val.to_le_bytes())
+            }
+
+            pub fn get(&self) -> $t {
+                <$t>::from_le_bytes(self.0)
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.get())
+            }
+        }
+    };
+}
+
+le_type!(Le16, u16);
+le_type!(Le32, u32);
+le_type!(Le64, u64);
+
+/// Структура, которая лежит в файле байт-в-байт.
+///
+/// # Safety
+///
+/// Только #[repr(C)] и только поля Le*/u8: выравнивание 1, паддинга нет,
+/// любая комбинация байт - валидное значение.
+pub unsafe trait OnDisk: Copy {}
+
+unsafe impl OnDisk for Meta {}
+unsafe impl OnDisk for PageHeader {}
+unsafe impl OnDisk for LeafInodeHeader {}
+unsafe impl OnDisk for BranchINodeHeader {}
+
+// Кодирование: байты структуры ровно в том виде, в котором они пишутся в файл
+pub fn to_bytes<T: OnDisk>(val: &T) -> &[u8] {
+    let raw_h: *const u8 = (val as *const T) as *const u8;
+
+    unsafe {
+        slice_from_raw_parts(raw_h, size_of::<T>()).as_ref().unwrap()
+    }
+}
+
+// Декодирование из начала buf (buf может быть не выровнен)
+pub fn from_bytes<T: OnDisk>(buf: &[u8]) -> T {
+    assert!(buf.len() >= size_of::<T>(), "Buffer is too short for {}", std::any::type_name::<T>());
+
+    unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const T) }
+}
+
+
+// Как сбрасывать данные на диск при коммите.
+// Порядок всегда такой: страницы с данными -> sync -> meta-страница -> sync, поэтому
+// meta никогда не ссылается на страницы, которые еще не дошли до диска.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SyncMode {
+    // fsync: данные и метаданные файла
+    Full,
+    // fdatasync: только данные (и размер файла)
+    Data,
+    // Без sync вообще (массовая загрузка, тесты). Сбросить на диск можно явно через DB::sync
+    None,
+}
+
+impl SyncMode {
+    pub fn sync(&self, f: &File) -> io::Result<()> {
+        match self {
+            SyncMode::Full => f.sync_all(),
+            SyncMode::Data => f.sync_data(),
+            SyncMode::None => Ok(()),
+        }
+    }
+}
+
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Meta {
+    pub magic: Le32,
+    pub version: Le32,
+    pub page_size: Le32,
+    pub root_page: Le32,
+    // Количество страниц в файле, которые уже заняты. Новые страницы выделяются после них.
+    pub page_count: Le32,
+    // Страница со списком свободных страниц. 0 - списка нет (meta всегда на страницах 0 и 1).
+    pub freelist_page: Le32,
+    // Номер последней закоммиченной транзакции. Выбирает слот meta; в nonce зашифрованных
+    // страниц не входит - там номер страницы и случайная соль записи (см. cipher).
+    pub txid: Le64,
+    // Id компаратора, по которому отсортированы ключи (см. comparator)
+    pub comparator: Le32,
+    // Флаги базы (META_*)
+    pub flags: Le32,
+}
+
+// Несколько значений на ключ (см. Options::dupsort)
+pub const META_DUPSORT: u32 = 1;
+
+impl Meta {
+    // Слот (страница), в который пишется эта meta
+    pub fn slot(&self) -> PageId {
+        self.txid.get() % META_PAGES
+    }
+
+    // Meta-страница целиком: заголовок страницы и сама Meta сразу за ним
+    pub fn to_page_bytes(&self) -> Vec<u8> {
+        let page = PageHeader {
+            id: Le64::new(self.slot()),
+            flags: Le16::new(PAGE_META),
+            inode_count: Le32::new(0),
+            page_overflow_count: Le32::new(0),
+        };
+
+        let mut buffer = to_bytes(&page).to_vec();
+        buffer.extend_from_slice(to_bytes(self));
+        buffer.extend_from_slice(&self.checksum().to_le_bytes());
+        buffer
+    }
+
+    // Обе meta-страницы нового файла: эта meta в своем слоте и она же с предыдущим txid в
+    // другом, чтобы оба слота были целыми. txid нового файла не меньше 1.
+    pub fn to_file_bytes(&self, page_size: usize) -> Vec<u8> {
+        let prev = Meta { txid: Le64::new(self.txid.get() - 1), ..*self };
+
+        let mut buffer = vec![0u8; META_PAGES as usize * page_size];
+        for meta in [prev, *self].iter() {
+            let page = meta.to_page_bytes();
+            let offset = meta.slot() as usize * page_size;
+            buffer[offset..offset + page.len()].copy_from_slice(&page);
+        }
+        buffer
+    }
+
+    // Контрольная сумма пишется на meta-странице сразу за Meta. Страницы с данными ею не
+    // покрываются: meta - единственное, что перезаписывается на месте.
+    pub fn checksum(&self) -> u32 {
+        fnv1a(to_bytes(self))
+    }
+
+    // Проверки, без которых файлу с этой meta нельзя доверять даже чтение страниц
+    pub fn validate(&self, file_len: u64) -> io::Result<()> {
+        if self.magic.get() != MAGIC {
+            return Err(invalid_data(format!("invalid magic {:#x}", self.magic.get())));
+        }
+
+        if self.version.get() != VERSION {
+            return Err(invalid_data(format!("unsupported version {}", self.version.get())));
+        }
+
+        let page_size = self.page_size.get();
+        if !page_size.is_power_of_two() || !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(invalid_data(format!("invalid page size {}", page_size)));
+        }
+
+        if (self.page_count.get() as PageId) < META_PAGES || self.page_count.get() as u64 * page_size as u64 > file_len {
+            return Err(invalid_data(format!("page_count {} does not match file size {}", self.page_count.get(), file_len)));
+        }
+
+        if self.freelist_page.get() >= self.page_count.get() {
+            return Err(invalid_data(format!("freelist page {} is out of range", self.freelist_page.get())));
+        }
+
+        if self.flags.get() & !META_DUPSORT != 0 {
+            return Err(invalid_data(format!("unknown meta flags {:#x}", self.flags.get())));
+        }
+
+        Ok(())
+    }
+}
+
+// Текущая meta файла data: из целых слотов тот, у которого больше txid. Если целых нет -
+// ошибка слота 0. page_size - размер страницы открытой базы; при открытии он еще не известен,
+// и слот 1 ищется по всем допустимым размерам, ведь слот 0 может быть порван целиком.
+pub fn read_meta(data: &[u8], page_size: Option<usize>, verify_checksums: bool) -> io::Result<Meta> {
+    let first = read_meta_slot(data, 0, page_size.unwrap_or(MIN_PAGE_SIZE as usize), verify_checksums);
+
+    let page_sizes = match page_size {
+        Some(page_size) => vec![page_size],
+        None => (MIN_PAGE_SIZE.trailing_zeros()..=MAX_PAGE_SIZE.trailing_zeros()).map(|x| 1 << x).collect(),
+    };
+    let second = page_sizes.into_iter().find_map(|x| read_meta_slot(data, 1, x, verify_checksums).ok());
+
+    match (first, second) {
+        (Ok(a), Some(b)) => Ok(if b.txid.get() > a.txid.get() { b } else { a }),
+        (Ok(a), None) => Ok(a),
+        (Err(_), Some(b)) => Ok(b),
+        (Err(e), None) => Err(e),
+    }
+}
+
+// Meta из слота slot при размере страницы page_size, со всеми проверками
+fn read_meta_slot(data: &[u8], slot: PageId, page_size: usize, verify_checksums: bool) -> io::Result<Meta> {
+    let offset = slot as usize * page_size;
+    let page = PageRef::new(data.get(offset..).unwrap_or(&[]))?;
+    let meta = page.meta()?;
+    meta.validate(data.len() as u64)?;
+
+    // Смещение слота 0 от размера страницы не зависит, у слота 1 размер должен совпасть
+    if !page.is_meta() || page.id() != slot || meta.slot() != slot || (slot > 0 && meta.page_size.get() as usize != page_size) {
+        return Err(invalid_data(format!("meta of txid {} does not belong to slot {}", meta.txid.get(), slot)));
+    }
+
+    if verify_checksums && page.meta_checksum()? != meta.checksum() {
+        return Err(invalid_data("meta checksum mismatch".to_string()));
+    }
+
+    Ok(meta)
+}
+
+// Страница freelist: заголовок и сразу за ним inode_count номеров свободных страниц (Le32)
+// по возрастанию. Возвращает страницу целиком, вместе с overflow-страницами.
+// overhead - сколько байт оставить свободными в конце страницы (см. cipher::page_overhead)
+pub fn freelist_to_page_bytes(page_id: PageId, free_pages: &[PageId], page_size: usize, overhead: usize) -> Vec<u8> {
+    let size = size_of::<PageHeader>() + free_pages.len() * size_of::<Le32>() + overhead;
+    let page_num = std::cmp::max(size.div_ceil(page_size), 1);
+
+    let page = PageHeader {
+        id: Le64::new(page_id),
+        flags: Le16::new(PAGE_FREELIST),
+        inode_count: Le32::new(free_pages.len() as u32),
+        page_overflow_count: Le32::new((page_num - 1) as u32),
+    };
+
+    let mut buffer = to_bytes(&page).to_vec();
+    for &id in free_pages.iter() {
+        buffer.extend_from_slice(&(id as u32).to_le_bytes());
+    }
+    buffer.resize(page_num * page_size, 0);
+    buffer
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BranchINodeHeader {
+    pub pos: Le32,
+    pub ksize: Le32,
+    pub page_id: Le32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LeafInodeHeader {
+    pub pos: Le32,
+    pub ksize: Le32,
+    pub vsize: Le32,
+    pub page_id: Le32,
+}
+
+pub const PAGE_LEAF: u16 = 0x01;
+pub const PAGE_BRANCH: u16 = 0x02;
+pub const PAGE_META: u16 = 0x04;
+pub const PAGE_FREELIST: u16 = 0x10;
+// Флаг листа или ветки: общий префикс ключей записан на странице один раз (см. PageRef::prefix)
+pub const PAGE_PREFIX: u16 = 0x20;
+// Флаг листа: перед каждым значением записан байт с id кодека, которым оно сжато (см. codec)
+pub const PAGE_CODEC: u16 = 0x40;
+// Страница зашифрована: заголовок открытым текстом, дальше соль записи и зашифрованное тело,
+// а в конце - тег аутентификации (см. cipher)
+pub const PAGE_ENCRYPTED: u16 = 0x80;
+
+// Page либо из mmap, либо из Vec<u8>; Это абстракция над несколькими видами памяти.
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PageHeader {
+    pub id: Le64,
+    pub flags: Le16,
+    pub inode_count: Le32,
+    pub page_overflow_count: Le32,
+}
+
+impl PageHeader {
+    pub fn type_name(&self) -> &str {
+        if self.flags.get() & PAGE_BRANCH != 0 {
+            return "branch";
+        }
+
+        if self.flags.get() & PAGE_LEAF != 0 {
+            return "leaf";
+        }
+
+        if self.flags.get() & PAGE_FREELIST != 0 {
+            return "freelist";
+        }
+
+        if self.flags.get() & PAGE_META != 0 {
+            return "meta";
+        }
+
+        "unknown"
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.flags.get() & PAGE_LEAF != 0
+    }
+
+    pub fn is_branch(&self) -> bool {
+        self.flags.get() & PAGE_BRANCH != 0
+    }
+
+    pub fn is_meta(&self) -> bool {
+        self.type_name() == "meta"
+    }
+
+    pub fn is_freelist(&self) -> bool {
+        self.type_name() == "freelist"
+    }
+
+    pub fn has_prefix(&self) -> bool {
+        self.flags.get() & PAGE_PREFIX != 0
+    }
+
+    pub fn has_codec(&self) -> bool {
+        self.flags.get() & PAGE_CODEC != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags.get() & PAGE_ENCRYPTED != 0
+    }
+}
+
+
+impl fmt::Display for PageHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} page: {:?}", self.type_name(), self)?;
+
+        fmt::Result::Ok(())
+    }
+}
+
+
+// Длина общего префикса, который стоит вынести на страницу с count ключами. Ключи
+// отсортированы, поэтому общий префикс всех - это общий префикс первого и последнего.
+// Префикс, который не экономит места, не выносится: сжатая страница никогда не больше
+// обычной.
+pub fn page_prefix_len(count: usize, first: &[u8], last: &[u8]) -> usize {
+    let len = first.iter().zip(last.iter()).take_while(|(a, b)| a == b).count();
+    if prefix_size(len) < count * len {
+        len
+    } else {
+        0
+    }
+}
+
+// Сколько байт страницы занимает общий префикс ключей: длина (Le32) и сами байты. Пустой
+// префикс не пишется вовсе, страница остается в обычном формате.
+pub fn prefix_size(len: usize) -> usize {
+    if len == 0 {
+        0
+    } else {
+        size_of::<u32>() + len
+    }
+}
+
+pub fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// FNV-1a: простая хеш-функция без зависимостей, для контрольной суммы meta хватает
+fn fnv1a(buf: &[u8]) -> u32 {
+    buf.iter().fold(0x811c9dc5u32, |hash, &x| (hash ^ x as u32).wrapping_mul(0x01000193))
+}
+
+// Inode листа, прочитанный из страницы. value указывает прямо в байты страницы в mmap, key -
+// тоже, если у страницы нет общего префикса. Иначе key собирается из префикса и суффикса.
+// У расшифрованной страницы (см. PageRef::owned) и то и другое - копии.
+// value - значение в том виде, в каком оно записано: сжатое кодеком codec.
+#[derive(Debug, Clone)]
+pub struct LeafINode<'a> {
+    pub header: LeafInodeHeader,
+    pub key: Cow<'a, [u8]>,
+    pub codec: u8,
+    pub value: Cow<'a, [u8]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BranchINode<'a> {
+    pub header: BranchINodeHeader,
+    pub key: Cow<'a, [u8]>,
+    pub page_id: PageId,
+}
+
+// Байты страницы: прямо в mmap или расшифрованная копия. Копию держит каждый, кто читает
+// страницу, поэтому кеш расшифрованных страниц может вытеснить ее в любой момент.
+#[derive(Clone)]
+enum PageBuf<'a> {
+    Mapped(&'a [u8]),
+    Owned(Arc<[u8]>),
+}
+
+// Страница файла: байты страницы вместе с overflow-страницами и разобранный заголовок.
+// Все чтения ограничены buf, поэтому битая страница дает ошибку, а не чтение чужой памяти.
+#[derive(Clone)]
+pub struct PageRef<'a> {
+    buf: PageBuf<'a>,
+    header: PageHeader,
+}
+
+impl<'a> PageRef<'a> {
+    pub fn new(buf: &'a [u8]) -> io::Result<PageRef<'a>> {
+        PageRef::with_buf(PageBuf::Mapped(buf))
+    }
+
+    // Страница не из mmap (расшифрованная). Ключи и значения с нее копируются.
+    pub fn owned(buf: Arc<[u8]>) -> io::Result<PageRef<'a>> {
+        PageRef::with_buf(PageBuf::Owned(buf))
+    }
+
+    fn with_buf(buf: PageBuf<'a>) -> io::Result<PageRef<'a>> {
+        let header = match &buf {
+            PageBuf::Mapped(x) => x.get(..size_of::<PageHeader>()).map(from_bytes),
+            PageBuf::Owned(x) => x.get(..size_of::<PageHeader>()).map(from_bytes),
+        };
+
+        match header {
+            Some(header) => Ok(PageRef { buf, header }),
+            None => Err(invalid_data(format!("page of {} bytes is too short for header", PageRef::buf_len(&buf)))),
+        }
+    }
+
+    fn buf_len(buf: &PageBuf<'_>) -> usize {
+        match buf {
+            PageBuf::Mapped(x) => x.len(),
+            PageBuf::Owned(x) => x.len(),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match &self.buf {
+            PageBuf::Mapped(x) => x,
+            PageBuf::Owned(x) => x,
+        }
+    }
+
+    pub fn into_bytes(self) -> Cow<'a, [u8]> {
+        match self.buf {
+            PageBuf::Mapped(x) => Cow::Borrowed(x),
+            PageBuf::Owned(x) => Cow::Owned(x.to_vec()),
+        }
+    }
+
+    pub fn id(&self) -> PageId {
+        self.header.id.get()
+    }
+
+    pub fn count(&self) -> usize {
+        self.header.inode_count.get() as usize
+    }
+
+    pub fn meta(&self) -> io::Result<Meta> {
+        Ok(from_bytes(self.range(size_of::<PageHeader>(), size_of::<Meta>())?))
+    }
+
+    // Контрольная сумма, записанная на meta-странице (см. Meta::checksum)
+    pub fn meta_checksum(&self) -> io::Result<u32> {
+        let buf = self.range(size_of::<PageHeader>() + size_of::<Meta>(), size_of::<u32>())?;
+        Ok(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]))
+    }
+
+    // Номера свободных страниц со страницы freelist
+    pub fn freelist(&self) -> io::Result<Vec<PageId>> {
+        if !self.is_freelist() {
+            return Err(invalid_data(format!("page {} is {}, not freelist", self.id(), self.type_name())));
+        }
+
+        let buf = self.range(size_of::<PageHeader>(), self.count().saturating_mul(size_of::<u32>()))?;
+        Ok(buf.chunks(size_of::<u32>())
+            .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as PageId)
+            .collect())
+    }
+
+    // Общий префикс ключей страницы с PAGE_PREFIX. Лежит сразу за заголовком страницы:
+    // длина (Le32) и байты префикса, заголовки inode идут после него, а ksize в них - длина
+    // суффикса. У страниц без флага префикс пустой.
+    pub fn prefix(&self) -> io::Result<&[u8]> {
+        if !self.has_prefix() {
+            return Ok(&[]);
+        }
+
+        let buf = self.range(size_of::<PageHeader>(), size_of::<u32>())?;
+        let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        self.range(size_of::<PageHeader>() + size_of::<u32>(), len)
+    }
+
+    pub fn leaf_inode(&self, idx: usize) -> io::Result<LeafINode<'a>> {
+        if !self.is_leaf() {
+            return Err(invalid_data(format!("page {} is {}, not leaf", self.id(), self.type_name())));
+        }
+
+        let offset = self.inode_offset::<LeafInodeHeader>(idx)?;
+        let header: LeafInodeHeader = from_bytes(self.range(offset, size_of::<LeafInodeHeader>())?);
+        let (pos, ksize, vsize) = (header.pos.get() as usize, header.ksize.get() as usize, header.vsize.get() as usize);
+
+        let key = self.key(offset + pos, ksize)?;
+
+        // Байт кодека лежит между ключом и значением и в vsize не входит
+        let (codec, codec_size) = if self.has_codec() {
+            (self.range(offset + pos + ksize, 1)?[0], 1)
+        } else {
+            (CODEC_NONE, 0)
+        };
+
+        Ok(LeafINode {
+            header,
+            key,
+            codec,
+            value: self.slice(offset + pos + ksize + codec_size, vsize)?,
+        })
+    }
+
+    pub fn branch_inode(&self, idx: usize) -> io::Result<BranchINode<'a>> {
+        if !self.is_branch() {
+            return Err(invalid_data(format!("page {} is {}, not branch", self.id(), self.type_name())));
+        }
+
+        let offset = self.inode_offset::<BranchINodeHeader>(idx)?;
+        let header: BranchINodeHeader = from_bytes(self.range(offset, size_of::<BranchINodeHeader>())?);
+        let (pos, ksize) = (header.pos.get() as usize, header.ksize.get() as usize);
+
+        Ok(BranchINode {
+            header,
+            key: self.key(offset + pos, ksize)?,
+            page_id: header.page_id.get() as PageId,
+        })
+    }
+
+    pub fn leaf_inodes(&self) -> io::Result<Vec<LeafINode<'a>>> {
+        (0..self.count()).map(|idx| self.leaf_inode(idx)).collect()
+    }
+
+    pub fn branch_inodes(&self) -> io::Result<Vec<BranchINode<'a>>> {
+        (0..self.count()).map(|idx| self.branch_inode(idx)).collect()
+    }
+
+    fn inode_offset<T: OnDisk>(&self, idx: usize) -> io::Result<usize> {
+        if idx >= self.count() {
+            return Err(invalid_data(format!("inode {} is out of range on page {} with {} inodes", idx, self.id(), self.count())));
+        }
+
+        let prefix_area = if self.has_prefix() { size_of::<u32>() + self.prefix()?.len() } else { 0 };
+        Ok(size_of::<PageHeader>() + prefix_area + idx * size_of::<T>())
+    }
+
+    // Ключ из общего префикса страницы и суффикса по смещению offset
+    fn key(&self, offset: usize, len: usize) -> io::Result<Cow<'a, [u8]>> {
+        let prefix = self.prefix()?;
+        if prefix.is_empty() {
+            self.slice(offset, len)
+        } else {
+            Ok(Cow::Owned([prefix, self.range(offset, len)?].concat()))
+        }
+    }
+
+    // Байты страницы, которые переживут PageRef: ссылка в mmap или копия
+    fn slice(&self, offset: usize, len: usize) -> io::Result<Cow<'a, [u8]>> {
+        let range = self.range(offset, len)?;
+        Ok(match self.buf {
+            PageBuf::Mapped(buf) => Cow::Borrowed(&buf[offset..offset + range.len()]),
+            PageBuf::Owned(_) => Cow::Owned(range.to_vec()),
+        })
+    }
+
+    fn range(&self, offset: usize, len: usize) -> io::Result<&[u8]> {
+        offset.checked_add(len)
+            .and_then(|end| self.bytes().get(offset..end))
+            .ok_or_else(|| invalid_data(format!("range {}+{} is out of bounds of page {} ({} bytes)",
+                                                offset, len, self.id(), self.bytes().len())))
+    }
+}
+
+impl Deref for PageRef<'_> {
+    type Target = PageHeader;
+
+    fn deref(&self) -> &PageHeader {
+        &self.header
+    }
+}
+
+impl fmt::Debug for PageRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.header, f)
+    }
+}
+
+impl fmt::Display for PageRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.header, f)
+    }
+}
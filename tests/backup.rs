@@ -0,0 +1,99 @@
+// Горячий бэкап (Tx::write_to, Tx::copy_file): копия, снятая во время коммитов, - целая
+// база ровно на одном из закоммиченных состояний.
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use rust_apps::db::DB;
+use rust_apps::types::str_to_key;
+
+mod common;
+use common::{options, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 512;
+const KEYS: u32 = 50;
+const ROUNDS: u8 = 40;
+
+fn create(path: &str) -> DB {
+    DB::create(path, options(PAGE_SIZE)).unwrap()
+}
+
+fn key(k: u32) -> [u8; 32] {
+    str_to_key(&format!("{:04}", k))
+}
+
+// Каждый коммит переписывает все ключи номером раунда
+fn write_round(db: &DB, round: u8) {
+    db.update(|tx| {
+        for k in 0..KEYS {
+            tx.put(key(k), vec![round; 16])?;
+        }
+        Ok(())
+    }).unwrap();
+}
+
+// Все ключи из одного коммита
+fn assert_consistent(db: &DB) -> u8 {
+    let round = db.get(key(0)).unwrap().unwrap()[0];
+    for k in 0..KEYS {
+        assert_eq!(db.get(key(k)).unwrap().as_deref(), Some(&[round; 16][..]), "key {}", k);
+    }
+    round
+}
+
+#[test]
+fn write_to_buffer() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "buffer");
+    let db = create(&path);
+    write_round(&db, 1);
+
+    let mut buf = vec![];
+    let mut len = 0;
+    db.view(|tx| {
+        len = tx.write_to(&mut buf)?;
+        Ok(())
+    }).unwrap();
+    assert_eq!(len, buf.len() as u64);
+    assert_eq!(len, db.meta().page_count.get() as u64 * PAGE_SIZE as u64);
+
+    let copy = temp_path(&dir, "buffer-copy");
+    fs::write(&copy, &buf).unwrap();
+    assert_eq!(assert_consistent(&DB::open(&copy).unwrap()), 1);
+}
+
+#[test]
+fn copy_during_commits() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "commits");
+    let db = create(&path);
+    write_round(&db, 0);
+
+    let done = AtomicBool::new(false);
+    let copies = thread::scope(|s| {
+        s.spawn(|| {
+            for round in 1..=ROUNDS {
+                write_round(&db, round);
+            }
+            done.store(true, Ordering::Release);
+        });
+
+        // Копии снимаются, пока пишущий поток коммитит
+        let mut copies = vec![];
+        while !done.load(Ordering::Acquire) || copies.is_empty() {
+            let copy = temp_path(&dir, &format!("commits-copy{}", copies.len()));
+            db.view(|tx| tx.copy_file(&copy).map(|_| ())).unwrap();
+            copies.push(copy);
+        }
+        copies
+    });
+
+    // Раунды копий не убывают: каждый снимок не старше предыдущего
+    let mut last = 0;
+    for copy in &copies {
+        let round = assert_consistent(&DB::open(copy).unwrap());
+        assert!(round >= last, "{} after {}", round, last);
+        last = round;
+    }
+    assert_eq!(assert_consistent(&db), ROUNDS);
+}
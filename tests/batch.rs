@@ -0,0 +1,158 @@
+// DB::batch: вызовы из разных потоков сливаются в одну транзакцию (ее выполняет поток-лидер)
+// и записываются одним коммитом, функция с ошибкой выполняется отдельно и возвращает свою
+// ошибку, паника не ломает следующие записи.
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use rust_apps::db::DB;
+use rust_apps::types::str_to_key;
+use tempfile::TempDir;
+
+mod common;
+use common::{options, temp_dir, temp_path};
+
+const THREADS: usize = 8;
+const PAGE_SIZE: usize = 4096;
+
+fn create(dir: &TempDir, name: &str) -> DB {
+    let mut db = DB::create(&temp_path(dir, name), options(PAGE_SIZE)).unwrap();
+
+    // Пачка закрывается, когда соберутся все потоки, задержка - только страховка
+    db.max_batch_size = THREADS;
+    db.max_batch_delay = Duration::from_secs(5);
+    db
+}
+
+fn value(db: &DB, key: &str) -> Option<Vec<u8>> {
+    db.get(str_to_key(key)).unwrap().map(|x| x.to_vec())
+}
+
+// Каждый вызов функции записывает поток, в котором она выполнялась
+type Runs = Arc<Mutex<Vec<(usize, ThreadId)>>>;
+
+fn run(runs: &Runs, t: usize) {
+    runs.lock().unwrap().push((t, thread::current().id()));
+}
+
+#[test]
+fn merges_callers() {
+    let dir = temp_dir();
+    let db = create(&dir, "merge");
+    let runs = Runs::default();
+
+    let barrier = Barrier::new(THREADS);
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let (db, barrier, runs) = (&db, &barrier, runs.clone());
+            s.spawn(move || {
+                barrier.wait();
+                db.batch(move |tx| {
+                    run(&runs, t);
+                    tx.put(str_to_key(&t.to_string()), vec![t as u8])?;
+                    Ok(())
+                }).unwrap();
+            });
+        }
+    });
+
+    // Все вызовы выполнены по разу одним потоком, то есть в одной транзакции
+    let runs = runs.lock().unwrap();
+    assert_eq!(runs.len(), THREADS);
+    assert!(runs.iter().all(|x| x.1 == runs[0].1));
+    for t in 0..THREADS {
+        assert_eq!(value(&db, &t.to_string()), Some(vec![t as u8]));
+    }
+}
+
+#[test]
+fn failed_call_runs_alone() {
+    let dir = temp_dir();
+    let db = create(&dir, "failed");
+    let runs = Runs::default();
+
+    let barrier = Barrier::new(THREADS);
+    let results: Vec<(ThreadId, io::Result<()>)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..THREADS).map(|t| {
+            let (db, barrier, runs) = (&db, &barrier, runs.clone());
+            s.spawn(move || {
+                barrier.wait();
+                let ret = db.batch(move |tx| {
+                    run(&runs, t);
+                    tx.put(str_to_key(&t.to_string()), vec![t as u8])?;
+                    if t == 3 {
+                        return Err(io::Error::new(ErrorKind::InvalidInput, "three"));
+                    }
+                    Ok(())
+                });
+                (thread::current().id(), ret)
+            })
+        }).collect();
+        handles.into_iter().map(|x| x.join().unwrap()).collect()
+    });
+
+    // Ошибка дошла только до своего вызова, его запись откатилась, остальные записаны
+    for (t, (_, ret)) in results.iter().enumerate() {
+        match ret {
+            Err(e) if t == 3 => assert_eq!((e.kind(), e.to_string().as_str()), (ErrorKind::InvalidInput, "three")),
+            Ok(()) if t != 3 => assert_eq!(value(&db, &t.to_string()), Some(vec![t as u8])),
+            _ => panic!("call {}: {:?}", t, ret),
+        }
+    }
+    assert_eq!(value(&db, "3"), None);
+
+    // Последний раз сломавшая пачку функция выполнена отдельно, в своем потоке
+    let runs = runs.lock().unwrap();
+    let last = runs.iter().rev().find(|x| x.0 == 3).unwrap();
+    assert_eq!(last.1, results[3].0);
+    let leader = runs.iter().find(|x| x.0 != 3).unwrap().1;
+    assert!(runs.iter().filter(|x| x.0 != 3).all(|x| x.1 == leader));
+}
+
+#[test]
+fn panic_does_not_poison() {
+    let dir = temp_dir();
+    let db = create(&dir, "panic");
+
+    let barrier = Barrier::new(THREADS);
+    let panicked: Vec<bool> = thread::scope(|s| {
+        let handles: Vec<_> = (0..THREADS).map(|t| {
+            let (db, barrier) = (&db, &barrier);
+            s.spawn(move || {
+                barrier.wait();
+                db.batch(move |tx| {
+                    if t == 5 {
+                        panic!("five");
+                    }
+                    tx.put(str_to_key(&t.to_string()), vec![t as u8])?;
+                    Ok(())
+                }).unwrap();
+            })
+        }).collect();
+        handles.into_iter().map(|x| x.join().is_err()).collect()
+    });
+
+    // Паника доходит только до своего потока, остальные вызовы записаны
+    for (t, &panicked) in panicked.iter().enumerate() {
+        assert_eq!(panicked, t == 5, "call {}", t);
+        assert_eq!(value(&db, &t.to_string()), if t == 5 { None } else { Some(vec![t as u8]) });
+    }
+
+    // И update, и следующая пачка по-прежнему работают
+    db.update(|tx| {
+        tx.put(str_to_key("after"), vec![1])?;
+        Ok(())
+    }).unwrap();
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let db = &db;
+            s.spawn(move || db.batch(move |tx| {
+                tx.put(str_to_key(&format!("next{}", t)), vec![2])?;
+                Ok(())
+            }).unwrap());
+        }
+    });
+    assert_eq!(value(&db, "after"), Some(vec![1]));
+    assert_eq!(value(&db, "next7"), Some(vec![2]));
+}
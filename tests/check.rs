@@ -0,0 +1,122 @@
+// Проверка целостности: чистая база проходит без нарушений, а испорченные страницы и
+// брошенные коммитами страницы находятся и описываются.
+use std::fs;
+
+use rust_apps::check::check;
+use rust_apps::compact::compact;
+use rust_apps::db::DB;
+use rust_apps::types::{Le32, Meta, PageId, str_to_key};
+
+mod common;
+use common::{assert_checked, options, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 4096;
+
+fn create(path: &str) -> DB {
+    DB::create(path, options(PAGE_SIZE)).unwrap()
+}
+
+// База с ключами 1..=count, сжатая, чтобы в ней не осталось брошенных страниц
+fn filled(path: &str, count: u32) {
+    let db = create(path);
+    db.update(|tx| {
+        for k in 1..=count {
+            tx.put(str_to_key(&k.to_string()), vec![k as u8; 10])?;
+        }
+        Ok(())
+    }).unwrap();
+    drop(db);
+
+    compact(path).unwrap();
+}
+
+// Meta переписывается целиком в свой слот, с новой контрольной суммой
+fn write_meta(path: &str, meta: &Meta) {
+    let mut data = fs::read(path).unwrap();
+    let offset = meta.slot() as usize * PAGE_SIZE;
+    let bytes = meta.to_page_bytes();
+    data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    fs::write(path, data).unwrap();
+}
+
+fn violations(path: &str) -> Vec<(PageId, String)> {
+    let db = DB::open(path).unwrap();
+    check(&db).unwrap().into_iter().map(|v| (v.page_id, v.message)).collect()
+}
+
+fn has(violations: &[(PageId, String)], page_id: PageId, message: &str) -> bool {
+    violations.iter().any(|(id, m)| *id == page_id && m.contains(message))
+}
+
+#[test]
+fn compacted_database_is_clean() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "clean");
+    filled(&path, 50);
+
+    assert_eq!(violations(&path), vec![]);
+}
+
+// Страницы, замененные коммитами, лежат в freelist и нарушением не считаются
+#[test]
+fn committed_database_is_clean() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "commits");
+    let db = create(&path);
+    for round in 0..3u8 {
+        for k in (0..50).step_by(7) {
+            db.update(|tx| tx.put(str_to_key(&k.to_string()), vec![round; 10])).unwrap();
+        }
+    }
+    assert!(!db.freelist().unwrap().is_empty());
+    assert_checked(&db);
+}
+
+// Без freelist брошенные страницы ничейные
+#[test]
+fn lost_pages() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "lost");
+    let db = create(&path);
+    db.update(|tx| tx.put(str_to_key("1"), vec![1])).unwrap();
+    let free = db.freelist().unwrap();
+    let meta = Meta { freelist_page: Le32::new(0), ..db.meta() };
+    drop(db);
+
+    write_meta(&path, &meta);
+
+    let found = violations(&path);
+    for page_id in free {
+        assert!(has(&found, page_id, "page is neither reachable nor free"), "{:?}", found);
+    }
+}
+
+#[test]
+fn unsorted_keys() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "unsorted");
+    filled(&path, 3);
+
+    // Ключ "2" становится "0" и оказывается меньше предыдущего "1"
+    let mut data = fs::read(&path).unwrap();
+    let (from, to) = (str_to_key("2"), str_to_key("0"));
+    let pos = data.windows(from.len()).position(|w| w == from).unwrap();
+    data[pos..pos + to.len()].copy_from_slice(&to);
+    fs::write(&path, data).unwrap();
+
+    let found = violations(&path);
+    assert!(found.iter().any(|(_, m)| m.contains("is not greater than previous key")), "{:?}", found);
+}
+
+#[test]
+fn root_out_of_range() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "root");
+    filled(&path, 3);
+
+    let meta = DB::open(&path).unwrap().meta();
+    write_meta(&path, &Meta { root_page: Le32::new(100), ..meta });
+
+    let found = violations(&path);
+    assert!(has(&found, meta.slot(), "root page 100 is out of range"), "{:?}", found);
+}
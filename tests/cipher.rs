@@ -0,0 +1,341 @@
+// Шифрование страниц: коммиты, save_tree и compact с шифром из Options, чтение без шифра
+// и с чужим ключом, базы, где зашифрованные страницы соседствуют с открытыми.
+use std::fs;
+use std::io::ErrorKind;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use rust_apps::check::check;
+use rust_apps::cipher::{self, CipherKey, PageCipher, TestCipher};
+use rust_apps::compact::{compact, compact_with};
+use rust_apps::db::{DB, Options};
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::{key_to_str, PageHeader, PageId, str_to_key};
+
+mod common;
+use common::{assert_checked, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 512;
+const COUNT: u32 = 500;
+
+fn plain() -> Options {
+    common::options(PAGE_SIZE)
+}
+
+fn encrypted(key: &[u8]) -> Options {
+    Options { cipher: Some(Arc::new(TestCipher)), cipher_key: CipherKey(key.to_vec()), ..plain() }
+}
+
+// Значение, которое легко найти в сырых байтах файла
+fn value(k: u32) -> Vec<u8> {
+    format!("secret-value-{:05}", k).into_bytes()
+}
+
+fn commit(db: &DB) {
+    for chunk in (0..COUNT).collect::<Vec<_>>().chunks(20) {
+        db.update(|tx| {
+            for &k in chunk.iter() {
+                tx.put(str_to_key(&k.to_string()), value(k))?;
+            }
+            Ok(())
+        }).unwrap();
+    }
+}
+
+fn assert_contents(db: &DB) {
+    assert_checked(db);
+
+    for k in 0..COUNT {
+        assert_eq!(db.get(str_to_key(&k.to_string())).unwrap().as_deref(), Some(value(k).as_slice()), "key {}", k);
+    }
+
+    let mut cursor = db.cursor();
+    let mut item = cursor.first().unwrap();
+    let mut count = 0;
+    while let Some((key, v)) = item {
+        let k: u32 = key_to_str(&key).parse().unwrap();
+        assert_eq!(v, value(k));
+        count += 1;
+        item = cursor.next().unwrap();
+    }
+    assert_eq!(count, COUNT);
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|x| x == needle)
+}
+
+// Флаги шифрования страниц, достижимых из корня, прочитанные прямо из файла
+fn encrypted_flags(path: &str, db: &DB, page_id: PageId, flags: &mut Vec<bool>) {
+    let raw = fs::read(path).unwrap();
+    let header: PageHeader = rust_apps::types::from_bytes(&raw[page_id as usize * PAGE_SIZE..]);
+    flags.push(header.is_encrypted());
+
+    let page = db.page(page_id).unwrap();
+    if page.is_branch() {
+        for inode in page.branch_inodes().unwrap() {
+            encrypted_flags(path, db, inode.page_id, flags);
+        }
+    }
+}
+
+fn tree_flags(path: &str, db: &DB) -> Vec<bool> {
+    let mut flags = vec![];
+    encrypted_flags(path, db, db.meta().root_page.get() as PageId, &mut flags);
+    flags
+}
+
+#[test]
+fn seal_and_open() {
+    let key = b"key";
+    let overhead = cipher::page_overhead(Some(&TestCipher));
+    assert_eq!(overhead, cipher::SALT_SIZE + cipher::TAG_SIZE);
+
+    let mut page = vec![0u8; PAGE_SIZE];
+    page[..size_of::<PageHeader>()].copy_from_slice(&[7, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    for (idx, byte) in page[size_of::<PageHeader>()..PAGE_SIZE - overhead].iter_mut().enumerate() {
+        *byte = idx as u8;
+    }
+    let original = page.clone();
+
+    cipher::seal(Some(&TestCipher), key, &mut page, 0x0102030405060708);
+    let header: PageHeader = rust_apps::types::from_bytes(&page);
+    assert!(header.is_encrypted());
+    assert_eq!(header.id.get(), 7);
+    assert_eq!(header.inode_count.get(), 1);
+    assert_eq!(&page[18..26], &[0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+    assert!(!contains(&page, &original[40..60]));
+
+    let opened = cipher::open(&TestCipher, key, 7, &page).unwrap();
+    assert_eq!(opened.len(), PAGE_SIZE - overhead);
+    assert_eq!(&opened[size_of::<PageHeader>()..], &original[size_of::<PageHeader>()..PAGE_SIZE - overhead]);
+
+    // Тег не сходится, если испорчено тело, заголовок или тег, или ключ не тот
+    for offset in [40, 9, PAGE_SIZE - 1] {
+        let mut corrupted = page.clone();
+        corrupted[offset] ^= 1;
+        assert_eq!(cipher::open(&TestCipher, key, 7, &corrupted).unwrap_err().kind(), ErrorKind::InvalidData, "offset {}", offset);
+    }
+    assert_eq!(cipher::open(&TestCipher, b"other key", 7, &page).unwrap_err().kind(), ErrorKind::InvalidData);
+
+    // Без шифра страница не меняется
+    let mut page = original.clone();
+    cipher::seal(None, key, &mut page, 1);
+    assert_eq!(page, original);
+
+    // Nonce у страниц с разными номерами или солью разный, соль каждый раз новая
+    assert_ne!(cipher::nonce(7, 1), cipher::nonce(8, 1));
+    assert_ne!(cipher::nonce(7, 1), cipher::nonce(7, 2));
+    assert_ne!(cipher::salt(Some(&TestCipher)).unwrap(), cipher::salt(Some(&TestCipher)).unwrap());
+    assert_eq!(cipher::salt(None).unwrap(), 0);
+}
+
+// Коммит, не дошедший до meta, оставляет свой txid и номера страниц следующему коммиту.
+// Страницы, записанные повторно, шифруются с другим nonce.
+#[test]
+fn lost_commit_does_not_reuse_nonce() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "lost");
+    let db = DB::create(&path, encrypted(b"key")).unwrap();
+    commit(&db);
+    drop(db);
+    let before = fs::read(&path).unwrap();
+
+    let rewrite = || {
+        let db = DB::open_with(&path, encrypted(b"key")).unwrap();
+        db.update(|tx| tx.put(str_to_key("1"), b"new".to_vec())).unwrap();
+        let root = db.meta().root_page.get() as usize;
+        drop(db);
+        (root, fs::read(&path).unwrap())
+    };
+
+    let (root, lost) = rewrite();
+    // Откатываем файл к состоянию до коммита: как будто meta так и не записалась
+    fs::write(&path, &before).unwrap();
+    let (same_root, written) = rewrite();
+    assert_eq!(root, same_root);
+
+    let salt = |file: &[u8]| file[root * PAGE_SIZE + size_of::<PageHeader>()..][..cipher::SALT_SIZE].to_vec();
+    assert_ne!(salt(&lost), salt(&written));
+    assert_contents_except_1(&DB::open_with(&path, encrypted(b"key")).unwrap());
+}
+
+fn assert_contents_except_1(db: &DB) {
+    assert_checked(db);
+    assert_eq!(db.get(str_to_key("1")).unwrap().as_deref(), Some(&b"new"[..]));
+    assert_eq!(db.get(str_to_key("2")).unwrap().as_deref(), Some(value(2).as_slice()));
+}
+
+#[test]
+fn commits_encrypt_pages() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "commit");
+    let db = DB::create(&path, encrypted(b"key")).unwrap();
+    commit(&db);
+    assert!(db.meta().txid.get() >= (COUNT / 20) as u64);
+    assert!(tree_flags(&path, &db).iter().all(|&x| x));
+    assert_contents(&db);
+    drop(db);
+
+    let raw = fs::read(&path).unwrap();
+    assert!(!contains(&raw, b"secret-value"));
+
+    let db = DB::open_with(&path, encrypted(b"key")).unwrap();
+    assert_contents(&db);
+}
+
+// Без шифра зашифрованные страницы не читаются, с чужим ключом не сходится тег
+#[test]
+fn wrong_cipher_or_key() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "wrong");
+    let db = DB::create(&path, encrypted(b"key")).unwrap();
+    commit(&db);
+    drop(db);
+
+    let db = DB::open_with(&path, plain()).unwrap();
+    assert_eq!(db.get(str_to_key("1")).unwrap_err().kind(), ErrorKind::Unsupported);
+    drop(db);
+
+    let db = DB::open_with(&path, encrypted(b"other key")).unwrap();
+    assert_eq!(db.get(str_to_key("1")).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(!check(&db).unwrap().is_empty());
+}
+
+// Шифр можно включить на существующей базе: новые страницы шифруются, старые читаются как были
+#[test]
+fn mixed_pages() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "mixed");
+    let db = DB::create(&path, plain()).unwrap();
+    commit(&db);
+    drop(db);
+
+    let db = DB::open_with(&path, encrypted(b"key")).unwrap();
+    db.update(|tx| tx.put(str_to_key("0"), value(0))).unwrap();
+    let flags = tree_flags(&path, &db);
+    assert!(flags.contains(&true) && flags.contains(&false), "{:?}", flags);
+    assert_contents(&db);
+    drop(db);
+
+    // compact с шифром шифрует все страницы
+    compact_with(&path, &encrypted(b"key")).unwrap();
+    let db = DB::open_with(&path, encrypted(b"key")).unwrap();
+    assert!(tree_flags(&path, &db).iter().all(|&x| x));
+    assert_contents(&db);
+}
+
+#[test]
+fn compact_keeps_txid_growing() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "compact");
+    let db = DB::create(&path, encrypted(b"key")).unwrap();
+    commit(&db);
+    let txid = db.meta().txid.get();
+    drop(db);
+
+    compact_with(&path, &encrypted(b"key")).unwrap();
+    let db = DB::open_with(&path, encrypted(b"key")).unwrap();
+    assert_eq!(db.meta().txid.get(), txid + 1);
+    assert_contents(&db);
+    assert!(!contains(&fs::read(&path).unwrap(), b"secret-value"));
+    drop(db);
+
+    // compact без шифра не может прочитать исходный файл
+    assert_eq!(compact(&path).unwrap_err().kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn save_tree_encrypts_pages() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "tree");
+    let options = encrypted(b"key");
+    let mut tree = BPlusTree::with_options(&options);
+    for k in 0..COUNT {
+        tree.add(str_to_key(&k.to_string()), value(k));
+    }
+    save_tree(&tree, &path, &options).unwrap();
+    assert!(!contains(&fs::read(&path).unwrap(), b"secret-value"));
+
+    let db = DB::open_with(&path, options.clone()).unwrap();
+    assert_eq!(db.meta().txid.get(), 1);
+    assert!(tree_flags(&path, &db).iter().all(|&x| x));
+    assert_contents(&db);
+    drop(db);
+
+    assert_eq!(BPlusTree::load(&path).err().unwrap().kind(), ErrorKind::Unsupported);
+    let tree = BPlusTree::load_with(&path, &options).unwrap();
+    assert_eq!(tree.get(str_to_key("5")), Some(&value(5)));
+}
+
+#[test]
+fn key_is_required_and_hidden() {
+    let dir = temp_dir();
+    let err = DB::create(&temp_path(&dir, "no-key"), encrypted(b"")).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let debug = format!("{:?}", encrypted(b"very-secret-key"));
+    assert!(!debug.contains("very-secret-key"), "{}", debug);
+    assert!(debug.contains("CipherKey(15 bytes)"), "{}", debug);
+}
+
+// Свой шифр из Options вызывается при записи и чтении страниц
+#[test]
+fn custom_cipher() {
+    #[derive(Debug)]
+    struct Not;
+
+    impl PageCipher for Not {
+        fn encrypt(&self, _key: &[u8], _nonce: &[u8; cipher::NONCE_SIZE], _aad: &[u8], data: &mut [u8]) -> [u8; cipher::TAG_SIZE] {
+            data.iter_mut().for_each(|x| *x = !*x);
+            [0xAA; cipher::TAG_SIZE]
+        }
+
+        fn decrypt(&self, key: &[u8], nonce: &[u8; cipher::NONCE_SIZE], aad: &[u8], data: &mut [u8], tag: &[u8; cipher::TAG_SIZE]) -> bool {
+            self.encrypt(key, nonce, aad, data) == *tag
+        }
+    }
+
+    let dir = temp_dir();
+    let path = temp_path(&dir, "custom");
+    let options = Options { cipher: Some(Arc::new(Not)), ..encrypted(b"key") };
+    let db = DB::create(&path, options.clone()).unwrap();
+    commit(&db);
+    drop(db);
+
+    let raw = fs::read(&path).unwrap();
+    assert!(contains(&raw, &value(1).iter().map(|x| !x).collect::<Vec<_>>()));
+    assert_contents(&DB::open_with(&path, options).unwrap());
+}
+
+// Расшифрованных страниц больше, чем держит кеш: вытесненные страницы читаются заново,
+// а значения, полученные раньше, остаются целыми
+#[test]
+fn more_pages_than_cache() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "cache");
+    let db = DB::create(&path, encrypted(b"key")).unwrap();
+    let count = 8000;
+    for chunk in (0..count).collect::<Vec<u32>>().chunks(1000) {
+        db.update(|tx| {
+            for &k in chunk.iter() {
+                tx.put(str_to_key(&k.to_string()), value(k))?;
+            }
+            Ok(())
+        }).unwrap();
+    }
+
+    let first = db.get(str_to_key("0")).unwrap().unwrap();
+    let mut cursor = db.cursor();
+    let mut item = cursor.first().unwrap();
+    let mut seen = 0;
+    while let Some((key, v)) = item {
+        assert_eq!(v, value(key_to_str(&key).parse().unwrap()));
+        seen += 1;
+        item = cursor.next().unwrap();
+    }
+    assert_eq!(seen, count);
+    assert!(db.stats().unwrap().leaf.count > 1024);
+    assert_eq!(first, value(0));
+    assert_eq!(db.get(str_to_key("0")).unwrap().unwrap(), value(0));
+}
@@ -0,0 +1,280 @@
+// Сжатие значений кодеком: встроенный Lz, свой кодек из Options, коммиты, save_tree,
+// compact и базы, где сжатые значения соседствуют с несжатыми.
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+use proptest::prelude::*;
+
+use rust_apps::check::check;
+use rust_apps::codec::{Codec, CODEC_LZ, CODEC_NONE, Lz};
+use rust_apps::compact::compact;
+use rust_apps::db::{DB, Options};
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::{invalid_data, key_to_str, PageId, str_to_key};
+
+mod common;
+use common::{assert_checked, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 4096;
+const COUNT: u32 = 300;
+
+fn options(codec: Option<Arc<dyn Codec>>) -> Options {
+    Options { codec, ..common::options(PAGE_SIZE) }
+}
+
+fn lz() -> Option<Arc<dyn Codec>> {
+    Some(Arc::new(Lz))
+}
+
+// JSON, похожий на настоящий: одни и те же поля, разные значения
+fn json(k: u32) -> Vec<u8> {
+    let items: Vec<String> = (0..30)
+        .map(|x| format!("{{\"id\":{},\"name\":\"item-{}\",\"description\":\"regular item of the default catalog\",\
+                          \"tags\":[\"alpha\",\"beta\"],\"price\":{{\"amount\":{},\"currency\":\"EUR\"}},\"active\":{}}}",
+                         k * 10 + x, x, x * 100, x % 2 == 0))
+        .collect();
+    format!("{{\"user\":{},\"items\":[{}]}}", k, items.join(",")).into_bytes()
+}
+
+// Кодек не из библиотеки: серии одинаковых байт парами (длина, байт)
+#[derive(Debug)]
+struct Rle;
+
+impl Codec for Rle {
+    fn id(&self) -> u8 {
+        7
+    }
+
+    fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        for chunk in data.chunk_by(|a, b| a == b) {
+            for part in chunk.chunks(255) {
+                out.extend_from_slice(&[part.len() as u8, part[0]]);
+            }
+        }
+        out
+    }
+
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if !data.len().is_multiple_of(2) {
+            return Err(invalid_data("truncated rle data".to_string()));
+        }
+        Ok(data.chunks(2).flat_map(|x| vec![x[1]; x[0] as usize]).collect())
+    }
+}
+
+fn assert_contents(db: &DB, value: fn(u32) -> Vec<u8>) {
+    assert_checked(db);
+
+    for k in 0..COUNT {
+        assert_eq!(db.get(str_to_key(&k.to_string())).unwrap().as_deref(), Some(value(k).as_slice()), "key {}", k);
+    }
+
+    let mut cursor = db.cursor();
+    let mut item = cursor.first().unwrap();
+    let mut count = 0;
+    while let Some((key, v)) = item {
+        let k: u32 = key_to_str(&key).parse().unwrap();
+        assert_eq!(v, value(k));
+        count += 1;
+        item = cursor.next().unwrap();
+    }
+    assert_eq!(count, COUNT);
+}
+
+// Кодеки значений во всех листьях дерева
+fn codecs(db: &DB, page_id: PageId, ids: &mut Vec<u8>) {
+    let page = db.page(page_id).unwrap();
+    if page.is_leaf() {
+        ids.extend(page.leaf_inodes().unwrap().iter().map(|x| x.codec));
+        return;
+    }
+
+    for inode in page.branch_inodes().unwrap() {
+        codecs(db, inode.page_id, ids);
+    }
+}
+
+fn leaf_codecs(db: &DB) -> Vec<u8> {
+    let mut ids = vec![];
+    codecs(db, db.meta().root_page.get() as PageId, &mut ids);
+    ids
+}
+
+fn commit(db: &DB, value: fn(u32) -> Vec<u8>) {
+    for chunk in (0..COUNT).collect::<Vec<_>>().chunks(10) {
+        db.update(|tx| {
+            for &k in chunk.iter() {
+                tx.put(str_to_key(&k.to_string()), value(k))?;
+            }
+            Ok(())
+        }).unwrap();
+    }
+}
+
+#[test]
+fn lz_compresses_json() {
+    let value = json(42);
+    let compressed = Lz.compress(&value);
+    assert!(compressed.len() * 5 < value.len(), "{} of {}", compressed.len(), value.len());
+    assert_eq!(Lz.decompress(&compressed).unwrap(), value);
+
+    // Серия одного байта кодируется повторами, перекрывающими сами себя
+    let compressed = Lz.compress(&[7; 10000]);
+    assert!(compressed.len() < 300, "{}", compressed.len());
+    assert_eq!(Lz.decompress(&compressed).unwrap(), vec![7; 10000]);
+}
+
+#[test]
+fn lz_rejects_corrupt_data() {
+    let compressed = Lz.compress(&json(1));
+    assert_eq!(Lz.decompress(&compressed[..compressed.len() - 1]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(Lz.decompress(&compressed[..3]).unwrap_err().kind(), ErrorKind::InvalidData);
+
+    // Длина в заголовке не сходится с данными
+    let mut wrong_len = compressed.clone();
+    wrong_len[0] ^= 1;
+    assert!(Lz.decompress(&wrong_len).is_err());
+
+    // Повтор раньше начала данных
+    assert!(Lz.decompress(&[8, 0, 0, 0, 0x00, b'a', 0x80, 0x02, 0x00]).is_err());
+}
+
+#[test]
+fn commits_compress_values() {
+    let dir = temp_dir();
+    let plain_path = temp_path(&dir, "commit-plain");
+    let plain = DB::create(&plain_path, options(None)).unwrap();
+    commit(&plain, json);
+
+    let path = temp_path(&dir, "commit");
+    let db = DB::create(&path, options(lz())).unwrap();
+    commit(&db, json);
+    assert!(leaf_codecs(&db).iter().all(|&x| x == CODEC_LZ));
+    assert_contents(&db, json);
+
+    let (plain_stats, stats) = (plain.stats().unwrap(), db.stats().unwrap());
+    assert!(stats.leaf.count * 3 < plain_stats.leaf.count, "{} vs {}", stats, plain_stats);
+    drop(db);
+    drop(plain);
+
+    // Встроенный кодек читается и без Options::codec, в том числе после compact
+    compact(&path).unwrap();
+    let db = DB::open_with(&path, options(None)).unwrap();
+    assert!(leaf_codecs(&db).iter().all(|&x| x == CODEC_LZ));
+    assert_contents(&db, json);
+}
+
+// Значения, которые не сжимаются, пишутся как есть
+#[test]
+fn incompressible_values() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "incompressible");
+    let db = DB::create(&path, options(lz())).unwrap();
+    commit(&db, |k| k.to_le_bytes().to_vec());
+
+    assert!(leaf_codecs(&db).iter().all(|&x| x == CODEC_NONE));
+    assert!(!db.page(db.meta().root_page.get() as PageId).unwrap().has_codec());
+    assert_contents(&db, |k| k.to_le_bytes().to_vec());
+}
+
+// Кодек можно включить на существующей базе: новые значения сжимаются, старые читаются как были
+#[test]
+fn mixed_values() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "mixed");
+    let db = DB::create(&path, options(None)).unwrap();
+    commit(&db, json);
+    drop(db);
+
+    let db = DB::open_with(&path, options(lz())).unwrap();
+    db.update(|tx| tx.put(str_to_key("0"), json(0))).unwrap();
+    let ids = leaf_codecs(&db);
+    assert!(ids.contains(&CODEC_LZ) && ids.contains(&CODEC_NONE), "{:?}", ids);
+    assert_contents(&db, json);
+}
+
+#[test]
+fn custom_codec() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "custom");
+    let rle: Option<Arc<dyn Codec>> = Some(Arc::new(Rle));
+    let value: fn(u32) -> Vec<u8> = |k| vec![k as u8; 100 + k as usize];
+
+    let db = DB::create(&path, options(rle.clone())).unwrap();
+    commit(&db, value);
+    assert!(leaf_codecs(&db).iter().all(|&x| x == 7));
+    assert_contents(&db, value);
+    drop(db);
+
+    // Без кодека значения не прочитать, но файл при этом цел
+    let db = DB::open_with(&path, options(None)).unwrap();
+    assert_eq!(db.get(str_to_key("1")).unwrap_err().kind(), ErrorKind::Unsupported);
+    assert!(check(&db).unwrap().is_empty());
+    drop(db);
+
+    compact(&path).unwrap();
+    assert_contents(&DB::open_with(&path, options(rle)).unwrap(), value);
+}
+
+#[test]
+fn save_tree_compresses_values() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "tree");
+    let mut tree = BPlusTree::with_options(&options(lz()));
+    for k in 0..COUNT {
+        tree.add(str_to_key(&k.to_string()), json(k));
+    }
+    save_tree(&tree, &path, &options(lz())).unwrap();
+
+    let db = DB::open(&path).unwrap();
+    assert!(leaf_codecs(&db).iter().all(|&x| x == CODEC_LZ));
+    assert_contents(&db, json);
+    drop(db);
+
+    // В памяти значения снова несжатые
+    let tree = BPlusTree::load(&path).unwrap();
+    assert_eq!(tree.get(str_to_key("5")), Some(&json(5)));
+}
+
+#[test]
+fn reserved_codec_id() {
+    #[derive(Debug)]
+    struct Zero;
+
+    impl Codec for Zero {
+        fn id(&self) -> u8 {
+            CODEC_NONE
+        }
+
+        fn compress(&self, data: &[u8]) -> Vec<u8> {
+            data.to_vec()
+        }
+
+        fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+            Ok(data.to_vec())
+        }
+    }
+
+    let dir = temp_dir();
+    let err = DB::create(&temp_path(&dir, "reserved"), options(Some(Arc::new(Zero)))).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+proptest! {
+    #[test]
+    fn lz_round_trip(data in prop::collection::vec(prop::sample::select(vec![0u8, 1, 2, b'a', 0xFF]), 0..5000)) {
+        prop_assert_eq!(Lz.decompress(&Lz.compress(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn lz_round_trip_random(data in prop::collection::vec(any::<u8>(), 0..3000)) {
+        prop_assert_eq!(Lz.decompress(&Lz.compress(&data)).unwrap(), data);
+    }
+
+    // Любые байты дают либо данные, либо ошибку
+    #[test]
+    fn lz_arbitrary_input(data in prop::collection::vec(any::<u8>(), 0..200)) {
+        let _ = Lz.decompress(&data);
+    }
+}
@@ -0,0 +1,29 @@
+// Общее для интеграционных тестов: временные файлы баз, Options для тестов и проверка
+// целостности. Подключается через `mod common;`, поэтому не каждый тест использует все.
+#![allow(dead_code)]
+
+use rust_apps::check::check;
+use rust_apps::db::{DB, Options};
+use rust_apps::types::SyncMode;
+use tempfile::TempDir;
+
+// Каталог для баз теста. Удаляется вместе со всем содержимым на drop, в том числе когда
+// тест паникует, поэтому должен жить до конца теста.
+pub fn temp_dir() -> TempDir {
+    tempfile::Builder::new().prefix("rust-apps-test-").tempdir().unwrap()
+}
+
+// Путь к файлу базы name внутри dir (сам файл не создается)
+pub fn temp_path(dir: &TempDir, name: &str) -> String {
+    dir.path().join(format!("{}.db", name)).to_str().unwrap().to_string()
+}
+
+// Без sync: тестам не нужна устойчивость к сбоям, а fsync на каждом коммите медленный
+pub fn options(page_size: usize) -> Options {
+    Options { sync_mode: SyncMode::None, page_size, ..Options::default() }
+}
+
+pub fn assert_checked(db: &DB) {
+    let violations: Vec<String> = check(db).unwrap().iter().map(|x| x.to_string()).collect();
+    assert!(violations.is_empty(), "{:?}", violations);
+}
@@ -0,0 +1,146 @@
+// Офлайн-компактизация: живое дерево переписывается в новый файл без брошенных страниц,
+// данные и размер страницы сохраняются, а старый файл подменяется целиком.
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use rust_apps::compact::{compact, compact_to};
+use rust_apps::comparator::Reverse;
+use rust_apps::db::{DB, Options};
+use rust_apps::types::str_to_key;
+
+mod common;
+use common::{assert_checked, options, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 4096;
+const COUNT: u32 = 500;
+
+fn create(path: &str) -> DB {
+    DB::create(path, options(PAGE_SIZE)).unwrap()
+}
+
+fn key(k: u32) -> [u8; 32] {
+    str_to_key(&k.to_string())
+}
+
+// Каждый ключ переписывается несколько раз небольшими коммитами: файл копит брошенные страницы
+fn fragmented(path: &str) {
+    let db = create(path);
+    for round in 0..3u8 {
+        for chunk in (0..COUNT).collect::<Vec<_>>().chunks(20) {
+            db.update(|tx| {
+                for &k in chunk.iter() {
+                    tx.put(key(k), vec![round; 10])?;
+                }
+                Ok(())
+            }).unwrap();
+        }
+    }
+}
+
+fn assert_contents(db: &DB) {
+    for k in 0..COUNT {
+        assert_eq!(db.get(key(k)).unwrap().as_deref(), Some(&[2u8; 10][..]), "key {}", k);
+    }
+}
+
+#[test]
+fn compact_in_place() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "in-place");
+    fragmented(&path);
+    let before = fs::metadata(&path).unwrap().len();
+
+    compact(&path).unwrap();
+
+    // Временный файл переименован в исходный
+    assert!(!Path::new(&format!("{}.compact", path)).exists());
+    let after = fs::metadata(&path).unwrap().len();
+    assert!(after * 10 < before, "{} -> {}", before, after);
+
+    let db = DB::open(&path).unwrap();
+    assert_eq!(db.page_size(), PAGE_SIZE);
+    assert_contents(&db);
+
+    // В сжатую базу можно писать дальше
+    db.update(|tx| {
+        tx.put(key(0), b"new".to_vec())?;
+        Ok(())
+    }).unwrap();
+    assert_eq!(db.get(key(0)).unwrap().as_deref(), Some(&b"new"[..]));
+}
+
+#[test]
+fn compact_to_copy() {
+    let dir = temp_dir();
+    let (src, dst) = (temp_path(&dir, "src"), temp_path(&dir, "dst"));
+    fragmented(&src);
+    let before = fs::read(&src).unwrap();
+
+    // dst перезаписывается, исходный файл не меняется
+    fs::write(&dst, b"garbage").unwrap();
+    compact_to(&src, &dst).unwrap();
+    assert_eq!(fs::read(&src).unwrap(), before);
+
+    assert_contents(&DB::open(&dst).unwrap());
+}
+
+#[test]
+fn compact_empty() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "empty");
+    drop(create(&path));
+
+    compact(&path).unwrap();
+    let db = DB::open(&path).unwrap();
+    assert_eq!(db.get(key(0)).unwrap(), None);
+
+    // Пустой лист сжатой базы принимает новые ключи
+    db.update(|tx| {
+        tx.put(key(1), vec![1])?;
+        Ok(())
+    }).unwrap();
+    assert_eq!(db.get(key(1)).unwrap().as_deref(), Some(&[1u8][..]));
+}
+
+// compact без options берет компаратор и dupsort из meta исходной базы
+#[test]
+fn compact_with_options_from_meta() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "reverse");
+    let reverse = Options { comparator: Some(Arc::new(Reverse)), ..options(PAGE_SIZE) };
+    let db = DB::create(&path, reverse.clone()).unwrap();
+    db.update(|tx| {
+        for k in 0..COUNT {
+            tx.put(key(k), vec![k as u8; 10])?;
+        }
+        Ok(())
+    }).unwrap();
+    drop(db);
+
+    compact(&path).unwrap();
+    let db = DB::open_with(&path, reverse).unwrap();
+    assert_checked(&db);
+    let first = db.cursor().first().unwrap().unwrap().0.to_vec();
+    assert_eq!(first, key(COUNT - 1));
+    for k in 0..COUNT {
+        assert_eq!(db.get(key(k)).unwrap().as_deref(), Some(&[k as u8; 10][..]), "key {}", k);
+    }
+
+    let path = temp_path(&dir, "dupsort");
+    let dupsort = Options { dupsort: true, ..options(PAGE_SIZE) };
+    let db = DB::create(&path, dupsort.clone()).unwrap();
+    db.update(|tx| {
+        for x in (0..COUNT).rev() {
+            tx.put_dup(key(1), x.to_be_bytes().to_vec())?;
+        }
+        Ok(())
+    }).unwrap();
+    drop(db);
+
+    compact(&path).unwrap();
+    let db = DB::open_with(&path, dupsort).unwrap();
+    assert_checked(&db);
+    let values: Vec<Vec<u8>> = db.get_all(key(1)).unwrap().iter().map(|x| x.to_vec()).collect();
+    assert_eq!(values, (0..COUNT).map(|x| x.to_be_bytes().to_vec()).collect::<Vec<_>>());
+}
@@ -0,0 +1,191 @@
+// Свой порядок ключей: встроенные Reverse и CaseInsensitive и компаратор из теста в
+// коммитах, save_tree, compact и курсоре; id компаратора в meta и отказ открыть базу с другим.
+use std::cmp::Ordering;
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+use rust_apps::compact::compact_with;
+use rust_apps::comparator::{CaseInsensitive, Comparator, COMPARATOR_REVERSE, Reverse};
+use rust_apps::db::{DB, Options};
+use rust_apps::export::{export, Encoding, Format};
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::{bytes_to_key, Key, str_to_key};
+
+mod common;
+use common::{assert_checked, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 512;
+const COUNT: u64 = 1000;
+
+fn options(comparator: Option<Arc<dyn Comparator>>) -> Options {
+    Options { comparator, ..common::options(PAGE_SIZE) }
+}
+
+fn reverse() -> Option<Arc<dyn Comparator>> {
+    Some(Arc::new(Reverse))
+}
+
+// Число в последних 8 байтах ключа, little-endian: побайтово такие ключи не сортируются
+fn le_key(k: u64) -> Key {
+    let mut key = [0; 32];
+    key[24..].copy_from_slice(&k.to_le_bytes());
+    key
+}
+
+fn le_value(key: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&key[24..]);
+    u64::from_le_bytes(buf)
+}
+
+#[derive(Debug)]
+struct LittleEndian;
+
+impl Comparator for LittleEndian {
+    fn id(&self) -> u32 {
+        1000
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        le_value(a).cmp(&le_value(b))
+    }
+}
+
+// Ключи в порядке, перемешанном относительно любого из компараторов
+fn shuffled() -> Vec<u64> {
+    (0..COUNT).map(|x| x * 7919 % COUNT).collect()
+}
+
+fn commit(db: &DB, key: fn(u64) -> Key) {
+    for chunk in shuffled().chunks(25) {
+        db.update(|tx| {
+            for &k in chunk.iter() {
+                tx.put(key(k), k.to_le_bytes().to_vec())?;
+            }
+            Ok(())
+        }).unwrap();
+    }
+}
+
+// Ключи курсора по порядку; заодно проверяет check и get
+fn keys(db: &DB) -> Vec<Vec<u8>> {
+    assert_checked(db);
+
+    let mut keys = vec![];
+    let mut cursor = db.cursor();
+    let mut item = cursor.first().unwrap();
+    while let Some((key, value)) = item {
+        assert_eq!(db.get(bytes_to_key(&key).unwrap()).unwrap().as_deref(), Some(&value[..]));
+        keys.push(key.to_vec());
+        item = cursor.next().unwrap();
+    }
+    keys
+}
+
+fn sorted_by(comparator: &dyn Comparator, key: fn(u64) -> Key) -> Vec<Vec<u8>> {
+    let mut keys: Vec<Vec<u8>> = (0..COUNT).map(|k| key(k).to_vec()).collect();
+    keys.sort_by(|a, b| comparator.compare(a, b));
+    keys
+}
+
+fn str_key(k: u64) -> Key {
+    str_to_key(&k.to_string())
+}
+
+#[test]
+fn reverse_order() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "reverse");
+    let db = DB::create(&path, options(reverse())).unwrap();
+    commit(&db, str_key);
+    assert_eq!(db.meta().comparator.get(), COMPARATOR_REVERSE);
+
+    let expected = sorted_by(&Reverse, str_key);
+    assert_eq!(keys(&db), expected);
+    assert_eq!(expected[0], str_key(COUNT - 1));
+
+    // seek встает на первый ключ, который не раньше заданного в порядке компаратора
+    let mut cursor = db.cursor();
+    assert_eq!(cursor.seek(&str_key(500)).unwrap().unwrap().0, &str_key(500)[..]);
+    drop(db);
+
+    compact_with(&path, &options(reverse())).unwrap();
+    let db = DB::open_with(&path, options(reverse())).unwrap();
+    assert_eq!(keys(&db), expected);
+
+    // Выгрузка диапазона [from, to) тоже в порядке компаратора
+    let mut out = vec![];
+    let count = export(&db, &mut out, Format::Csv, Encoding::Hex, Some(&str_key(20)), Some(&str_key(10))).unwrap();
+    assert_eq!(count, 10);
+}
+
+#[test]
+fn case_insensitive() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "case");
+    let db = DB::create(&path, options(Some(Arc::new(CaseInsensitive)))).unwrap();
+
+    db.update(|tx| {
+        tx.put(str_to_key("Key"), b"1".to_vec())?;
+        tx.put(str_to_key("b"), b"2".to_vec())?;
+        tx.put(str_to_key("KEY"), b"3".to_vec())?;
+        tx.put(str_to_key("A"), b"4".to_vec())
+    }).unwrap();
+
+    assert_eq!(db.get(str_to_key("key")).unwrap().as_deref(), Some(&b"3"[..]));
+    assert_eq!(db.get(str_to_key("a")).unwrap().as_deref(), Some(&b"4"[..]));
+    assert_eq!(keys(&db), vec![str_to_key("A").to_vec(), str_to_key("b").to_vec(), str_to_key("Key").to_vec()]);
+}
+
+#[test]
+fn custom_comparator() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "custom");
+    let comparator: Option<Arc<dyn Comparator>> = Some(Arc::new(LittleEndian));
+    let db = DB::create(&path, options(comparator)).unwrap();
+    commit(&db, le_key);
+
+    let keys = keys(&db);
+    assert_eq!(keys, sorted_by(&LittleEndian, le_key));
+    assert_eq!(keys.iter().map(|x| le_value(x)).collect::<Vec<_>>(), (0..COUNT).collect::<Vec<_>>());
+}
+
+#[test]
+fn save_tree_with_comparator() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "tree");
+    let mut tree = BPlusTree::with_options(&options(reverse()));
+    for k in shuffled() {
+        tree.add(str_key(k), k.to_le_bytes().to_vec());
+    }
+    assert_eq!(tree.get(str_key(5)), Some(&5u64.to_le_bytes().to_vec()));
+    save_tree(&tree, &path, &options(reverse())).unwrap();
+
+    let db = DB::open_with(&path, options(reverse())).unwrap();
+    assert_eq!(db.meta().comparator.get(), COMPARATOR_REVERSE);
+    assert_eq!(keys(&db), sorted_by(&Reverse, str_key));
+    drop(db);
+
+    let mut tree = BPlusTree::load_with(&path, &options(reverse())).unwrap();
+    assert_eq!(tree.remove(str_key(7)), Some(7u64.to_le_bytes().to_vec()));
+    assert_eq!(tree.len(), COUNT as usize - 1);
+}
+
+// База открывается только с тем компаратором, с которым создана
+#[test]
+fn mismatched_comparator() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "mismatch");
+    drop(DB::create(&path, options(reverse())).unwrap());
+
+    for comparator in [None, Some(Arc::new(CaseInsensitive) as Arc<dyn Comparator>), Some(Arc::new(LittleEndian))] {
+        let err = DB::open_with(&path, options(comparator)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+    assert_eq!(BPlusTree::load(&path).err().unwrap().kind(), ErrorKind::InvalidInput);
+    assert!(DB::open_with(&path, options(reverse())).is_ok());
+
+    let path = temp_path(&dir, "plain");
+    drop(DB::create(&path, options(None)).unwrap());
+    assert_eq!(DB::open_with(&path, options(reverse())).err().unwrap().kind(), ErrorKind::InvalidInput);
+}
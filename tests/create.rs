@@ -0,0 +1,139 @@
+// Пустая база из DB::create: запись начинается с корневого листа без ключей, страницы,
+// замененные коммитами, попадают в freelist, а порванная meta откатывается на предыдущую.
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::os::unix::fs::FileExt;
+
+use rust_apps::db::DB;
+use rust_apps::types::str_to_key;
+
+mod common;
+use common::{assert_checked, options, temp_dir, temp_path};
+
+#[test]
+fn empty_database() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "empty");
+    let db = DB::create(&path, options(1024)).unwrap();
+
+    assert_eq!(db.page_size(), 1024);
+    assert_eq!(db.meta().page_count.get(), 4);
+    assert_eq!(db.freelist().unwrap(), vec![]);
+    assert_eq!(db.get(str_to_key("1")).unwrap().as_deref(), None);
+    assert_eq!(db.cursor().first().unwrap(), None);
+    assert_eq!(db.cursor().last().unwrap(), None);
+    assert_eq!(db.cursor().seek(&str_to_key("1")).unwrap(), None);
+    assert_eq!(db.stats().unwrap().depth, 1);
+    assert_checked(&db);
+}
+
+#[test]
+fn put_into_empty_database() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "put");
+    let db = DB::create(&path, options(1024)).unwrap();
+
+    db.update(|tx| tx.put(str_to_key("b"), b"2".to_vec())).unwrap();
+    db.update(|tx| {
+        tx.put(str_to_key("a"), b"1".to_vec())?;
+        tx.put(str_to_key("c"), b"3".to_vec())
+    }).unwrap();
+    assert_checked(&db);
+    drop(db);
+
+    let db = DB::open(&path).unwrap();
+    for (k, v) in [("a", "1"), ("b", "2"), ("c", "3")].iter() {
+        assert_eq!(db.get(str_to_key(k)).unwrap().as_deref(), Some(v.as_bytes()));
+    }
+}
+
+#[test]
+fn replaced_pages_go_to_freelist() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "freelist");
+    let db = DB::create(&path, options(1024)).unwrap();
+
+    // Лист (3) и пустой freelist (2) заменяются первым же коммитом
+    db.update(|tx| tx.put(str_to_key("a"), b"1".to_vec())).unwrap();
+    assert_eq!(db.freelist().unwrap(), vec![2, 3]);
+    assert_eq!(db.meta().root_page.get(), 4);
+    assert_eq!(db.meta().freelist_page.get(), 5);
+
+    db.update(|tx| tx.put(str_to_key("a"), b"2".to_vec())).unwrap();
+    assert_eq!(db.freelist().unwrap(), vec![2, 3, 4, 5]);
+    assert_eq!(db.stats().unwrap().freelist.count, 1);
+    assert_checked(&db);
+
+    // Пустая транзакция ничего не переписывает
+    db.update(|_| Ok(())).unwrap();
+    assert_eq!(db.freelist().unwrap(), vec![2, 3, 4, 5]);
+}
+
+#[test]
+fn create_does_not_overwrite() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "exists");
+    let db = DB::create(&path, options(1024)).unwrap();
+    db.update(|tx| tx.put(str_to_key("a"), b"1".to_vec())).unwrap();
+    drop(db);
+
+    assert_eq!(DB::create(&path, options(1024)).err().unwrap().kind(), ErrorKind::AlreadyExists);
+    assert_eq!(DB::open(&path).unwrap().get(str_to_key("a")).unwrap().as_deref(), Some(&b"1"[..]));
+}
+
+// Коммиты делят переполненные ноды: лист не растет overflow-страницами, а дерево растет вглубь
+#[test]
+fn commits_split_nodes() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "split");
+    let db = DB::create(&path, options(512)).unwrap();
+
+    for k in 0..500 {
+        db.update(|tx| tx.put(str_to_key(&k.to_string()), vec![k as u8; 20])).unwrap();
+    }
+    // Значение больше страницы: лист из одного элемента с overflow-страницами
+    db.update(|tx| tx.put(str_to_key("big"), vec![7; 2000])).unwrap();
+
+    let stats = db.stats().unwrap();
+    assert!(stats.depth >= 3, "{}", stats);
+    assert_eq!(stats.overflow_count, 1, "{}", stats);
+    assert_checked(&db);
+
+    for k in 0..500 {
+        assert_eq!(db.get(str_to_key(&k.to_string())).unwrap().as_deref(), Some(&[k as u8; 20][..]));
+    }
+    assert_eq!(db.get(str_to_key("big")).unwrap().as_deref(), Some(&[7; 2000][..]));
+}
+
+// Коммиты пишут meta по очереди в два слота: если запись meta порвалась, база открывается
+// с предыдущей meta, а следующий коммит перезаписывает порванный слот
+#[test]
+fn torn_meta_falls_back() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "torn");
+    let db = DB::create(&path, options(1024)).unwrap();
+    db.update(|tx| tx.put(str_to_key("a"), b"1".to_vec())).unwrap();
+    let prev = db.meta();
+    db.update(|tx| tx.put(str_to_key("a"), b"2".to_vec())).unwrap();
+    let slot = db.meta().slot();
+    assert_ne!(slot, prev.slot());
+    drop(db);
+
+    // Вторая половина meta не дописана
+    let f = OpenOptions::new().write(true).open(&path).unwrap();
+    f.write_all_at(&[0; 24], slot * 1024 + 18 + 16).unwrap();
+    drop(f);
+
+    let db = DB::open(&path).unwrap();
+    assert_eq!(db.meta().txid.get(), prev.txid.get());
+    assert_eq!(db.get(str_to_key("a")).unwrap().as_deref(), Some(&b"1"[..]));
+    assert_checked(&db);
+
+    db.update(|tx| tx.put(str_to_key("b"), b"3".to_vec())).unwrap();
+    assert_eq!(db.meta().slot(), slot);
+    drop(db);
+
+    let db = DB::open(&path).unwrap();
+    assert_eq!(db.get(str_to_key("b")).unwrap().as_deref(), Some(&b"3"[..]));
+    assert_checked(&db);
+}
@@ -0,0 +1,123 @@
+// dbinspect как отдельная программа: meta, страницы и дерево печатаются, dump показывает
+// байты с диска (зашифрованные - зашифрованными), а цикл в дереве не зацикливает печать.
+use std::fs;
+use std::mem::size_of;
+use std::process::Command;
+use std::sync::Arc;
+
+use rust_apps::cipher::{CipherKey, TestCipher};
+use rust_apps::db::{DB, Options};
+use rust_apps::types::{BranchINodeHeader, Le16, Le32, Le64, MAGIC, Meta, PAGE_BRANCH, PAGE_LEAF, PageHeader, str_to_key, to_bytes, VERSION};
+
+mod common;
+use common::{options, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 512;
+
+// Код выхода 0 и stdout
+fn dbinspect(args: &[&str]) -> (bool, String) {
+    let out = Command::new(env!("CARGO_BIN_EXE_dbinspect")).args(args).output().unwrap();
+    (out.status.success(), String::from_utf8(out.stdout).unwrap())
+}
+
+// Файл из meta в слоте 0, пустого второго слота, пустого листа 2 и ветки 3 с ключами
+// "a" -> 2 и "b" -> child
+fn write_tree(path: &str, child: u32) {
+    let meta = Meta { magic: Le32::new(MAGIC), version: Le32::new(VERSION), page_size: Le32::new(PAGE_SIZE as u32), root_page: Le32::new(3), page_count: Le32::new(4), freelist_page: Le32::new(0), txid: Le64::new(2), comparator: Le32::new(0), flags: Le32::new(0) };
+    let leaf = PageHeader { id: Le64::new(2), flags: Le16::new(PAGE_LEAF), inode_count: Le32::new(0), page_overflow_count: Le32::new(0) };
+    let branch = PageHeader { id: Le64::new(3), flags: Le16::new(PAGE_BRANCH), inode_count: Le32::new(2), page_overflow_count: Le32::new(0) };
+
+    // Ключи лежат сразу за обоими inode, pos отсчитывается от начала своего inode
+    let inode_size = size_of::<BranchINodeHeader>() as u32;
+    let inodes = [
+        BranchINodeHeader { pos: Le32::new(2 * inode_size), ksize: Le32::new(1), page_id: Le32::new(2) },
+        BranchINodeHeader { pos: Le32::new(inode_size + 1), ksize: Le32::new(1), page_id: Le32::new(child) },
+    ];
+
+    let mut data = meta.to_page_bytes();
+    data.resize(2 * PAGE_SIZE, 0);
+    data.extend_from_slice(to_bytes(&leaf));
+    data.resize(3 * PAGE_SIZE, 0);
+    data.extend_from_slice(to_bytes(&branch));
+    for inode in inodes.iter() {
+        data.extend_from_slice(to_bytes(inode));
+    }
+    data.extend_from_slice(b"ab");
+    data.resize(4 * PAGE_SIZE, 0);
+    fs::write(path, data).unwrap();
+}
+
+#[test]
+fn meta_page_and_tree() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "tree");
+    write_tree(&path, 2);
+
+    let (ok, out) = dbinspect(&["meta", &path]);
+    assert!(ok);
+    assert!(out.contains("root_page:  3\n"), "{}", out);
+    assert!(out.contains("page_count: 4\n"), "{}", out);
+
+    let (ok, out) = dbinspect(&["page", &path, "3"]);
+    assert!(ok);
+    assert!(out.starts_with("branch page"), "{}", out);
+    assert!(out.contains("  #1 ") && out.contains("key=\"b\""), "{}", out);
+
+    let (ok, out) = dbinspect(&["tree", &path]);
+    assert!(ok);
+    assert!(out.starts_with("-- page 3 (branch, 2 inodes"), "{}", out);
+    assert!(out.contains("  |-- page 2 (leaf, 0 inodes"), "{}", out);
+
+    // Несуществующая страница - ошибка, а не паника
+    let (ok, _) = dbinspect(&["page", &path, "100000"]);
+    assert!(!ok);
+}
+
+#[test]
+fn dump() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "dump");
+    write_tree(&path, 2);
+
+    let (ok, out) = dbinspect(&["dump", &path, "3"]);
+    assert!(ok);
+    let lines: Vec<&str> = out.lines().collect();
+    assert!(lines[0].starts_with("00000000  03 00 00 00"), "{}", out);
+    assert!(out.contains("61 62") && out.contains("ab...."), "{}", out);
+    assert_eq!(lines.last(), Some(&format!("{:08x}", PAGE_SIZE).as_str()));
+}
+
+#[test]
+fn dump_encrypted() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "encrypted");
+    let cipher = Options { cipher: Some(Arc::new(TestCipher)), cipher_key: CipherKey(b"key".to_vec()), ..options(PAGE_SIZE) };
+    let db = DB::create(&path, cipher).unwrap();
+    db.update(|tx| tx.put(str_to_key("a"), b"secret value".to_vec())).unwrap();
+    let root = db.meta().root_page.get() as usize;
+    drop(db);
+
+    // Без шифра страницу не разобрать, но байты с диска показать можно
+    let (ok, _) = dbinspect(&["page", &path, &root.to_string()]);
+    assert!(!ok);
+
+    let (ok, out) = dbinspect(&["dump", &path, &root.to_string()]);
+    assert!(ok);
+    let file = fs::read(&path).unwrap();
+    let page = &file[root * PAGE_SIZE..(root + 1) * PAGE_SIZE];
+    let hex: Vec<String> = page[..16].iter().map(|x| format!("{:02x}", x)).collect();
+    assert!(out.starts_with(&format!("00000000  {}", hex.join(" "))), "{}", out);
+    assert!(!out.contains("secret"), "{}", out);
+}
+
+#[test]
+fn tree_with_cycle() {
+    let dir = temp_dir();
+    // Вторая ссылка корня ведет на сам корень
+    let path = temp_path(&dir, "cycle");
+    write_tree(&path, 3);
+
+    let (ok, out) = dbinspect(&["tree", &path]);
+    assert!(ok);
+    assert!(out.contains("page 3 (already visited: cycle in tree)"), "{}", out);
+}
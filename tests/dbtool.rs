@@ -0,0 +1,164 @@
+// dbtool как отдельная программа: export с границами диапазона в том виде, в каком ключи
+// хранятся в базе, и база с невстроенными настройками (компаратор и dupsort из meta).
+use std::cmp::Ordering;
+use std::process::Command;
+use std::sync::Arc;
+
+use rust_apps::comparator::{Comparator, Reverse};
+use rust_apps::db::{DB, Options};
+use rust_apps::export::Encoding;
+use rust_apps::keys::to_key;
+use rust_apps::types::str_to_key;
+
+mod common;
+use common::{options, temp_dir, temp_path};
+
+// Код выхода 0 и stdout
+fn dbtool(args: &[&str]) -> (bool, String) {
+    let out = Command::new(env!("CARGO_BIN_EXE_dbtool")).args(args).output().unwrap();
+    (out.status.success(), String::from_utf8(out.stdout).unwrap())
+}
+
+// Ключи из keys::to_key выровнены влево: граница из того же to_key отрезает ровно свои ключи
+#[test]
+fn export_range_of_encoded_keys() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "encoded");
+    let db = DB::create(&path, options(512)).unwrap();
+    db.update(|tx| {
+        for k in 0..100u64 {
+            tx.put(to_key(&k).unwrap(), vec![k as u8])?;
+        }
+        Ok(())
+    }).unwrap();
+    drop(db);
+
+    let hex = |k: u64| Encoding::Hex.encode(&to_key(&k).unwrap());
+    let (ok, out) = dbtool(&["export", &path, "--format", "csv", "--from", &hex(10), "--to", &hex(20)]);
+    assert!(ok, "{}", out);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 11, "{}", out);
+    assert_eq!(lines[1], format!("{},0a", hex(10)));
+    assert_eq!(lines[10], format!("{},13", hex(19)));
+
+    // Граница короче ключа - его префикс
+    let (ok, out) = dbtool(&["export", &path, "--from", "0000000000000000", "--to", "0000000000000002"]);
+    assert!(ok);
+    assert_eq!(out.lines().count(), 2, "{}", out);
+}
+
+fn dbinspect(args: &[&str]) -> (bool, String) {
+    let out = Command::new(env!("CARGO_BIN_EXE_dbinspect")).args(args).output().unwrap();
+    (out.status.success(), String::from_utf8(out.stdout).unwrap())
+}
+
+fn fill(db: &DB) {
+    db.update(|tx| {
+        for k in 0..300 {
+            tx.put(str_to_key(&format!("{:04}", k)), vec![k as u8; 8])?;
+        }
+        Ok(())
+    }).unwrap();
+}
+
+// Все команды открывают базу с компаратором из ее meta
+#[test]
+fn reverse_comparator() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "reverse");
+    let reverse = Options { comparator: Some(Arc::new(Reverse)), ..options(512) };
+    fill(&DB::create(&path, reverse.clone()).unwrap());
+
+    for command in ["check", "stats"] {
+        let (ok, out) = dbtool(&[command, &path]);
+        assert!(ok, "{}: {}", command, out);
+    }
+
+    // Ключи выгружаются от больших к меньшим
+    let (ok, out) = dbtool(&["export", &path, "--format", "csv"]);
+    assert!(ok);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 301);
+    assert!(lines[1].starts_with(&Encoding::Hex.encode(&str_to_key("0299"))), "{}", lines[1]);
+
+    let (ok, out) = dbinspect(&["tree", &path]);
+    assert!(ok, "{}", out);
+
+    let (ok, out) = dbtool(&["compact", &path]);
+    assert!(ok, "{}", out);
+    let db = DB::open_with(&path, reverse).unwrap();
+    assert_eq!(db.cursor().first().unwrap().unwrap().0.as_ref(), &str_to_key("0299")[..]);
+}
+
+// Свой компаратор по id не восстановить: понятная ошибка вместо неверного порядка
+#[test]
+fn custom_comparator() {
+    #[derive(Debug)]
+    struct Custom;
+
+    impl Comparator for Custom {
+        fn id(&self) -> u32 {
+            1000
+        }
+
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+            a.cmp(b)
+        }
+    }
+
+    let dir = temp_dir();
+    let path = temp_path(&dir, "custom");
+    fill(&DB::create(&path, Options { comparator: Some(Arc::new(Custom)), ..options(512) }).unwrap());
+
+    let out = Command::new(env!("CARGO_BIN_EXE_dbtool")).args(["check", &path]).output().unwrap();
+    assert!(!out.status.success());
+    assert!(String::from_utf8(out.stderr).unwrap().contains("custom comparator 1000"));
+}
+
+// Флаг dupsort берется из meta: каждое значение ключа выгружается отдельной строкой, а
+// выгрузка загружается обратно в dupsort-базу
+#[test]
+fn dupsort() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "dupsort");
+    let dupsort = Options { dupsort: true, ..options(512) };
+    let db = DB::create(&path, dupsort.clone()).unwrap();
+    db.update(|tx| {
+        for k in 0..100 {
+            for x in 0..3u8 {
+                tx.put(str_to_key(&format!("{:04}", k)), vec![x; 4])?;
+            }
+        }
+        Ok(())
+    }).unwrap();
+    drop(db);
+
+    for command in ["check", "stats"] {
+        let (ok, out) = dbtool(&[command, &path]);
+        assert!(ok, "{}: {}", command, out);
+    }
+    let (ok, out) = dbinspect(&["tree", &path]);
+    assert!(ok, "{}", out);
+
+    let hex = |k: &str| Encoding::Hex.encode(&str_to_key(k));
+    let (ok, out) = dbtool(&["export", &path, "--format", "csv", "--from", &hex("0007"), "--to", &hex("0008")]);
+    assert!(ok, "{}", out);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines, vec!["key,value".to_string(), format!("{},00000000", hex("0007")),
+                           format!("{},01010101", hex("0007")), format!("{},02020202", hex("0007"))]);
+
+    let (ok, out) = dbtool(&["compact", &path]);
+    assert!(ok, "{}", out);
+    let (ok, out) = dbtool(&["export", &path]);
+    assert!(ok);
+    assert_eq!(out.lines().count(), 300);
+
+    let dump = temp_path(&dir, "dump.jsonl");
+    std::fs::write(&dump, &out).unwrap();
+    let copy = temp_path(&dir, "copy");
+    drop(DB::create(&copy, dupsort.clone()).unwrap());
+    let (ok, out) = dbtool(&["import", &copy, &dump]);
+    assert!(ok, "{}", out);
+    let db = DB::open_with(&copy, dupsort).unwrap();
+    assert_eq!(db.get_all(str_to_key("0042")).unwrap(), vec![vec![0; 4], vec![1; 4], vec![2; 4]]);
+}
@@ -0,0 +1,237 @@
+// Несколько значений на ключ: put_dup/delete_dup, get_all, next_dup/prev_dup курсора на
+// границах листьев, check и compact dupsort-базы, флаг в meta и отказ открыть базу без него.
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+use rust_apps::compact::compact_with;
+use rust_apps::comparator::Reverse;
+use rust_apps::db::{DB, Options};
+use rust_apps::export::{export, import, Encoding, Format};
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::{META_DUPSORT, str_to_key};
+
+mod common;
+use common::{assert_checked, temp_dir, temp_path};
+
+fn options() -> Options {
+    Options { dupsort: true, ..common::options(512) }
+}
+
+fn get_all(db: &DB, key: &str) -> Vec<Vec<u8>> {
+    db.get_all(str_to_key(key)).unwrap().iter().map(|x| x.to_vec()).collect()
+}
+
+// Значения с перемешанным порядком вставки: по 300 на каждый из трех ключей
+fn fill(db: &DB) {
+    db.update(|tx| {
+        for key in ["b", "a", "c"].iter() {
+            for x in 0..300u32 {
+                tx.put_dup(str_to_key(key), (x * 7919 % 300).to_be_bytes().to_vec())?;
+            }
+        }
+        Ok(())
+    }).unwrap();
+}
+
+fn values(range: std::ops::Range<u32>) -> Vec<Vec<u8>> {
+    range.map(|x| x.to_be_bytes().to_vec()).collect()
+}
+
+#[test]
+fn sorted_values() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "sorted");
+    let db = DB::create(&path, options()).unwrap();
+    assert_eq!(db.meta().flags.get(), META_DUPSORT);
+    fill(&db);
+    assert_checked(&db);
+
+    assert_eq!(get_all(&db, "a"), values(0..300));
+    assert_eq!(get_all(&db, "c"), values(0..300));
+    assert!(get_all(&db, "d").is_empty());
+    assert_eq!(db.get(str_to_key("b")).unwrap().as_deref(), Some(&0u32.to_be_bytes()[..]));
+    assert_eq!(db.get(str_to_key("d")).unwrap(), None);
+
+    // Пара добавляется один раз, put в dupsort-базе - тот же put_dup
+    db.update(|tx| {
+        tx.put_dup(str_to_key("a"), 5u32.to_be_bytes().to_vec())?;
+        tx.put(str_to_key("a"), 1000u32.to_be_bytes().to_vec())
+    }).unwrap();
+    let mut expected = values(0..300);
+    expected.push(1000u32.to_be_bytes().to_vec());
+    assert_eq!(get_all(&db, "a"), expected);
+
+    // Курсор видит все пары по порядку ключей, а внутри ключа - значений
+    let mut cursor = db.cursor();
+    let mut item = cursor.first().unwrap();
+    let mut count = 0;
+    while let Some((key, value)) = item {
+        assert_eq!(key.len(), 32);
+        if count == 0 {
+            assert_eq!((&key[..], &value[..]), (&str_to_key("a")[..], &0u32.to_be_bytes()[..]));
+        }
+        count += 1;
+        item = cursor.next().unwrap();
+    }
+    assert_eq!(count, 901);
+}
+
+#[test]
+fn delete_dup() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "delete");
+    let db = DB::create(&path, options()).unwrap();
+    fill(&db);
+
+    db.update(|tx| {
+        for x in (0..300u32).filter(|x| x % 3 != 0) {
+            assert!(tx.delete_dup(str_to_key("b"), &x.to_be_bytes())?);
+        }
+        assert!(!tx.delete_dup(str_to_key("b"), &1u32.to_be_bytes())?);
+        assert!(!tx.delete_dup(str_to_key("d"), &0u32.to_be_bytes())?);
+
+        // Транзакция видит свои удаления: первое значение "b" теперь 0, следующее - 3
+        let mut cursor = tx.cursor();
+        assert_eq!(tx.get(str_to_key("b"))?.as_deref(), Some(&0u32.to_be_bytes()[..]));
+        cursor.seek(&str_to_key("b"))?;
+        assert_eq!(cursor.next_dup()?.unwrap().1.as_ref(), &3u32.to_be_bytes()[..]);
+
+        // Ключ целиком в dupsort-базе не удаляется
+        assert_eq!(tx.delete(str_to_key("a")).unwrap_err().kind(), ErrorKind::InvalidInput);
+        Ok(())
+    }).unwrap();
+    assert_checked(&db);
+
+    let expected: Vec<Vec<u8>> = (0..300u32).filter(|x| x % 3 == 0).map(|x| x.to_be_bytes().to_vec()).collect();
+    assert_eq!(get_all(&db, "b"), expected);
+    assert_eq!(get_all(&db, "a"), values(0..300));
+
+    // Все значения всех ключей: дерево пустеет
+    db.update(|tx| {
+        for key in ["a", "b", "c"].iter() {
+            for x in 0..300u32 {
+                tx.delete_dup(str_to_key(key), &x.to_be_bytes())?;
+            }
+        }
+        Ok(())
+    }).unwrap();
+    assert_checked(&db);
+    assert!(db.cursor().first().unwrap().is_none());
+}
+
+// next_dup/prev_dup ходят только по значениям своего ключа, в том числе через границы листьев
+#[test]
+fn cursor_dups() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "cursor");
+    let db = DB::create(&path, options()).unwrap();
+    fill(&db);
+    assert!(db.stats().unwrap().depth > 1);
+
+    let mut cursor = db.cursor();
+    assert_eq!(cursor.seek(&str_to_key("b")).unwrap().unwrap().1, &0u32.to_be_bytes()[..]);
+    let mut forward = vec![0u32.to_be_bytes().to_vec()];
+    while let Some((key, value)) = cursor.next_dup().unwrap() {
+        assert_eq!(key, &str_to_key("b")[..]);
+        forward.push(value.to_vec());
+    }
+    assert_eq!(forward, values(0..300));
+
+    // На последнем значении курсор остается на месте, и next переходит к следующему ключу
+    assert_eq!(cursor.current().unwrap().unwrap().1, &299u32.to_be_bytes()[..]);
+    assert!(cursor.next_dup().unwrap().is_none());
+    assert_eq!(cursor.next().unwrap().unwrap().0, &str_to_key("c")[..]);
+
+    assert!(cursor.prev_dup().unwrap().is_none());
+    assert_eq!(cursor.prev().unwrap().unwrap().1, &299u32.to_be_bytes()[..]);
+    let mut backward = vec![299u32.to_be_bytes().to_vec()];
+    while let Some((_, value)) = cursor.prev_dup().unwrap() {
+        backward.push(value.to_vec());
+    }
+    backward.reverse();
+    assert_eq!(backward, values(0..300));
+}
+
+// Значения длиннее ключа и пустые, компаратор без укороченных разделителей
+#[test]
+fn long_values_with_comparator() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "reverse");
+    let options = || Options { comparator: Some(Arc::new(Reverse)), prefix_compression: true, ..options() };
+    let db = DB::create(&path, options()).unwrap();
+
+    db.update(|tx| {
+        for k in 0..40 {
+            for v in 0..10u8 {
+                tx.put_dup(str_to_key(&k.to_string()), vec![v; v as usize * 10])?;
+            }
+        }
+        Ok(())
+    }).unwrap();
+    assert_checked(&db);
+
+    let expected: Vec<Vec<u8>> = (0..10u8).map(|v| vec![v; v as usize * 10]).collect();
+    assert_eq!(get_all(&db, "7"), expected);
+    assert_eq!(db.cursor().first().unwrap().unwrap().0, &str_to_key("39")[..]);
+    drop(db);
+
+    compact_with(&path, &options()).unwrap();
+    let db = DB::open_with(&path, options()).unwrap();
+    assert_checked(&db);
+    assert_eq!(get_all(&db, "7"), expected);
+}
+
+#[test]
+fn compact_and_export() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "compact");
+    let db = DB::create(&path, options()).unwrap();
+    fill(&db);
+    drop(db);
+
+    compact_with(&path, &options()).unwrap();
+    let db = DB::open_with(&path, options()).unwrap();
+    assert_checked(&db);
+    assert_eq!(db.meta().flags.get(), META_DUPSORT);
+    assert_eq!(get_all(&db, "c"), values(0..300));
+
+    // Каждое значение - отдельная строка выгрузки, и загружается обратно тоже по одному
+    let mut out = vec![];
+    assert_eq!(export(&db, &mut out, Format::JsonLines, Encoding::Hex, None, None).unwrap(), 900);
+    drop(db);
+
+    let copy = temp_path(&dir, "import");
+    let db = DB::create(&copy, options()).unwrap();
+    assert_eq!(import(&db, &out[..], Format::JsonLines, Encoding::Hex).unwrap(), 900);
+    assert_eq!(get_all(&db, "a"), values(0..300));
+}
+
+// Флаг задается при создании: открыть базу можно только с тем же dupsort
+#[test]
+fn mismatched_dupsort() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "mismatch");
+    drop(DB::create(&path, options()).unwrap());
+
+    let plain = Options { dupsort: false, ..options() };
+    assert_eq!(DB::open_with(&path, plain.clone()).err().unwrap().kind(), ErrorKind::InvalidInput);
+    assert_eq!(BPlusTree::load_with(&path, &options()).err().unwrap().kind(), ErrorKind::InvalidInput);
+    assert_eq!(save_tree(&BPlusTree::new(512), &path, &options()).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    let path = temp_path(&dir, "plain");
+    let db = DB::create(&path, plain).unwrap();
+    db.update(|tx| {
+        assert_eq!(tx.put_dup(str_to_key("a"), vec![1]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(tx.delete_dup(str_to_key("a"), &[1]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        tx.put(str_to_key("a"), vec![1])
+    }).unwrap();
+
+    // Без dupsort у ключа одно значение, next_dup дальше не идет
+    assert_eq!(get_all(&db, "a"), vec![vec![1]]);
+    let mut cursor = db.cursor();
+    cursor.first().unwrap();
+    assert!(cursor.next_dup().unwrap().is_none());
+    drop(db);
+
+    assert_eq!(DB::open_with(&path, options()).err().unwrap().kind(), ErrorKind::InvalidInput);
+}
@@ -0,0 +1,174 @@
+// Выгрузка и загрузка: ключи и значения с разделителями CSV, кавычками, переводами строк и
+// не-UTF-8 байтами переживают круг export -> import в обоих форматах и кодировках, hex и
+// base64 разбираются строго, а диапазон [from, to) отрезает ровно свои ключи.
+use std::io::ErrorKind;
+
+use rust_apps::db::DB;
+use rust_apps::export::{Encoding, export, Format, import};
+use rust_apps::types::{bytes_to_key, Key};
+
+mod common;
+use common::{options, temp_dir, temp_path};
+
+const FORMATS: [Format; 2] = [Format::JsonLines, Format::Csv];
+const ENCODINGS: [Encoding; 2] = [Encoding::Hex, Encoding::Base64];
+
+fn tricky() -> Vec<(Vec<u8>, Vec<u8>)> {
+    vec![
+        (b"a,b".to_vec(), b"1,2,3".to_vec()),
+        (b"\"quoted\"".to_vec(), b"{\"key\":\"x\"}".to_vec()),
+        (b"line\nbreak".to_vec(), b"\r\n".to_vec()),
+        (vec![0xFF, 0xFE, 0x00, 0x80], vec![0xC3, 0x28]),
+        (b"empty".to_vec(), vec![]),
+    ]
+}
+
+fn contents(db: &DB) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut ret = vec![];
+    let mut cursor = db.cursor();
+    let mut item = cursor.first().unwrap();
+    while let Some((key, value)) = item {
+        ret.push((key.to_vec(), value.to_vec()));
+        item = cursor.next().unwrap();
+    }
+    ret
+}
+
+fn dump(db: &DB, format: Format, encoding: Encoding, from: Option<&[u8]>, to: Option<&[u8]>) -> (u64, String) {
+    let mut out = vec![];
+    let count = export(db, &mut out, format, encoding, from, to).unwrap();
+    (count, String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn round_trip() {
+    let dir = temp_dir();
+    let db = DB::create(&temp_path(&dir, "src"), options(512)).unwrap();
+    db.update(|tx| {
+        for (key, value) in tricky() {
+            tx.put(bytes_to_key(&key).unwrap(), value)?;
+        }
+        Ok(())
+    }).unwrap();
+    let expected = contents(&db);
+
+    for &format in FORMATS.iter() {
+        for &encoding in ENCODINGS.iter() {
+            let (count, text) = dump(&db, format, encoding, None, None);
+            assert_eq!(count, tricky().len() as u64);
+            // Ни один байт ключа или значения не попадает в файл как есть
+            let header = if format == Format::Csv { 1 } else { 0 };
+            assert_eq!(text.lines().count(), header + tricky().len(), "{}", text);
+            assert!(!text.contains("quoted") && !text.contains("a,b"), "{}", text);
+
+            let copy = DB::create(&temp_path(&dir, &format!("{:?}-{:?}", format, encoding)), options(512)).unwrap();
+            assert_eq!(import(&copy, text.as_bytes(), format, encoding).unwrap(), count);
+            assert_eq!(contents(&copy), expected, "{:?} {:?}", format, encoding);
+        }
+    }
+}
+
+#[test]
+fn hex() {
+    assert_eq!(Encoding::Hex.encode(&[]), "");
+    assert_eq!(Encoding::Hex.encode(&[0x00, 0xDE, 0xAD, 0x0F]), "00dead0f");
+    assert_eq!(Encoding::Hex.decode("00dead0f").unwrap(), vec![0x00, 0xDE, 0xAD, 0x0F]);
+    assert_eq!(Encoding::Hex.decode("DEAD").unwrap(), vec![0xDE, 0xAD]);
+    assert_eq!(Encoding::Hex.decode("").unwrap(), Vec::<u8>::new());
+
+    for bad in ["abc", "zz", "0x", "é0", " 00", "+1"] {
+        assert_eq!(Encoding::Hex.decode(bad).unwrap_err().kind(), ErrorKind::InvalidData, "{:?}", bad);
+    }
+}
+
+// Векторы из RFC 4648
+#[test]
+fn base64() {
+    let vectors = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="),
+                   ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+    for (plain, encoded) in vectors {
+        assert_eq!(Encoding::Base64.encode(plain.as_bytes()), encoded);
+        assert_eq!(Encoding::Base64.decode(encoded).unwrap(), plain.as_bytes());
+    }
+    assert_eq!(Encoding::Base64.encode(&[0xFB, 0xFF]), "+/8=");
+    assert_eq!(Encoding::Base64.decode("+/8=").unwrap(), vec![0xFB, 0xFF]);
+
+    for bad in ["Zg=", "Zg", "Z===", "Zg=a", "Zm9v!A==", "Zm9vYg==Zm8="] {
+        assert!(Encoding::Base64.decode(bad).is_err(), "{:?}", bad);
+    }
+}
+
+// Файл, написанный руками: заголовок CSV, кавычки, пробелы, другой порядок полей JSON и
+// экранированный слеш. Одна битая строка откатывает весь файл.
+#[test]
+fn import_parsing() {
+    let dir = temp_dir();
+    let db = DB::create(&temp_path(&dir, "import"), options(512)).unwrap();
+    let key = |x: &[u8]| -> Key { bytes_to_key(x).unwrap() };
+
+    let csv = "key,value\n\"01\", \"aa\"\n\n02,bb\n";
+    assert_eq!(import(&db, csv.as_bytes(), Format::Csv, Encoding::Hex).unwrap(), 2);
+    assert_eq!(db.get(key(&[0x01])).unwrap().as_deref(), Some(&[0xAA][..]));
+
+    let json = "{\"value\": \"Yg==\", \"key\": \"Aw==\"}\n{\"key\":\"BA==\",\"value\":\"\\/w==\"}\n";
+    assert_eq!(import(&db, json.as_bytes(), Format::JsonLines, Encoding::Base64).unwrap(), 2);
+    assert_eq!(db.get(key(&[0x03])).unwrap().as_deref(), Some(&b"b"[..]));
+    assert_eq!(db.get(key(&[0x04])).unwrap().as_deref(), Some(&[0xFF][..]));
+
+    let bad = [
+        (Format::Csv, "05,aa\n06,bb,cc\n"),
+        (Format::Csv, "05,aa\n06,zz\n"),
+        (Format::JsonLines, "{\"key\":\"05\",\"value\":\"aa\"}\n{\"key\":\"06\"}\n"),
+        (Format::JsonLines, "{\"key\":\"05\",\"value\":\"aa\"}\n{\"key\":\"06\",\"value\":\"\\n\"}\n"),
+    ];
+    for (format, text) in bad.iter() {
+        let err = import(&db, text.as_bytes(), *format, Encoding::Hex).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", text);
+        assert!(err.to_string().contains("line 2"), "{}", err);
+        assert_eq!(db.get(key(&[0x05])).unwrap(), None, "{}", text);
+    }
+
+    // Ключ длиннее ключа базы
+    let long = format!("{},00\n", "11".repeat(33));
+    assert_eq!(import(&db, long.as_bytes(), Format::Csv, Encoding::Hex).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn range_bounds() {
+    let dir = temp_dir();
+    let db = DB::create(&temp_path(&dir, "range"), options(512)).unwrap();
+    let key = |k: u8| -> Key {
+        let mut key = [0; 32];
+        key[0] = k;
+        key
+    };
+    db.update(|tx| {
+        for k in (10..50).step_by(2) {
+            tx.put(key(k), vec![k])?;
+        }
+        Ok(())
+    }).unwrap();
+
+    let values = |from: Option<&[u8]>, to: Option<&[u8]>| -> Vec<u8> {
+        let (count, text) = dump(&db, Format::Csv, Encoding::Hex, from, to);
+        let ret: Vec<u8> = text.lines().skip(1)
+            .map(|x| Encoding::Hex.decode(x.split(',').nth(1).unwrap()).unwrap()[0])
+            .collect();
+        assert_eq!(ret.len() as u64, count);
+        ret
+    };
+
+    // from включается, to - нет
+    assert_eq!(values(Some(&key(20)), Some(&key(26))), vec![20, 22, 24]);
+    // Границы между ключами и короче ключа
+    assert_eq!(values(Some(&key(19)), Some(&key(25))), vec![20, 22, 24]);
+    assert_eq!(values(Some(&[20]), Some(&[24])), vec![20, 22]);
+    // Открытые границы
+    assert_eq!(values(None, Some(&key(14))), vec![10, 12]);
+    assert_eq!(values(Some(&key(46)), None), vec![46, 48]);
+    assert_eq!(values(None, None).len(), 20);
+    // Пустые диапазоны
+    assert_eq!(values(Some(&key(30)), Some(&key(30))), vec![]);
+    assert_eq!(values(Some(&key(30)), Some(&key(20))), vec![]);
+    assert_eq!(values(Some(&key(50)), None), vec![]);
+}
@@ -0,0 +1,119 @@
+// Заполнение страниц при делении нод: fill_percent и деление по правому краю для
+// возрастающих ключей, и в дереве writer, и в коммитах.
+use std::io::ErrorKind;
+
+use rust_apps::db::{DB, Options};
+use rust_apps::stats::Stats;
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::str_to_key;
+
+mod common;
+use common::{assert_checked, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 1024;
+const COUNT: u32 = 2000;
+
+fn options(fill_percent: f64, right_edge_split: bool) -> Options {
+    Options { fill_percent, right_edge_split, ..common::options(PAGE_SIZE) }
+}
+
+fn key(k: u32) -> [u8; 32] {
+    str_to_key(&k.to_string())
+}
+
+// Средняя заполненность листьев, кроме последнего: он по определению заполнен как попало
+fn leaf_fill(stats: &Stats) -> f64 {
+    let full = stats.leaf.avg_fill() * stats.leaf.count as f64;
+    (full - 1.0).max(0.0) / (stats.leaf.count - 1) as f64
+}
+
+fn tree_stats(keys: &[u32], options: &Options) -> Stats {
+    let mut tree = BPlusTree::with_options(options);
+    for &k in keys.iter() {
+        tree.add(key(k), vec![k as u8; 10]);
+    }
+
+    let dir = temp_dir();
+    let path = temp_path(&dir, &format!("tree-{}-{}-{}", options.fill_percent, options.right_edge_split, keys[0]));
+    save_tree(&tree, &path, options).unwrap();
+    let db = DB::open(&path).unwrap();
+    assert_checked(&db);
+    db.stats().unwrap()
+}
+
+fn commit_stats(keys: &[u32], options: &Options) -> Stats {
+    let dir = temp_dir();
+    let path = temp_path(&dir, &format!("commit-{}-{}-{}", options.fill_percent, options.right_edge_split, keys[0]));
+    let db = DB::create(&path, options.clone()).unwrap();
+
+    // По несколько ключей в транзакции, чтобы деления шли и внутри одного коммита
+    for chunk in keys.chunks(7) {
+        db.update(|tx| {
+            for &k in chunk.iter() {
+                tx.put(key(k), vec![k as u8; 10])?;
+            }
+            Ok(())
+        }).unwrap();
+    }
+
+    assert_checked(&db);
+    for &k in keys.iter() {
+        assert_eq!(db.get(key(k)).unwrap().as_deref(), Some(&[k as u8; 10][..]));
+    }
+    db.stats().unwrap()
+}
+
+#[test]
+fn sequential_keys_fill_pages() {
+    let keys: Vec<u32> = (0..COUNT).collect();
+
+    for stats in [tree_stats(&keys, &options(0.5, true)), commit_stats(&keys, &options(0.5, true))].iter() {
+        assert!(leaf_fill(stats) > 0.9, "{}", stats);
+    }
+
+    // Без деления по правому краю левые страницы так и остаются заполненными на fill_percent
+    for stats in [tree_stats(&keys, &options(0.5, false)), commit_stats(&keys, &options(0.5, false))].iter() {
+        assert!(leaf_fill(stats) < 0.6, "{}", stats);
+    }
+}
+
+#[test]
+fn fill_percent() {
+    let keys: Vec<u32> = (0..COUNT).collect();
+
+    for &fill_percent in [0.3, 0.7, 1.0].iter() {
+        for stats in [tree_stats(&keys, &options(fill_percent, false)), commit_stats(&keys, &options(fill_percent, false))].iter() {
+            assert!((leaf_fill(stats) - fill_percent).abs() < 0.1, "fill {}: {}", fill_percent, stats);
+        }
+    }
+}
+
+// Случайные ключи через правый край почти не проходят: страницы остаются примерно такими же,
+// как без эвристики
+#[test]
+fn random_keys() {
+    let mut keys: Vec<u32> = (0..COUNT).collect();
+    let mut seed = 1u32;
+    for idx in (1..keys.len()).rev() {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        keys.swap(idx, (seed >> 16) as usize % (idx + 1));
+    }
+
+    for &right_edge_split in [true, false].iter() {
+        let options = options(0.5, right_edge_split);
+        for stats in [tree_stats(&keys, &options), commit_stats(&keys, &options)].iter() {
+            assert!(leaf_fill(stats) > 0.5 && leaf_fill(stats) < 0.9, "{}", stats);
+        }
+    }
+}
+
+#[test]
+fn invalid_fill_percent() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "invalid");
+
+    for &fill_percent in [0.0, 0.05, 1.5, f64::NAN].iter() {
+        let err = DB::create(&path, options(fill_percent, true)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,325 @@
+// Побайтовые проверки формата файла. Все числа - little-endian, структуры без паддинга.
+use std::fs;
+use std::mem::size_of;
+
+use rust_apps::codec::{self, CODEC_LZ, CODEC_NONE};
+use rust_apps::db::DB;
+use rust_apps::types::{BranchINodeHeader, freelist_to_page_bytes, from_bytes, LeafInodeHeader, Le16, Le32, Le64, MAGIC, Meta,
+                       META_DUPSORT, PAGE_BRANCH, PAGE_CODEC, PAGE_LEAF, PAGE_META, PAGE_PREFIX, PageHeader, PageRef, str_to_key, to_bytes, VERSION};
+
+mod common;
+use common::{temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 4096;
+
+fn page_header(id: u64, flags: u16, inode_count: u32, page_overflow_count: u32) -> PageHeader {
+    PageHeader {
+        id: Le64::new(id),
+        flags: Le16::new(flags),
+        inode_count: Le32::new(inode_count),
+        page_overflow_count: Le32::new(page_overflow_count),
+    }
+}
+
+fn meta(root_page: u32, page_count: u32) -> Meta {
+    Meta {
+        magic: Le32::new(MAGIC),
+        version: Le32::new(VERSION),
+        page_size: Le32::new(PAGE_SIZE as u32),
+        root_page: Le32::new(root_page),
+        page_count: Le32::new(page_count),
+        freelist_page: Le32::new(0),
+        txid: Le64::new(9),
+        comparator: Le32::new(0),
+        flags: Le32::new(0),
+    }
+}
+
+// Le* не OnDisk сами по себе, поэтому байты берем напрямую
+fn to_bytes_of<T: Copy>(val: T) -> Vec<u8> {
+    let raw = &val as *const T as *const u8;
+    unsafe { std::slice::from_raw_parts(raw, size_of::<T>()).to_vec() }
+}
+
+#[test]
+fn struct_sizes() {
+    assert_eq!(size_of::<PageHeader>(), 18);
+    assert_eq!(size_of::<LeafInodeHeader>(), 16);
+    assert_eq!(size_of::<BranchINodeHeader>(), 12);
+    assert_eq!(size_of::<Meta>(), 40);
+}
+
+#[test]
+fn le_values() {
+    assert_eq!(to_bytes_of(Le16::new(0x0102)), [0x02, 0x01]);
+    assert_eq!(to_bytes_of(Le32::new(0x01020304)), [0x04, 0x03, 0x02, 0x01]);
+    assert_eq!(to_bytes_of(Le64::new(0x0102030405060708)), [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+    assert_eq!(Le32::new(0xDEADBEEF).get(), 0xDEADBEEF);
+}
+
+#[test]
+fn page_header_layout() {
+    let page = page_header(0x0102030405060708, PAGE_BRANCH, 0x0A0B0C0D, 0x11121314);
+
+    assert_eq!(to_bytes(&page), &[
+        0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, // id
+        0x02, 0x00, // flags
+        0x0D, 0x0C, 0x0B, 0x0A, // inode_count
+        0x14, 0x13, 0x12, 0x11, // page_overflow_count
+    ][..]);
+}
+
+#[test]
+fn leaf_inode_header_layout() {
+    let header = LeafInodeHeader {
+        pos: Le32::new(0x100),
+        ksize: Le32::new(32),
+        vsize: Le32::new(0x10203),
+        page_id: Le32::new(7),
+    };
+
+    assert_eq!(to_bytes(&header), &[
+        0x00, 0x01, 0x00, 0x00, // pos
+        0x20, 0x00, 0x00, 0x00, // ksize
+        0x03, 0x02, 0x01, 0x00, // vsize
+        0x07, 0x00, 0x00, 0x00, // page_id
+    ][..]);
+}
+
+#[test]
+fn branch_inode_header_layout() {
+    let header = BranchINodeHeader {
+        pos: Le32::new(0x24),
+        ksize: Le32::new(32),
+        page_id: Le32::new(0x01020304),
+    };
+
+    assert_eq!(to_bytes(&header), &[
+        0x24, 0x00, 0x00, 0x00, // pos
+        0x20, 0x00, 0x00, 0x00, // ksize
+        0x04, 0x03, 0x02, 0x01, // page_id
+    ][..]);
+}
+
+#[test]
+fn meta_page_layout() {
+    let bytes = Meta { comparator: Le32::new(0x0A0B0C0D), flags: Le32::new(META_DUPSORT), ..meta(3, 5) }.to_page_bytes();
+
+    assert_eq!(bytes, vec![
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // id = слот txid % 2
+        0x04, 0x00, // flags = PAGE_META
+        0x00, 0x00, 0x00, 0x00, // inode_count
+        0x00, 0x00, 0x00, 0x00, // page_overflow_count
+        0xEE, 0xB9, 0x9A, 0x9B, // magic
+        0x0B, 0x00, 0x00, 0x00, // version
+        0x00, 0x10, 0x00, 0x00, // page_size
+        0x03, 0x00, 0x00, 0x00, // root_page
+        0x05, 0x00, 0x00, 0x00, // page_count
+        0x00, 0x00, 0x00, 0x00, // freelist_page
+        0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // txid
+        0x0D, 0x0C, 0x0B, 0x0A, // comparator
+        0x01, 0x00, 0x00, 0x00, // flags = META_DUPSORT
+        0x42, 0xEB, 0xB2, 0xDC, // checksum = FNV-1a от Meta
+    ]);
+    assert_eq!(PAGE_META, 0x04);
+    assert_eq!(VERSION, 11);
+}
+
+#[test]
+fn freelist_page_layout() {
+    let bytes = freelist_to_page_bytes(7, &[2, 0x01020304], 512, 0);
+
+    assert_eq!(bytes.len(), 512);
+    assert_eq!(&bytes[..26], &[
+        0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // id
+        0x10, 0x00, // flags = PAGE_FREELIST
+        0x02, 0x00, 0x00, 0x00, // inode_count = количество свободных страниц
+        0x00, 0x00, 0x00, 0x00, // page_overflow_count
+        0x02, 0x00, 0x00, 0x00, // free page
+        0x04, 0x03, 0x02, 0x01, // free page
+    ][..]);
+    assert_eq!(PageRef::new(&bytes).unwrap().freelist().unwrap(), vec![2, 0x01020304]);
+
+    // Список, не влезающий в страницу, уходит в overflow-страницы
+    let ids: Vec<u64> = (0..200).collect();
+    let bytes = freelist_to_page_bytes(7, &ids, 512, 0);
+    let page = PageRef::new(&bytes).unwrap();
+    assert_eq!((bytes.len(), page.page_overflow_count.get()), (1024, 1));
+    assert_eq!(page.freelist().unwrap(), ids);
+}
+
+// Лист с общим префиксом: ключи "a" и "b" отличаются только последним байтом
+#[test]
+fn prefix_page_layout() {
+    let mut buf = to_bytes(&page_header(1, PAGE_LEAF | PAGE_PREFIX, 2, 0)).to_vec();
+    buf.extend_from_slice(&[0x1F, 0x00, 0x00, 0x00]); // длина префикса
+    buf.extend_from_slice(&[0; 31]); // префикс
+    buf.extend_from_slice(to_bytes(&LeafInodeHeader { pos: Le32::new(32), ksize: Le32::new(1), vsize: Le32::new(5), page_id: Le32::new(1) }));
+    buf.extend_from_slice(to_bytes(&LeafInodeHeader { pos: Le32::new(22), ksize: Le32::new(1), vsize: Le32::new(6), page_id: Le32::new(1) }));
+    buf.extend_from_slice(b"afirstbsecond");
+
+    let page = PageRef::new(&buf).unwrap();
+    assert_eq!(PAGE_PREFIX, 0x20);
+    assert_eq!((page.is_leaf(), page.has_prefix(), page.type_name()), (true, true, "leaf"));
+    assert_eq!(page.prefix().unwrap(), &[0; 31][..]);
+
+    let inodes = page.leaf_inodes().unwrap();
+    assert_eq!((&inodes[0].key[..], &inodes[0].value[..]), (&str_to_key("a")[..], &b"first"[..]));
+    assert_eq!((&inodes[1].key[..], &inodes[1].value[..]), (&str_to_key("b")[..], &b"second"[..]));
+
+    // Префикс длиннее страницы
+    buf[18] = 0xFF;
+    let page = PageRef::new(&buf).unwrap();
+    assert!(page.prefix().is_err());
+    assert!(page.leaf_inode(0).is_err());
+}
+
+// Лист со сжатыми значениями: байт кодека между ключом и значением, vsize его не включает
+#[test]
+fn codec_page_layout() {
+    let mut buf = to_bytes(&page_header(1, PAGE_LEAF | PAGE_CODEC, 2, 0)).to_vec();
+    buf.extend_from_slice(to_bytes(&LeafInodeHeader { pos: Le32::new(32), ksize: Le32::new(1), vsize: Le32::new(5), page_id: Le32::new(1) }));
+    buf.extend_from_slice(to_bytes(&LeafInodeHeader { pos: Le32::new(23), ksize: Le32::new(1), vsize: Le32::new(11), page_id: Le32::new(1) }));
+    buf.extend_from_slice(b"a\x00first");
+    buf.extend_from_slice(b"b\x01");
+    buf.extend_from_slice(&[
+        0x06, 0x00, 0x00, 0x00, // длина несжатого значения
+        0x05, // 6 литералов
+    ]);
+    buf.extend_from_slice(b"second");
+
+    let page = PageRef::new(&buf).unwrap();
+    assert_eq!((PAGE_CODEC, CODEC_NONE, CODEC_LZ), (0x40, 0, 1));
+    assert!(page.has_codec());
+
+    let inodes = page.leaf_inodes().unwrap();
+    assert_eq!((&inodes[0].key[..], inodes[0].codec, &inodes[0].value[..]), (&b"a"[..], CODEC_NONE, &b"first"[..]));
+    assert_eq!((&inodes[1].key[..], inodes[1].codec), (&b"b"[..], CODEC_LZ));
+    assert_eq!(codec::decode(None, inodes[1].codec, &inodes[1].value[..]).unwrap(), &b"second"[..]);
+
+    // Без флага тот же байт считается началом значения
+    buf[8] = PAGE_LEAF as u8;
+    let inode = PageRef::new(&buf).unwrap().leaf_inode(0).unwrap();
+    assert_eq!((inode.codec, &inode.value[..]), (CODEC_NONE, &b"\x00firs"[..]));
+}
+
+#[test]
+fn decode_unaligned() {
+    let page = page_header(42, PAGE_LEAF, 3, 1);
+    let header = LeafInodeHeader {
+        pos: Le32::new(48),
+        ksize: Le32::new(32),
+        vsize: Le32::new(5),
+        page_id: Le32::new(42),
+    };
+
+    // Смещение на 1 байт: структуры должны читаться с любого адреса
+    let mut buf = vec![0xFF];
+    buf.extend_from_slice(to_bytes(&page));
+    buf.extend_from_slice(to_bytes(&header));
+
+    let decoded: PageHeader = from_bytes(&buf[1..]);
+    assert_eq!((decoded.id.get(), decoded.flags.get(), decoded.inode_count.get(), decoded.page_overflow_count.get()),
+               (42, PAGE_LEAF, 3, 1));
+
+    let decoded: LeafInodeHeader = from_bytes(&buf[1 + size_of::<PageHeader>()..]);
+    assert_eq!((decoded.pos.get(), decoded.ksize.get(), decoded.vsize.get(), decoded.page_id.get()), (48, 32, 5, 42));
+}
+
+#[test]
+#[should_panic]
+fn decode_short_buffer() {
+    let _: Meta = from_bytes(&[0; 23]);
+}
+
+// Файл, собранный руками байт за байтом, должен читаться через DB
+#[test]
+fn read_hand_built_file() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "hand-built");
+    let mut file = vec![0u8; 3 * PAGE_SIZE];
+
+    // Meta с txid 9 лежит в слоте 1, слот 0 пустой: базе хватает одного целого слота
+    let meta_page = meta(2, 3).to_page_bytes();
+    file[PAGE_SIZE..PAGE_SIZE + meta_page.len()].copy_from_slice(&meta_page);
+
+    let kvs = [("a", "first"), ("b", "second")];
+    let leaf = &mut file[2 * PAGE_SIZE..];
+    leaf[..18].copy_from_slice(&[
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // id = 2
+        0x01, 0x00, // flags = PAGE_LEAF
+        0x02, 0x00, 0x00, 0x00, // inode_count = 2
+        0x00, 0x00, 0x00, 0x00, // page_overflow_count = 0
+    ]);
+
+    let mut kvoffset = 18 + kvs.len() * 16;
+    for (idx, (k, v)) in kvs.iter().enumerate() {
+        let offset = 18 + idx * 16;
+        let pos = (kvoffset - offset) as u32;
+        leaf[offset..offset + 4].copy_from_slice(&pos.to_le_bytes());
+        leaf[offset + 4..offset + 8].copy_from_slice(&32u32.to_le_bytes());
+        leaf[offset + 8..offset + 12].copy_from_slice(&(v.len() as u32).to_le_bytes());
+        leaf[offset + 12..offset + 16].copy_from_slice(&2u32.to_le_bytes());
+
+        leaf[kvoffset..kvoffset + 32].copy_from_slice(&str_to_key(k));
+        leaf[kvoffset + 32..kvoffset + 32 + v.len()].copy_from_slice(v.as_bytes());
+        kvoffset += 32 + v.len();
+    }
+
+    fs::write(&path, &file).unwrap();
+
+    let db = DB::open(&path).unwrap();
+    assert_eq!(db.get(str_to_key("a")).unwrap().as_deref(), Some(&b"first"[..]));
+    assert_eq!(db.get(str_to_key("b")).unwrap().as_deref(), Some(&b"second"[..]));
+    assert_eq!(db.get(str_to_key("c")).unwrap().as_deref(), None);
+}
+
+// Лист с одним inode: key из 32 байт и value из 5 байт сразу за заголовками
+fn leaf_page(pos: u32, inode_count: u32) -> Vec<u8> {
+    let mut buf = to_bytes(&page_header(1, PAGE_LEAF, inode_count, 0)).to_vec();
+    buf.extend_from_slice(to_bytes(&LeafInodeHeader {
+        pos: Le32::new(pos),
+        ksize: Le32::new(32),
+        vsize: Le32::new(5),
+        page_id: Le32::new(1),
+    }));
+    buf.extend_from_slice(&str_to_key("k"));
+    buf.extend_from_slice(b"value");
+    buf
+}
+
+#[test]
+fn page_ref_reads_inodes() {
+    let buf = leaf_page(16, 1);
+    let page = PageRef::new(&buf).unwrap();
+
+    assert_eq!((page.id(), page.count(), page.is_leaf()), (1, 1, true));
+    let inode = page.leaf_inode(0).unwrap();
+    assert_eq!(inode.key, &str_to_key("k")[..]);
+    assert_eq!(&inode.value[..], b"value");
+}
+
+#[test]
+fn page_ref_rejects_out_of_bounds() {
+    assert!(PageRef::new(&[0; 17]).is_err());
+
+    // pos указывает за конец страницы
+    let buf = leaf_page(17, 1);
+    assert!(PageRef::new(&buf).unwrap().leaf_inode(0).is_err());
+
+    let buf = leaf_page(u32::MAX, 1);
+    assert!(PageRef::new(&buf).unwrap().leaf_inode(0).is_err());
+
+    // inode_count больше, чем заголовков помещается в странице
+    let buf = leaf_page(16, 1000);
+    let page = PageRef::new(&buf).unwrap();
+    assert!(page.leaf_inode(0).is_ok());
+    assert!(page.leaf_inode(999).is_err());
+    assert!(page.leaf_inodes().is_err());
+    assert!(page.leaf_inode(1000).is_err());
+
+    // Не тот тип страницы
+    assert!(page.branch_inode(0).is_err());
+    assert!(page.meta().is_ok());
+    assert!(PageRef::new(&buf[..30]).unwrap().meta().is_err());
+}
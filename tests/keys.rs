@@ -0,0 +1,184 @@
+// Кодирование ключей с сохранением порядка: байты закодированных значений должны
+// сравниваться так же, как сами значения, и декодироваться обратно, в том числе из ключа
+// базы, дополненного нулями.
+use std::io::ErrorKind;
+
+use proptest::prelude::*;
+
+use rust_apps::db::DB;
+use rust_apps::keys::{decode, encode, from_key, KeyPart, to_key};
+
+mod common;
+use common::{options, temp_dir, temp_path};
+
+fn assert_order<T: KeyPart + PartialOrd + std::fmt::Debug>(a: &T, b: &T) -> Result<(), TestCaseError> {
+    let (ea, eb) = (encode(a), encode(b));
+    prop_assert_eq!(a.partial_cmp(b), Some(ea.cmp(&eb)), "{:?} {:?}", a, b);
+
+    // Ключи базы, дополненные нулями, сравниваются так же
+    if let (Ok(ka), Ok(kb)) = (to_key(a), to_key(b)) {
+        prop_assert_eq!(ka.cmp(&kb), ea.cmp(&eb));
+    }
+    Ok(())
+}
+
+#[test]
+fn integers() {
+    assert_eq!(encode(&1u64), [0, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(encode(&-1i64), [0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(encode(&0i64), [0x80, 0, 0, 0, 0, 0, 0, 0]);
+
+    let values = [i64::MIN, -1000, -1, 0, 1, 255, 256, i64::MAX];
+    for pair in values.windows(2) {
+        assert!(encode(&pair[0]) < encode(&pair[1]), "{:?}", pair);
+    }
+    for &x in values.iter() {
+        assert_eq!(decode::<i64>(&encode(&x)).unwrap(), x);
+    }
+}
+
+#[test]
+fn floats() {
+    let values = [f64::NEG_INFINITY, -1e300, -1.5, -f64::MIN_POSITIVE, -0.0, 0.0, f64::MIN_POSITIVE, 1.0, 1.5, 1e300, f64::INFINITY];
+    for pair in values.windows(2) {
+        assert!(encode(&pair[0]) < encode(&pair[1]), "{:?}", pair);
+    }
+    for &x in values.iter() {
+        assert_eq!(decode::<f64>(&encode(&x)).unwrap().to_bits(), x.to_bits());
+    }
+    assert!(decode::<f64>(&encode(&f64::NAN)).unwrap().is_nan());
+}
+
+#[test]
+fn strings() {
+    assert_eq!(encode(&"ab".to_string()), b"ab\x00\x00");
+    assert_eq!(encode(&b"a\x00b".to_vec()), b"a\x00\xFFb\x00\x00");
+
+    let values = ["", "\u{0}", "a", "a\u{0}", "a\u{0}\u{0}", "a\u{1}", "aa", "b"];
+    for pair in values.windows(2) {
+        assert!(encode(&pair[0].to_string()) < encode(&pair[1].to_string()), "{:?}", pair);
+    }
+    for &x in values.iter() {
+        assert_eq!(decode::<String>(&encode(&x.to_string())).unwrap(), x);
+    }
+}
+
+#[test]
+fn tuples() {
+    let key = (7u64, -5i64, "x".to_string());
+    assert_eq!(from_key::<(u64, i64, String)>(&to_key(&key).unwrap()).unwrap(), key);
+
+    // Первая часть кортежа - префикс кодировки всего ключа
+    assert!(encode(&key).starts_with(&encode(&7u64)));
+    assert!(encode(&(1u64, i64::MAX)) < encode(&(2u64, i64::MIN)));
+    assert!(encode(&("a".to_string(), 9u64)) < encode(&("ab".to_string(), 0u64)));
+}
+
+#[test]
+fn invalid_keys() {
+    assert_eq!(decode::<u64>(&[1, 2, 3]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<u64>(&[0; 9]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<Vec<u8>>(b"ab").unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<Vec<u8>>(b"a\x00\x01\x00\x00").unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<String>(b"\xFF\x00\x00").unwrap_err().kind(), ErrorKind::InvalidData);
+
+    // Ключ не влезает в Key, после значения в ключе только нули
+    assert_eq!(to_key(&"x".repeat(40)).unwrap_err().kind(), ErrorKind::InvalidInput);
+    let mut key = to_key(&5u64).unwrap();
+    key[20] = 1;
+    assert_eq!(from_key::<u64>(&key).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+// Выборка диапазона по составному ключу (tenant_id, timestamp) через курсор
+#[test]
+fn range_scan() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "range");
+    let db = DB::create(&path, options(512)).unwrap();
+
+    // Порядок вставки перемешан, метки времени и отрицательные, и положительные
+    let timestamps: Vec<i64> = (0..200).map(|x| (x * 7919 % 200) - 100).collect();
+    db.update(|tx| {
+        for tenant in [3u64, 1, 256, 2].iter() {
+            for &ts in timestamps.iter() {
+                tx.put(to_key(&(*tenant, ts))?, ts.to_le_bytes().to_vec())?;
+            }
+        }
+        Ok(())
+    }).unwrap();
+
+    // Все записи арендатора 2 с меткой от -10: seek на начало, дальше пока не кончится префикс
+    let prefix = encode(&2u64);
+    let mut cursor = db.cursor();
+    let mut item = cursor.seek(&to_key(&(2u64, -10i64)).unwrap()).unwrap();
+    let mut found = vec![];
+    while let Some((key, _)) = item {
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        found.push(from_key::<(u64, i64)>(&key).unwrap().1);
+        item = cursor.next().unwrap();
+    }
+    assert_eq!(found, (-10..100).collect::<Vec<i64>>());
+
+    // И все ключи базы по порядку
+    let mut all = vec![];
+    let mut item = cursor.first().unwrap();
+    while let Some((key, _)) = item {
+        all.push(from_key::<(u64, i64)>(&key).unwrap());
+        item = cursor.next().unwrap();
+    }
+    let mut expected = all.clone();
+    expected.sort();
+    assert_eq!(all.len(), 800);
+    assert_eq!(all, expected);
+}
+
+proptest! {
+    #[test]
+    fn u64_order(a in any::<u64>(), b in any::<u64>()) {
+        assert_order(&a, &b)?;
+        prop_assert_eq!(from_key::<u64>(&to_key(&a).unwrap()).unwrap(), a);
+    }
+
+    #[test]
+    fn i64_order(a in any::<i64>(), b in any::<i64>()) {
+        assert_order(&a, &b)?;
+        prop_assert_eq!(from_key::<i64>(&to_key(&a).unwrap()).unwrap(), a);
+    }
+
+    #[test]
+    fn f64_order(a in any::<f64>(), b in any::<f64>()) {
+        // -0.0 и 0.0 равны как числа, но кодируются по-разному
+        if a.partial_cmp(&b).is_some() && !(a == 0.0 && b == 0.0) {
+            assert_order(&a, &b)?;
+        }
+        prop_assert_eq!(decode::<f64>(&encode(&a)).unwrap().to_bits(), a.to_bits());
+    }
+
+    #[test]
+    fn bytes_order(a in prop::collection::vec(prop::sample::select(vec![0u8, 1, 0xFF]), 0..10),
+                   b in prop::collection::vec(prop::sample::select(vec![0u8, 1, 0xFF]), 0..10)) {
+        assert_order(&a, &b)?;
+        prop_assert_eq!(decode::<Vec<u8>>(&encode(&a)).unwrap(), a);
+    }
+
+    #[test]
+    fn string_order(a in ".{0,8}", b in ".{0,8}") {
+        assert_order(&a, &b)?;
+        prop_assert_eq!(decode::<String>(&encode(&a)).unwrap(), a);
+    }
+
+    #[test]
+    fn tuple_order(a in (any::<u64>(), any::<i64>()), b in (any::<u64>(), any::<i64>())) {
+        assert_order(&a, &b)?;
+        prop_assert_eq!(from_key::<(u64, i64)>(&to_key(&a).unwrap()).unwrap(), a);
+    }
+
+    #[test]
+    fn mixed_tuple_order(a in ("[ab\u{0}]{0,3}", prop::collection::vec(0u8..3, 0..3), any::<i64>()),
+                         b in ("[ab\u{0}]{0,3}", prop::collection::vec(0u8..3, 0..3), any::<i64>())) {
+        assert_order(&a, &b)?;
+        prop_assert_eq!(decode::<(String, Vec<u8>, i64)>(&encode(&a)).unwrap(), a);
+    }
+}
@@ -0,0 +1,140 @@
+// Options: размер страницы новой базы, только чтение, создание файла, контрольная сумма
+// meta и блокировка файла между открытиями.
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::os::unix::fs::FileExt;
+use std::time::Duration;
+
+use rust_apps::db::{DB, Options};
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::str_to_key;
+
+mod common;
+use common::{temp_dir, temp_path};
+
+fn options() -> Options {
+    common::options(Options::default().page_size)
+}
+
+fn write_tree(path: &str, options: &Options) {
+    let mut tree = BPlusTree::new(options.page_size);
+    for k in 0..100 {
+        tree.add(str_to_key(&k.to_string()), format!("value{}", k).into_bytes());
+    }
+    save_tree(&tree, path, options).unwrap();
+}
+
+#[test]
+fn page_size_from_options() {
+    for &page_size in [512, 1024, 1 << 16].iter() {
+        let dir = temp_dir();
+        let path = temp_path(&dir, &format!("page-size-{}", page_size));
+        write_tree(&path, &Options { page_size, ..options() });
+
+        // Размер страницы существующей базы берется из meta, а не из options
+        let db = DB::open(&path).unwrap();
+        assert_eq!(db.page_size(), page_size);
+        assert_eq!(db.meta().page_size.get() as usize, page_size);
+        assert_eq!(fs::metadata(&path).unwrap().len(), db.meta().page_count.get() as u64 * page_size as u64);
+        assert_eq!(db.get(str_to_key("42")).unwrap().as_deref(), Some(&b"value42"[..]));
+    }
+}
+
+#[test]
+fn invalid_page_size() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "invalid-page-size");
+
+    for &page_size in [0, 256, 1000, 1 << 17].iter() {
+        let err = save_tree(&BPlusTree::new(4096), &path, &Options { page_size, ..options() }).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn initial_size() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "initial-size");
+    write_tree(&path, &Options { page_size: 4096, initial_size: 1 << 20, ..options() });
+
+    assert_eq!(fs::metadata(&path).unwrap().len(), 1 << 20);
+    assert_eq!(DB::open(&path).unwrap().get(str_to_key("7")).unwrap().as_deref(), Some(&b"value7"[..]));
+}
+
+#[test]
+fn read_only() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "read-only");
+    write_tree(&path, &options());
+
+    let db = DB::open_with(&path, Options { read_only: true, ..options() }).unwrap();
+    assert_eq!(db.get(str_to_key("1")).unwrap().as_deref(), Some(&b"value1"[..]));
+
+    let err = db.update(|tx| tx.put(str_to_key("1"), b"changed".to_vec())).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert_eq!(db.get(str_to_key("1")).unwrap().as_deref(), Some(&b"value1"[..]));
+}
+
+#[test]
+fn create_if_missing() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "create-if-missing");
+
+    assert_eq!(DB::open(&path).err().unwrap().kind(), ErrorKind::NotFound);
+
+    let db = DB::open_with(&path, Options { create_if_missing: true, page_size: 1024, ..options() }).unwrap();
+    assert_eq!(db.page_size(), 1024);
+    assert_eq!(db.get(str_to_key("1")).unwrap().as_deref(), None);
+    drop(db);
+
+    // Существующий файл не пересоздается
+    write_tree(&path, &options());
+    let db = DB::open_with(&path, Options { create_if_missing: true, ..options() }).unwrap();
+    assert_eq!(db.get(str_to_key("1")).unwrap().as_deref(), Some(&b"value1"[..]));
+}
+
+#[test]
+fn meta_checksum() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "checksum");
+    write_tree(&path, &options());
+
+    // Портим root_page в обоих слотах: meta остается правдоподобной, но сумма уже не
+    // сходится, и откатиться не на что (откат на целый слот - в tests/create.rs)
+    let page_size = options().page_size as u64;
+    let f = OpenOptions::new().write(true).open(&path).unwrap();
+    for slot in 0..2 {
+        f.write_at(&1u32.to_le_bytes(), slot * page_size + 18 + 12).unwrap();
+    }
+    drop(f);
+
+    let err = DB::open(&path).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("checksum"), "{}", err);
+
+    assert!(DB::open_with(&path, Options { verify_checksums: false, ..options() }).is_ok());
+}
+
+#[test]
+fn lock_timeout() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "lock");
+    write_tree(&path, &options());
+    let timeout = Options { lock_timeout: Some(Duration::from_millis(100)), ..options() };
+    let read_only = Options { read_only: true, ..timeout.clone() };
+
+    // Блокировка flock привязана к открытому файлу, поэтому второе открытие в том же
+    // процессе ведет себя как другой процесс
+    let writer = DB::open_with(&path, timeout.clone()).unwrap();
+    assert_eq!(DB::open_with(&path, timeout.clone()).err().unwrap().kind(), ErrorKind::WouldBlock);
+    assert_eq!(DB::open_with(&path, read_only.clone()).err().unwrap().kind(), ErrorKind::WouldBlock);
+    drop(writer);
+
+    let reader1 = DB::open_with(&path, read_only.clone()).unwrap();
+    let reader2 = DB::open_with(&path, read_only).unwrap();
+    assert_eq!(DB::open_with(&path, timeout.clone()).err().unwrap().kind(), ErrorKind::WouldBlock);
+    drop(reader1);
+    drop(reader2);
+
+    assert!(DB::open_with(&path, timeout).is_ok());
+}
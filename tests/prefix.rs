@@ -0,0 +1,216 @@
+// Сжатие префиксов: страницы с PAGE_PREFIX пишут и save_tree, и коммиты, и compact, а
+// get, курсоры, check и запись поверх читают их так же, как обычные.
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+
+use rust_apps::compact::compact;
+use rust_apps::db::{DB, Options};
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::{bytes_to_key, Key, PageId, str_to_key};
+
+mod common;
+use common::{assert_checked, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 1024;
+const COUNT: u32 = 1000;
+
+fn options(prefix_compression: bool) -> Options {
+    Options { prefix_compression, ..common::options(PAGE_SIZE) }
+}
+
+fn value(k: u32) -> Vec<u8> {
+    format!("value{}", k).into_bytes()
+}
+
+// Все ключи читаются и через get, и курсором, в том числе с seek посередине
+fn assert_contents(db: &DB, count: u32) {
+    for k in 0..count {
+        assert_eq!(db.get(str_to_key(&k.to_string())).unwrap().as_deref(), Some(value(k).as_slice()), "key {}", k);
+    }
+
+    let mut expected: Vec<String> = (0..count).map(|k| k.to_string()).collect();
+    expected.sort_by_key(|k| str_to_key(k));
+
+    let mut cursor = db.cursor();
+    let mut item = cursor.first().unwrap();
+    let mut read = vec![];
+    while let Some((k, _)) = item {
+        read.push(k.into_owned());
+        item = cursor.next().unwrap();
+    }
+    assert_eq!(read, expected.iter().map(|k| str_to_key(k).to_vec()).collect::<Vec<_>>());
+
+    let middle = &expected[expected.len() / 2];
+    assert_eq!(cursor.seek(&str_to_key(middle)).unwrap().unwrap().0, &str_to_key(middle)[..]);
+}
+
+// Страницы дерева со сжатием и без
+fn count_pages(db: &DB, page_id: PageId, counts: &mut (usize, usize)) {
+    let page = db.page(page_id).unwrap();
+    if page.has_prefix() {
+        counts.0 += 1;
+    } else {
+        counts.1 += 1;
+    }
+
+    if page.is_branch() {
+        for inode in page.branch_inodes().unwrap() {
+            count_pages(db, inode.page_id, counts);
+        }
+    }
+}
+
+fn prefixed_pages(db: &DB) -> (usize, usize) {
+    let mut counts = (0, 0);
+    count_pages(db, db.meta().root_page.get() as PageId, &mut counts);
+    counts
+}
+
+fn save(path: &str, options: &Options) {
+    let mut tree = BPlusTree::with_options(options);
+    for k in 0..COUNT {
+        tree.add(str_to_key(&k.to_string()), value(k));
+    }
+    save_tree(&tree, path, options).unwrap();
+}
+
+#[test]
+fn save_tree_compresses_pages() {
+    let dir = temp_dir();
+    let plain_path = temp_path(&dir, "tree-plain");
+    save(&plain_path, &options(false));
+    let plain = DB::open(&plain_path).unwrap();
+    assert_eq!(prefixed_pages(&plain).0, 0);
+
+    let path = temp_path(&dir, "tree");
+    save(&path, &options(true));
+    let db = DB::open(&path).unwrap();
+    assert_eq!(prefixed_pages(&db).1, 0);
+    assert_checked(&db);
+    assert_contents(&db, COUNT);
+
+    // Ключи в 32 байта почти целиком из нулей: листьев должно стать заметно меньше
+    let (plain_stats, stats) = (plain.stats().unwrap(), db.stats().unwrap());
+    assert!(stats.leaf.count * 3 < plain_stats.leaf.count * 2, "{} vs {}", stats, plain_stats);
+    // Размеры ключей в статистике - полные, как до сжатия
+    assert_eq!(stats.key_sizes.buckets, plain_stats.key_sizes.buckets);
+
+    // Сжатое дерево читается обратно в память
+    drop(db);
+    drop(plain);
+    assert_eq!(BPlusTree::load(&path).unwrap().len(), COUNT as usize);
+}
+
+#[test]
+fn commits_compress_pages() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "commit");
+    let db = DB::create(&path, options(true)).unwrap();
+
+    for chunk in (0..COUNT).collect::<Vec<_>>().chunks(10) {
+        db.update(|tx| {
+            for &k in chunk.iter() {
+                tx.put(str_to_key(&k.to_string()), value(k))?;
+            }
+            Ok(())
+        }).unwrap();
+    }
+
+    assert_eq!(prefixed_pages(&db).1, 0);
+    assert_checked(&db);
+    assert_contents(&db, COUNT);
+}
+
+// Сжатие можно включать и выключать на существующей базе: страницы разных форматов
+// живут в одном дереве
+#[test]
+fn mixed_pages() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "mixed");
+    save(&path, &options(false));
+
+    let db = DB::open_with(&path, options(true)).unwrap();
+    db.update(|tx| tx.put(str_to_key("0"), value(0))).unwrap();
+    let (prefixed, plain) = prefixed_pages(&db);
+    assert!(prefixed > 0 && plain > 0, "{} {}", prefixed, plain);
+    assert_checked(&db);
+    assert_contents(&db, COUNT);
+    drop(db);
+
+    // Переписанная без сжатия страница снова становится обычной
+    let db = DB::open_with(&path, options(false)).unwrap();
+    db.update(|tx| tx.put(str_to_key("0"), value(0))).unwrap();
+    assert_eq!(prefixed_pages(&db).0, 0);
+    assert_contents(&db, COUNT);
+}
+
+#[test]
+fn compact_keeps_compression() {
+    for &prefix_compression in [true, false].iter() {
+        let dir = temp_dir();
+        let path = temp_path(&dir, &format!("compact-{}", prefix_compression));
+        save(&path, &options(prefix_compression));
+        compact(&path).unwrap();
+
+        let db = DB::open(&path).unwrap();
+        let (prefixed, plain) = prefixed_pages(&db);
+        assert_eq!(prefixed > 0, prefix_compression);
+        assert_eq!(plain > 0, !prefix_compression);
+        assert_checked(&db);
+        assert_contents(&db, COUNT);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    // Ключи разной длины с разными общими префиксами, удаления и значения больше страницы:
+    // и writer, и коммиты дают то же, что BTreeMap
+    #[test]
+    fn matches_btreemap(ops in prop::collection::vec((prop::collection::vec(0..4u8, 1..32), prop::option::weighted(0.8, prop::collection::vec(any::<u8>(), 0..1500))), 0..300)) {
+        let mut tree = BPlusTree::with_options(&options(true));
+        let mut model = BTreeMap::<Key, Vec<u8>>::new();
+        for (k, v) in ops.iter() {
+            let k = bytes_to_key(k).unwrap();
+            match v {
+                Some(v) => {
+                    tree.add(k, v.clone());
+                    model.insert(k, v.clone());
+                }
+                None => {
+                    prop_assert_eq!(tree.remove(k), model.remove(&k));
+                }
+            }
+        }
+
+        let dir = temp_dir();
+        let path = temp_path(&dir, "proptest");
+        save_tree(&tree, &path, &options(true)).unwrap();
+        let db = DB::open_with(&path, options(true)).unwrap();
+        assert_checked(&db);
+
+        // Поверх файла writer - те же ключи коммитами (удаленные возвращаются)
+        db.update(|tx| {
+            for (k, _) in ops.iter() {
+                let k = bytes_to_key(k).unwrap();
+                tx.put(k, model.get(&k).cloned().unwrap_or_default())?;
+            }
+            Ok(())
+        }).unwrap();
+        for (k, _) in ops.iter() {
+            model.entry(bytes_to_key(k).unwrap()).or_default();
+        }
+        assert_checked(&db);
+
+        let mut cursor = db.cursor();
+        let mut item = cursor.first().unwrap();
+        let mut read = vec![];
+        while let Some((k, v)) = item {
+            read.push((k.into_owned(), v.to_vec()));
+            item = cursor.next().unwrap();
+        }
+        let expected: Vec<_> = model.iter().map(|(k, v)| (k.to_vec(), v.clone())).collect();
+        prop_assert_eq!(read, expected);
+    }
+}
@@ -0,0 +1,113 @@
+// Укороченные разделители в ветках: writer, коммиты и compact пишут в ветку не первый ключ
+// потомка целиком, а самый короткий ключ, который отделяет его от предыдущего.
+use rust_apps::compact::compact;
+use rust_apps::db::{DB, Options};
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::{Key, PageId, shortest_separator};
+
+mod common;
+use common::{assert_checked, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 512;
+const COUNT: u32 = 2000;
+
+fn options() -> Options {
+    common::options(PAGE_SIZE)
+}
+
+// Длинные ключи, которые различаются в первых байтах: разделителю хватает 1-4 байт
+fn key(k: u32) -> Key {
+    let mut key = [0xAB; 32];
+    key[..4].copy_from_slice(&k.to_be_bytes());
+    key
+}
+
+fn assert_readable(db: &DB) {
+    assert_checked(db);
+
+    for k in 0..COUNT {
+        assert_eq!(db.get(key(k)).unwrap().as_deref(), Some(&k.to_le_bytes()[..]), "key {}", k);
+        assert_eq!(db.cursor().seek(&key(k)).unwrap().unwrap().0, &key(k)[..]);
+    }
+}
+
+// Длины разделителей во всех ветках, кроме первого в каждой (он - первый ключ целиком)
+fn separator_lens(db: &DB, page_id: PageId, lens: &mut Vec<usize>) {
+    let page = db.page(page_id).unwrap();
+    if page.is_leaf() {
+        return;
+    }
+
+    for (idx, inode) in page.branch_inodes().unwrap().iter().enumerate() {
+        if idx > 0 {
+            lens.push(inode.key.len());
+        }
+        separator_lens(db, inode.page_id, lens);
+    }
+}
+
+fn assert_truncated(db: &DB) {
+    let mut lens = vec![];
+    separator_lens(db, db.meta().root_page.get() as PageId, &mut lens);
+    assert!(!lens.is_empty());
+    assert!(lens.iter().all(|&x| x <= 4), "{:?}", lens);
+}
+
+#[test]
+fn shortest() {
+    assert_eq!(shortest_separator(b"abc", b"abd"), b"abd");
+    assert_eq!(shortest_separator(b"abc", b"b"), b"b");
+    assert_eq!(shortest_separator(b"abc", b"bcd"), b"b");
+    assert_eq!(shortest_separator(b"ab", b"abcd"), b"abc");
+    assert_eq!(shortest_separator(b"", b"a"), b"a");
+}
+
+#[test]
+fn writer_truncates_separators() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "writer");
+    let mut tree = BPlusTree::with_options(&options());
+    for k in 0..COUNT {
+        tree.add(key(k), k.to_le_bytes().to_vec());
+    }
+    save_tree(&tree, &path, &options()).unwrap();
+
+    let db = DB::open(&path).unwrap();
+    assert_truncated(&db);
+    assert_readable(&db);
+    drop(db);
+
+    // В памяти разделители снова полные ключи, при записи укорачиваются заново
+    let mut tree = BPlusTree::load(&path).unwrap();
+    assert_eq!(tree.len(), COUNT as usize);
+    tree.add(key(COUNT / 2), (COUNT / 2).to_le_bytes().to_vec());
+    save_tree(&tree, &path, &options()).unwrap();
+    assert_readable(&DB::open(&path).unwrap());
+}
+
+#[test]
+fn commits_truncate_separators() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "commit");
+    let db = DB::create(&path, options()).unwrap();
+
+    // В разном порядке, чтобы ключи попадали и в самый левый лист, и в середину
+    let keys: Vec<u32> = (0..COUNT).step_by(2).chain((1..COUNT).step_by(2).rev()).collect();
+    for chunk in keys.chunks(10) {
+        db.update(|tx| {
+            for &k in chunk.iter() {
+                tx.put(key(k), k.to_le_bytes().to_vec())?;
+            }
+            Ok(())
+        }).unwrap();
+    }
+
+    assert_truncated(&db);
+    assert_readable(&db);
+    drop(db);
+
+    compact(&path).unwrap();
+    let db = DB::open(&path).unwrap();
+    assert_truncated(&db);
+    assert_readable(&db);
+}
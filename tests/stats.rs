@@ -0,0 +1,109 @@
+// Статистика страниц и обходы всего дерева (stats, compact, BPlusTree::load): цифры по
+// известному дереву и остановка с ошибкой на цикле или общем поддереве вместо зависания.
+use std::fs;
+use std::io::ErrorKind;
+use std::mem::size_of;
+
+use rust_apps::compact::{compact, compact_to};
+use rust_apps::db::DB;
+use rust_apps::stats::Histogram;
+use rust_apps::tree::BPlusTree;
+use rust_apps::types::{BranchINodeHeader, PageHeader, str_to_key};
+
+mod common;
+use common::{options, temp_dir, temp_path};
+
+const PAGE_SIZE: usize = 512;
+const COUNT: u32 = 300;
+
+fn create(path: &str) -> DB {
+    DB::create(path, options(PAGE_SIZE)).unwrap()
+}
+
+// Коммит кладет все в один лист, а компактизация раскладывает его по дереву
+fn write_tree(path: &str) {
+    let db = create(path);
+    db.update(|tx| {
+        for k in 0..COUNT {
+            tx.put(str_to_key(&format!("{:04}", k)), vec![k as u8; 8])?;
+        }
+        // Значение больше страницы уходит в overflow
+        tx.put(str_to_key("big"), vec![1; 2000])?;
+        Ok(())
+    }).unwrap();
+    drop(db);
+
+    compact(path).unwrap();
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+#[test]
+fn tree_stats() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "stats");
+    write_tree(&path);
+    let db = DB::open(&path).unwrap();
+    let stats = db.stats().unwrap();
+
+    assert!(stats.depth >= 2, "{}", stats);
+    assert_eq!(stats.meta.count, 2);
+    assert_eq!(stats.inline_count, COUNT as u64);
+    assert_eq!(stats.overflow_count, 1);
+    assert_eq!(stats.overflow_page_count, 4);
+
+    // Все ключи по 32 байта, значения по 8 и одно большое
+    let bucket = |size: usize| (usize::BITS - size.leading_zeros()) as usize;
+    assert_eq!(stats.key_sizes.buckets.iter().sum::<u64>(), COUNT as u64 + 1);
+    assert_eq!(stats.key_sizes.buckets[bucket(32)], COUNT as u64 + 1);
+    assert_eq!(stats.value_sizes.buckets[bucket(8)], COUNT as u64);
+    assert_eq!(stats.value_sizes.buckets[bucket(2000)], 1);
+    assert_eq!(Histogram::bounds(bucket(2000)), (1024, 2048));
+
+    // Каждая страница файла чем-то занята или свободна
+    let pages = stats.meta.count + stats.leaf.count + stats.branch.count + stats.overflow_page_count + stats.free_page_count;
+    assert_eq!(pages * PAGE_SIZE as u64, fs::metadata(&path).unwrap().len());
+    assert!(stats.leaf.avg_fill() > 0.5 && stats.leaf.avg_fill() <= 1.0, "{}", stats);
+}
+
+// Портит вторую ссылку корневой ветки так, чтобы она вела на страницу target
+fn redirect(path: &str, target: impl FnOnce(u32, u32) -> u32) {
+    let root = DB::open(path).unwrap().meta().root_page.get() as usize;
+    let mut data = fs::read(path).unwrap();
+    let child_offset = |idx: usize| root * PAGE_SIZE + size_of::<PageHeader>() + idx * size_of::<BranchINodeHeader>() + 2 * size_of::<u32>();
+
+    let target = target(root as u32, read_u32(&data, child_offset(0)));
+    data[child_offset(1)..child_offset(1) + 4].copy_from_slice(&target.to_le_bytes());
+    fs::write(path, data).unwrap();
+}
+
+fn assert_walks_fail(path: &str) {
+    let db = DB::open(path).unwrap();
+    assert_eq!(db.stats().unwrap_err().kind(), ErrorKind::InvalidData);
+    drop(db);
+
+    let dst = format!("{}.copy", path);
+    assert_eq!(compact_to(path, &dst).unwrap_err().kind(), ErrorKind::InvalidData);
+
+    assert_eq!(BPlusTree::load(path).err().unwrap().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn cycle() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "cycle");
+    write_tree(&path);
+    redirect(&path, |root, _| root);
+    assert_walks_fail(&path);
+}
+
+#[test]
+fn shared_subtree() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "shared");
+    write_tree(&path);
+    redirect(&path, |_, first| first);
+    assert_walks_fail(&path);
+}
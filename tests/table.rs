@@ -0,0 +1,254 @@
+// Типизированные таблицы: кодирование значений Encode/Decode, свои структуры через
+// table_value!/table_key!, get/put/delete и выборки диапазонов, удаление ключей из дерева,
+// отказ создать таблицу в dupsort-базе.
+use std::io::ErrorKind;
+
+use proptest::prelude::*;
+use tempfile::TempDir;
+
+use rust_apps::compact::compact;
+use rust_apps::db::{DB, Options, Tx};
+use rust_apps::table::{decode, encode, Table};
+use rust_apps::types::str_to_key;
+use rust_apps::{table_key, table_value};
+
+mod common;
+use common::{assert_checked, options, temp_dir, temp_path};
+
+// Каталог возвращается вместе с базой: пока он жив, файл не удаляется
+fn create(name: &str) -> (TempDir, String, DB) {
+    let dir = temp_dir();
+    let path = temp_path(&dir, name);
+    let db = DB::create(&path, options(512)).unwrap();
+    (dir, path, db)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct User {
+    name: String,
+    age: u32,
+    email: Option<String>,
+    tags: Vec<String>,
+}
+
+table_value!(User { name, age, email, tags });
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+struct EventKey {
+    tenant: u64,
+    ts: i64,
+}
+
+table_key!(EventKey { tenant, ts });
+
+fn user(id: u64) -> User {
+    User {
+        name: format!("user-{}", id),
+        age: 20 + (id % 50) as u32,
+        email: if id.is_multiple_of(3) { None } else { Some(format!("user{}@example.com", id)) },
+        tags: (0..id % 4).map(|x| format!("tag{}", x)).collect(),
+    }
+}
+
+#[test]
+fn values() {
+    assert_eq!(encode(&0x0102u16), [0x02, 0x01]);
+    assert_eq!(encode("ab"), [2, 0, 0, 0, b'a', b'b']);
+    assert_eq!(encode(&(true, Some(7u8), None::<u8>)), [1, 1, 7, 0]);
+
+    let value = (-5i64, 1.5f64, "x".to_string(), vec![1u16, 2, 3]);
+    assert_eq!(decode::<(i64, f64, String, Vec<u16>)>(&encode(&value)).unwrap(), value);
+    assert_eq!(decode::<User>(&encode(&user(5))).unwrap(), user(5));
+}
+
+#[test]
+fn invalid_values() {
+    assert_eq!(decode::<u32>(&[1, 2]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<u32>(&[1, 2, 3, 4, 5]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<bool>(&[2]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<String>(&[5, 0, 0, 0, b'a']).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<String>(&[1, 0, 0, 0, 0xFF]).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(decode::<Vec<u64>>(&[0xFF, 0xFF, 0xFF, 0xFF]).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn get_put_delete() {
+    let (_dir, _, db) = create("crud");
+    let users: Table<u64, User> = Table::new(&db).unwrap();
+
+    db.update(|tx| {
+        for id in 0..300 {
+            users.put(tx, &id, &user(id))?;
+        }
+        Ok(())
+    }).unwrap();
+
+    db.view(|tx| {
+        assert_eq!(users.get(tx, &42)?, Some(user(42)));
+        assert_eq!(users.get(tx, &1000)?, None);
+        Ok(())
+    }).unwrap();
+
+    db.update(|tx| {
+        assert!(users.delete(tx, &42)?);
+        assert!(!users.delete(tx, &1000)?);
+        users.put(tx, &7, &User { age: 99, ..user(7) })
+    }).unwrap();
+
+    db.view(|tx| {
+        assert_eq!(users.get(tx, &42)?, None);
+        assert_eq!(users.get(tx, &7)?.unwrap().age, 99);
+        assert_eq!(users.iter(tx)?.count(), 299);
+        Ok(())
+    }).unwrap();
+    assert_checked(&db);
+
+    // Значение, записанное не таблицей, - ошибка декодирования, а не паника
+    db.update(|tx| tx.put(rust_apps::keys::to_key(&5u64)?, vec![1, 2, 3])).unwrap();
+    db.view(|tx| {
+        assert_eq!(users.get(tx, &5).unwrap_err().kind(), ErrorKind::InvalidData);
+        Ok(())
+    }).unwrap();
+}
+
+// Чтения внутри update видят put и delete той же транзакции, а откат их выбрасывает
+#[test]
+fn reads_in_transaction() {
+    let (_dir, _, db) = create("tx");
+    let users: Table<u64, User> = Table::new(&db).unwrap();
+
+    db.update(|tx| {
+        for id in 0..300 {
+            users.put(tx, &id, &user(id))?;
+        }
+        // Ключи еще не закоммичены и не поделены по листьям
+        assert_eq!(users.get(tx, &42)?, Some(user(42)));
+        assert_eq!(users.iter(tx)?.count(), 300);
+        Ok(())
+    }).unwrap();
+
+    db.update(|tx| {
+        assert!(users.delete(tx, &42)?);
+        users.put(tx, &7, &User { age: 99, ..user(7) })?;
+        users.put(tx, &1000, &user(1000))?;
+
+        assert_eq!(users.get(tx, &42)?, None);
+        assert_eq!(users.get(tx, &7)?.unwrap().age, 99);
+        assert_eq!(users.get(tx, &1000)?, Some(user(1000)));
+        let ids: Vec<u64> = users.range(tx, 40..45)?.map(|x| x.unwrap().0).collect();
+        assert_eq!(ids, vec![40, 41, 43, 44]);
+        let ids: Vec<u64> = users.range(tx, 299..)?.map(|x| x.unwrap().0).collect();
+        assert_eq!(ids, vec![299, 1000]);
+        assert_eq!(users.iter(tx)?.count(), 300);
+
+        // Снимок базы не изменился
+        assert!(db.get(rust_apps::keys::to_key(&42u64)?)?.is_some());
+        Err(std::io::Error::other("rollback"))
+    }).unwrap_err();
+
+    db.view(|tx| {
+        assert_eq!(users.get(tx, &42)?, Some(user(42)));
+        assert_eq!(users.get(tx, &1000)?, None);
+        assert_eq!(users.iter(tx)?.count(), 300);
+        Ok(())
+    }).unwrap();
+}
+
+// У ключа таблицы одно значение, а в dupsort-базе put добавлял бы еще одно
+#[test]
+fn dupsort_database() {
+    let dir = temp_dir();
+    let db = DB::create(&temp_path(&dir, "dupsort"), Options { dupsort: true, ..options(512) }).unwrap();
+    let err = Table::<u64, User>::new(&db).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn ranges() {
+    let (_dir, _, db) = create("range");
+    let events: Table<EventKey, (String, f64)> = Table::new(&db).unwrap();
+
+    db.update(|tx| {
+        for tenant in [2u64, 1, 3].iter() {
+            for ts in (-50..50).rev() {
+                events.put(tx, &EventKey { tenant: *tenant, ts }, &(format!("{}:{}", tenant, ts), ts as f64 / 2.0))?;
+            }
+        }
+        Ok(())
+    }).unwrap();
+
+    let tx = Tx::new(&db);
+    let all: Vec<EventKey> = events.iter(&tx).unwrap().map(|x| x.unwrap().0).collect();
+    assert_eq!(all.len(), 300);
+    assert!(all.windows(2).all(|x| x[0] < x[1]));
+
+    let key = |tenant, ts| EventKey { tenant, ts };
+    let ts = |range: Vec<(EventKey, (String, f64))>| range.iter().map(|x| x.0.ts).collect::<Vec<_>>();
+
+    let range: Vec<_> = events.range(&tx, key(2, -3)..key(2, 3)).unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(ts(range.clone()), (-3..3).collect::<Vec<_>>());
+    assert_eq!(range[0].1, ("2:-3".to_string(), -1.5));
+
+    let range: Vec<_> = events.range(&tx, key(2, 47)..=key(3, -49)).unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(ts(range), vec![47, 48, 49, -50, -49]);
+
+    let range: Vec<_> = events.range(&tx, key(3, 47)..).unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(ts(range), vec![47, 48, 49]);
+
+    let range: Vec<_> = events.range(&tx, ..key(1, -48)).unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(ts(range), vec![-50, -49]);
+
+    let range: Vec<_> = events.range(&tx, (std::ops::Bound::Excluded(key(1, 0)), std::ops::Bound::Included(key(1, 2))))
+        .unwrap().map(|x| x.unwrap()).collect();
+    assert_eq!(ts(range), vec![1, 2]);
+
+    assert_eq!(events.range(&tx, key(9, 0)..).unwrap().count(), 0);
+}
+
+// Удаление всех ключей: опустевшие листья и ветки уходят из дерева, база остается целой
+#[test]
+fn delete_everything() {
+    let (_dir, path, db) = create("delete");
+    db.update(|tx| {
+        for k in 0..2000 {
+            tx.put(str_to_key(&k.to_string()), vec![1; 20])?;
+        }
+        Ok(())
+    }).unwrap();
+
+    for chunk in (0..2000).collect::<Vec<_>>().chunks(150) {
+        db.update(|tx| {
+            for k in chunk.iter() {
+                assert!(tx.delete(str_to_key(&k.to_string()))?);
+            }
+            Ok(())
+        }).unwrap();
+        assert_checked(&db);
+        assert_eq!(db.get(str_to_key(&chunk[0].to_string())).unwrap(), None);
+    }
+
+    assert!(db.cursor().first().unwrap().is_none());
+    assert!(db.page(db.meta().root_page.get() as u64).unwrap().is_leaf());
+
+    db.update(|tx| tx.put(str_to_key("1"), b"again".to_vec())).unwrap();
+    assert_eq!(db.get(str_to_key("1")).unwrap().as_deref(), Some(&b"again"[..]));
+    drop(db);
+
+    compact(&path).unwrap();
+    let db = DB::open(&path).unwrap();
+    assert_checked(&db);
+    assert_eq!(db.get(str_to_key("1")).unwrap().as_deref(), Some(&b"again"[..]));
+}
+
+proptest! {
+    #[test]
+    fn value_round_trip(value in (any::<u64>(), any::<i32>(), ".{0,10}", prop::collection::vec(any::<Option<bool>>(), 0..5))) {
+        prop_assert_eq!(decode::<(u64, i32, String, Vec<Option<bool>>)>(&encode(&value)).unwrap(), value);
+    }
+
+    // Любые байты дают либо значение, либо ошибку
+    #[test]
+    fn arbitrary_input(data in prop::collection::vec(any::<u8>(), 0..100)) {
+        let _ = decode::<User>(&data);
+    }
+}
@@ -0,0 +1,178 @@
+// Модельные тесты BPlusTree: случайные последовательности add/remove/get применяются к
+// дереву и к BTreeMap, результаты должны совпадать. Затем дерево пишется save_tree и
+// читается обратно через DB или BPlusTree::load.
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+
+use rust_apps::db::DB;
+use rust_apps::tree::{BPlusTree, save_tree};
+use rust_apps::types::{Key, str_to_key};
+
+mod common;
+use common::{assert_checked, options, temp_dir, temp_path};
+
+// Маленькие страницы дают глубокие деревья и много делений даже на сотне ключей
+const PAGE_SIZES: [usize; 3] = [512, 1024, 4096];
+
+#[derive(Debug, Clone)]
+enum Op {
+    Add(u16, Vec<u8>),
+    Remove(u16),
+    Get(u16),
+}
+
+// Маленькое пространство ключей, чтобы были и перезаписи, и удаления существующих
+fn key(n: u16) -> Key {
+    str_to_key(&n.to_string())
+}
+
+fn value() -> impl Strategy<Value=Vec<u8>> {
+    prop_oneof![
+        9 => prop::collection::vec(any::<u8>(), 0..64),
+        // Значения больше страницы: ноды уходят в overflow-страницы
+        1 => prop::collection::vec(any::<u8>(), 4000..10000),
+    ]
+}
+
+fn op() -> impl Strategy<Value=Op> {
+    prop_oneof![
+        5 => (0..300u16, value()).prop_map(|(k, v)| Op::Add(k, v)),
+        2 => (0..300u16).prop_map(Op::Remove),
+        2 => (0..300u16).prop_map(Op::Get),
+    ]
+}
+
+fn apply(tree: &mut BPlusTree, model: &mut BTreeMap<Key, Vec<u8>>, ops: &[Op]) {
+    for op in ops {
+        match op {
+            Op::Add(k, v) => {
+                tree.add(key(*k), v.clone());
+                model.insert(key(*k), v.clone());
+            }
+            Op::Remove(k) => assert_eq!(tree.remove(key(*k)), model.remove(&key(*k)), "remove {}", k),
+            Op::Get(k) => assert_eq!(tree.get(key(*k)), model.get(&key(*k)), "get {}", k),
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn matches_btreemap(ops in prop::collection::vec(op(), 0..500)) {
+        for &page_size in PAGE_SIZES.iter() {
+            let mut tree = BPlusTree::new(page_size);
+            let mut model = BTreeMap::new();
+            apply(&mut tree, &mut model, &ops);
+
+            prop_assert_eq!(tree.len(), model.len());
+            prop_assert!(tree.iter().eq(model.iter()), "page size {}", page_size);
+            for k in 0..300 {
+                prop_assert_eq!(tree.get(key(k)), model.get(&key(k)));
+            }
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn save_and_read_back(ops in prop::collection::vec(op(), 0..300)) {
+        for &page_size in PAGE_SIZES.iter() {
+            let mut tree = BPlusTree::new(page_size);
+            let mut model = BTreeMap::new();
+            apply(&mut tree, &mut model, &ops);
+
+            let dir = temp_dir();
+            let path = temp_path(&dir, "save");
+            save_tree(&tree, &path, &options(page_size)).unwrap();
+
+            let db = DB::open(&path).unwrap();
+            assert_checked(&db);
+
+            for k in 0..300 {
+                prop_assert_eq!(db.get(key(k)).unwrap().map(|x| x.to_vec()), model.get(&key(k)).cloned(), "page size {}, key {}", page_size, k);
+            }
+
+            let mut cursor = db.cursor();
+            let mut item = cursor.first().unwrap();
+            let mut read = vec![];
+            while let Some((k, v)) = item {
+                read.push((k.to_vec(), v.to_vec()));
+                item = cursor.next().unwrap();
+            }
+            let expected: Vec<_> = model.iter().map(|(k, v)| (k.to_vec(), v.clone())).collect();
+            prop_assert_eq!(read, expected);
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    // save_tree -> load -> еще операции -> save_tree: дерево, прочитанное из файла, ведет
+    // себя так же, как построенное в памяти
+    #[test]
+    fn load_and_edit(ops in prop::collection::vec(op(), 0..200), more in prop::collection::vec(op(), 0..200)) {
+        for &page_size in PAGE_SIZES.iter() {
+            let mut tree = BPlusTree::new(page_size);
+            let mut model = BTreeMap::new();
+            apply(&mut tree, &mut model, &ops);
+
+            let dir = temp_dir();
+            let path = temp_path(&dir, "load");
+            save_tree(&tree, &path, &options(page_size)).unwrap();
+
+            let mut tree = BPlusTree::load(&path).unwrap();
+            prop_assert!(tree.iter().eq(model.iter()), "page size {}", page_size);
+
+            apply(&mut tree, &mut model, &more);
+            prop_assert!(tree.iter().eq(model.iter()), "page size {}", page_size);
+
+            save_tree(&tree, &path, &options(page_size)).unwrap();
+            let db = DB::open(&path).unwrap();
+            assert_checked(&db);
+            for k in 0..300 {
+                prop_assert_eq!(db.get(key(k)).unwrap().map(|x| x.to_vec()), model.get(&key(k)).cloned());
+            }
+        }
+    }
+}
+
+// Файл, записанный не writer, а коммитами: дерево строит Tx, читает BPlusTree::load
+#[test]
+fn load_committed() {
+    let dir = temp_dir();
+    let path = temp_path(&dir, "committed");
+    let db = DB::create(&path, options(1024)).unwrap();
+    for k in (0..300).rev() {
+        db.update(|tx| tx.put(key(k), vec![k as u8; k as usize])).unwrap();
+    }
+    drop(db);
+
+    let tree = BPlusTree::load(&path).unwrap();
+    assert_eq!(tree.len(), 300);
+    for k in 0..300 {
+        assert_eq!(tree.get(key(k)), Some(&vec![k as u8; k as usize]));
+    }
+}
+
+#[test]
+fn remove_everything() {
+    for &page_size in PAGE_SIZES.iter() {
+        let mut tree = BPlusTree::new(page_size);
+        for k in 0..200 {
+            tree.add(key(k), vec![k as u8]);
+        }
+
+        // Удаляем вперемешку, чтобы опустошались и крайние, и средние листья
+        for k in (0..200).step_by(2).chain((1..200).step_by(2).rev()) {
+            assert_eq!(tree.remove(key(k)), Some(vec![k as u8]));
+            assert_eq!(tree.remove(key(k)), None);
+        }
+
+        assert!(tree.is_empty());
+        tree.add(key(1), vec![1]);
+        assert_eq!(tree.get(key(1)), Some(&vec![1]));
+    }
+}